@@ -0,0 +1,65 @@
+//! Passes of what the camera sees first, besides the light, which compositors use to separate
+//! and relight a render
+
+use rayon::iter::ParallelIterator;
+use renderer_types::{
+    image::{Exr, ExrCompression, ExrPrecision},
+    prelude::*,
+};
+
+use crate::{
+    camera::Camera,
+    object::{Hit, Object},
+    scene::Scene,
+};
+
+/// What the ray through the center of each pixel hits first
+pub struct Aovs {
+    pub albedo: Buffer<Colorf32>,
+    /// Shading normals in world space, zero where nothing was hit
+    pub normal: Buffer<Vec3f>,
+    /// Distance from the camera, infinite where nothing was hit
+    pub depth: Buffer<f32>,
+    /// Index into [`Scene::objects`] plus one, zero where nothing was hit
+    pub object_id: Buffer<u32>,
+}
+
+impl Aovs {
+    pub fn render(scene: &Scene, camera: &Camera, width: usize, height: usize) -> Self {
+        let mut hits: Buffer<Option<(usize, Hit, f32)>> = Buffer::new(width, height, None);
+        hits.par_iter_pos_mut().for_each(|(x, y, pixel)| {
+            let ray = camera.ray(x as f32 + 0.5, y as f32 + 0.5, width, height);
+            *pixel = scene
+                .objects
+                .iter()
+                .enumerate()
+                .flat_map(|(i, object)| Some((i, object.hit(&ray, 0.0..)?)))
+                .min_by(|(_, a), (_, b)| a.t.total_cmp(&b.t))
+                .map(|(i, hit)| (i, hit, hit.t * ray.direction().len()));
+        });
+        Self {
+            albedo: hits.map(|hit| match hit {
+                Some((_, hit, _)) => scene.materials[hit.material].albedo(hit.uv),
+                None => Color::black(),
+            }),
+            normal: hits.map(|hit| match hit {
+                Some((_, hit, _)) => scene.materials[hit.material].shading_hit(hit).normal,
+                None => Vec3f::default(),
+            }),
+            depth: hits.map(|hit| hit.map_or(f32::INFINITY, |(_, _, depth)| depth)),
+            object_id: hits.map(|hit| hit.map_or(0, |(i, _, _)| i as u32 + 1)),
+        }
+    }
+    /// A multi-layer EXR with `beauty` as the main image, the passes as the `albedo`, `normal`,
+    /// `depth` and `objectId` layers
+    pub fn to_exr(&self, beauty: &Buffer<Colorf32>) -> Exr {
+        let Vec2 { x, y } = beauty.dimensions();
+        Exr::new(x, y)
+            .with_compression(ExrCompression::Zip)
+            .with_color("", beauty, ExrPrecision::Half)
+            .with_color("albedo", &self.albedo, ExrPrecision::Half)
+            .with_vector("normal", &self.normal, ExrPrecision::Half)
+            .with_channel("depth.Z", &self.depth, ExrPrecision::Float)
+            .with_ids("objectId.id", &self.object_id)
+    }
+}
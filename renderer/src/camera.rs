@@ -0,0 +1,69 @@
+use renderer_types::prelude::*;
+
+/// Pinhole camera looking towards +y, with +z pointing up
+#[derive(Debug, Clone, Copy)]
+pub struct Camera {
+    pub origin: Vec3f,
+    pub focal_len: f32,
+    /// Height of the image plane at `focal_len` away from the origin
+    pub viewport_height: f32,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            origin: vec3f(0, 0, 0),
+            focal_len: 1.0,
+            viewport_height: 0.5,
+        }
+    }
+}
+
+impl Camera {
+    /// Returns the ray through the continuous pixel position (`x`, `y`) of a `width`x`height`
+    /// image, with (0, 0) being the top left corner
+    pub fn ray(&self, x: f32, y: f32, width: usize, height: usize) -> Ray3f {
+        let viewport = self.viewport(width, height);
+        let delta = viewport.x / width as f32;
+        let corner = vec3f(-viewport.x / 2.0, self.focal_len, viewport.y / 2.0) + self.origin;
+        let target = corner + vec3f(delta * x, 0, -delta * y);
+        self.origin.to(target)
+    }
+    /// Where `point` shows up on a `width`x`height` image, in the pixel coordinates
+    /// [`Camera::ray`] takes, or `None` if it's out of view
+    pub fn raster(&self, point: Vec3f, width: usize, height: usize) -> Option<Vec2f> {
+        let offset = point - self.origin;
+        if offset.y <= 0.0 {
+            return None;
+        }
+        let viewport = self.viewport(width, height);
+        let delta = viewport.x / width as f32;
+        let on_plane = offset * (self.focal_len / offset.y);
+        let x = (on_plane.x + viewport.x / 2.0) / delta;
+        let y = (viewport.y / 2.0 - on_plane.z) / delta;
+        ((0.0..width as f32).contains(&x) && (0.0..height as f32).contains(&y)).then(|| vec2f(x, y))
+    }
+    /// Probability density of [`Camera::ray`] picking `direction` with respect to solid angle,
+    /// when the pixel position is uniformly distributed over the image
+    pub fn pdf_direction(&self, direction: Vec3f, width: usize, height: usize) -> f32 {
+        if self
+            .raster(self.origin + direction, width, height)
+            .is_none()
+        {
+            return 0.0;
+        }
+        let cos = direction.unit().y;
+        let viewport = self.viewport(width, height);
+        // Area of the image plane, scaled to be one unit in front of the camera
+        let area = viewport.x * viewport.y / (self.focal_len * self.focal_len);
+        1.0 / (area * cos * cos * cos)
+    }
+    /// Importance emitted along `direction`, normalized so it integrates to one over the image
+    pub fn importance(&self, direction: Vec3f, width: usize, height: usize) -> f32 {
+        self.pdf_direction(direction, width, height) / direction.unit().y
+    }
+    fn viewport(&self, width: usize, height: usize) -> Vec2f {
+        let aspect = width as f32 / height as f32;
+        vec2f(aspect * self.viewport_height, self.viewport_height)
+    }
+}
@@ -0,0 +1,112 @@
+use renderer_types::prelude::*;
+
+/// Piecewise-constant 1D distribution over [0, 1), sampled by inverting its CDF
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Builds a distribution proportional to `func`.
+    ///
+    /// Falls back to a uniform distribution when `func` integrates to zero
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty(), "Cannot build a distribution over 0 bins");
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        let mut acc = 0.0;
+        for f in &func {
+            acc += f.abs() / n;
+            cdf.push(acc);
+        }
+        let integral = acc;
+        if integral > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= integral);
+        } else {
+            cdf.iter_mut()
+                .enumerate()
+                .for_each(|(i, c)| *c = i as f32 / n);
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+    fn len(&self) -> usize {
+        self.func.len()
+    }
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+    /// Returns the sampled value in [0, 1), its pdf and the index of the bin it fell in
+    pub fn sample_continuous(&self, u: f32) -> (f32, f32, usize) {
+        let offset = self
+            .cdf
+            .partition_point(|&c| c <= u)
+            .saturating_sub(1)
+            .min(self.len() - 1);
+        let (lo, hi) = (self.cdf[offset], self.cdf[offset + 1]);
+        let mut du = u - lo;
+        if hi > lo {
+            du /= hi - lo;
+        }
+        let x = ((offset as f32 + du) / self.len() as f32).min(1.0 - f32::EPSILON);
+        (x, self.pdf_at(offset), offset)
+    }
+    /// Density at `x` in [0, 1)
+    pub fn pdf(&self, x: f32) -> f32 {
+        let offset = ((x * self.len() as f32) as usize).min(self.len() - 1);
+        self.pdf_at(offset)
+    }
+    fn pdf_at(&self, offset: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[offset].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Piecewise-constant 2D distribution over [0, 1)^2, stored as a marginal distribution over rows
+/// and a conditional distribution within every row
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Builds a distribution from a row-major `width`x`height` grid of weights
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        assert_eq!(
+            func.len(),
+            width * height,
+            "Expected {width}x{height} weights, got {}",
+            func.len()
+        );
+        let conditional: Vec<_> = func
+            .chunks_exact(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+    /// Returns the sampled point and its pdf with respect to area in [0, 1)^2
+    pub fn sample_continuous(&self, u: Vec2f) -> (Vec2f, f32) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.y);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u.x);
+        (vec2f(x, y), pdf_x * pdf_y)
+    }
+    pub fn pdf(&self, p: Vec2f) -> f32 {
+        let height = self.conditional.len();
+        let row = ((p.y * height as f32) as usize).min(height - 1);
+        self.marginal.pdf(p.y) * self.conditional[row].pdf(p.x)
+    }
+}
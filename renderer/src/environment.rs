@@ -0,0 +1,172 @@
+use std::f32::consts::{PI, TAU};
+
+use renderer_types::{
+    prelude::*,
+    sampling::{sample_uniform_sphere, uniform_sphere_pdf},
+};
+
+use crate::{distribution::Distribution2D, lerp, sky::PhysicalSky};
+
+/// Radiance arriving from infinitely far away, seen by every ray that escapes the scene
+#[derive(Debug, Clone)]
+pub enum Environment {
+    /// Equirectangular (latitude-longitude) HDR image
+    Map(EnvironmentMap),
+    /// Analytic daylight sky, including the sun disk
+    Sky(PhysicalSky),
+}
+
+/// A direction towards the environment chosen by [`Environment::sample`]
+#[derive(Debug, Clone, Copy)]
+pub struct EnvSample {
+    /// Unit direction pointing away from the scene
+    pub direction: Vec3f,
+    pub radiance: Colorf32,
+    /// Probability density with respect to solid angle
+    pub pdf: f32,
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: Vec3f) -> Colorf32 {
+        match self {
+            Self::Map(map) => map.radiance(direction),
+            Self::Sky(sky) => sky.radiance(direction),
+        }
+    }
+    /// Radiance without the parts of the environment that are also in the scene's lights, so
+    /// they aren't counted twice when the lights are sampled directly
+    pub fn radiance_without_sun(&self, direction: Vec3f) -> Colorf32 {
+        match self {
+            Self::Map(map) => map.radiance(direction),
+            Self::Sky(sky) => sky.sky_radiance(direction),
+        }
+    }
+    /// Chooses a direction proportionally to the incoming radiance, `u` is a pair of uniform
+    /// samples in [0, 1).
+    ///
+    /// The radiance of the sample doesn't include the sun, see
+    /// [`Environment::radiance_without_sun`]
+    pub fn sample(&self, u: Vec2f) -> EnvSample {
+        match self {
+            Self::Sky(_) => {
+                let direction = sample_uniform_sphere(u);
+                EnvSample {
+                    direction,
+                    radiance: self.radiance_without_sun(direction),
+                    pdf: uniform_sphere_pdf(),
+                }
+            }
+            Self::Map(map) => map.sample(u),
+        }
+    }
+    /// Density [`Environment::sample`] would return for `direction`
+    pub fn pdf(&self, direction: Vec3f) -> f32 {
+        match self {
+            Self::Sky(_) => uniform_sphere_pdf(),
+            Self::Map(map) => map.pdf(direction),
+        }
+    }
+}
+
+/// Equirectangular environment image, with +z pointing up.
+///
+/// The u axis wraps around the horizon and the v axis goes from the zenith (v = 0) to the nadir
+/// (v = 1)
+#[derive(Debug, Clone)]
+pub struct EnvironmentMap {
+    image: Buffer<Colorf32>,
+    /// Rotation around the z axis, in radians
+    rotation: f32,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Creates an environment map from an image of linear radiance
+    pub fn new(image: Buffer<Colorf32>) -> Self {
+        let (width, height) = (image.width(), image.height());
+        assert!(width > 0 && height > 0, "Environment map must not be empty");
+        // Rows near the poles cover a smaller solid angle, weigh them accordingly
+        let weights: Vec<f32> = image
+            .inner_buf()
+            .chunks_exact(width)
+            .enumerate()
+            .flat_map(|(y, row)| {
+                let sin_theta = (PI * (y as f32 + 0.5) / height as f32).sin();
+                row.iter().map(move |p| p.luminance().max(0.0) * sin_theta)
+            })
+            .collect();
+        Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            image,
+            rotation: 0.0,
+        }
+    }
+    pub fn with_rotation(mut self, radians: f32) -> Self {
+        self.rotation = radians;
+        self
+    }
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+    pub fn set_rotation(&mut self, radians: f32) {
+        self.rotation = radians;
+    }
+    pub fn dimensions(&self) -> Vec2<usize> {
+        self.image.dimensions()
+    }
+    fn direction_to_uv(&self, direction: Vec3f) -> Vec2f {
+        let d = direction.unit();
+        let phi = d.y.atan2(d.x) - self.rotation;
+        let u = (phi / TAU).rem_euclid(1.0);
+        let v = d.z.clamp(-1.0, 1.0).acos() / PI;
+        vec2f(u, v)
+    }
+    fn uv_to_direction(&self, uv: Vec2f) -> Vec3f {
+        let phi = uv.x.mul_add(TAU, self.rotation);
+        let (sin_theta, cos_theta) = (uv.y * PI).sin_cos();
+        vec3f(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+    fn texel(&self, x: usize, y: usize) -> Colorf32 {
+        self.image.get(x, y).unwrap_or_default()
+    }
+    /// Bilinearly filtered lookup, wrapping horizontally and clamping vertically
+    pub fn lookup(&self, uv: Vec2f) -> Colorf32 {
+        let x = uv.x.mul_add(self.image.width() as f32, -0.5);
+        let y = uv.y.mul_add(self.image.height() as f32, -0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let wrap = |x: f32| (x as isize).rem_euclid(self.image.width() as isize) as usize;
+        let clamp = |y: f32| (y.max(0.0) as usize).min(self.image.height() - 1);
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let (y0, y1) = (clamp(y0), clamp(y0 + 1.0));
+
+        let top = lerp(self.texel(x0, y0), self.texel(x1, y0), fx);
+        let bottom = lerp(self.texel(x0, y1), self.texel(x1, y1), fx);
+        lerp(top, bottom, fy)
+    }
+    pub fn radiance(&self, direction: Vec3f) -> Colorf32 {
+        self.lookup(self.direction_to_uv(direction))
+    }
+    pub fn sample(&self, u: Vec2f) -> EnvSample {
+        let (uv, pdf_uv) = self.distribution.sample_continuous(u);
+        let direction = self.uv_to_direction(uv);
+        EnvSample {
+            direction,
+            radiance: self.lookup(uv),
+            pdf: Self::uv_pdf_to_solid_angle(pdf_uv, uv),
+        }
+    }
+    pub fn pdf(&self, direction: Vec3f) -> f32 {
+        let uv = self.direction_to_uv(direction);
+        Self::uv_pdf_to_solid_angle(self.distribution.pdf(uv), uv)
+    }
+    /// The mapping stretches [0, 1)^2 over the sphere, with a jacobian of 2 * pi^2 * sin(theta)
+    fn uv_pdf_to_solid_angle(pdf: f32, uv: Vec2f) -> f32 {
+        let sin_theta = (uv.y * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        pdf / (2.0 * PI * PI * sin_theta)
+    }
+}
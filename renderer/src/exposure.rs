@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use rayon::{iter::ParallelIterator, slice::ParallelSlice};
+use renderer_types::prelude::*;
+
+/// Number of bins of a [`LuminanceHistogram`]
+const BINS: usize = 128;
+/// Range of log2 luminance the histogram covers, darker and brighter pixels land in the first
+/// and last bin
+const MIN_LOG: f32 = -16.0;
+const MAX_LOG: f32 = 16.0;
+
+/// How many pixels of an image fall into each range of log2 luminance
+#[derive(Debug, Clone)]
+pub struct LuminanceHistogram {
+    bins: [u32; BINS],
+}
+
+impl LuminanceHistogram {
+    /// Histogram of `hdr` with every value multiplied by `scale`, skipping pure black, which
+    /// usually is the background rather than something to expose for
+    pub fn new(hdr: &Buffer<Colorf32>, scale: f32) -> Self {
+        let bins = hdr
+            .inner_buf()
+            .par_chunks(4096)
+            .map(|chunk| {
+                let mut bins = [0; BINS];
+                for color in chunk {
+                    let luminance = color.luminance() * scale;
+                    if luminance > 0.0 && luminance.is_finite() {
+                        bins[bin(luminance.log2())] += 1;
+                    }
+                }
+                bins
+            })
+            .reduce(
+                || [0; BINS],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                    a
+                },
+            );
+        Self { bins }
+    }
+    pub fn total(&self) -> u32 {
+        self.bins.iter().sum()
+    }
+    /// Luminance below which `fraction` of the counted pixels are, `None` if there are none
+    pub fn percentile(&self, fraction: f32) -> Option<f32> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+        let target = fraction.clamp(0.0, 1.0) * total as f32;
+        let mut below = 0.0;
+        for (i, &count) in self.bins.iter().enumerate() {
+            let count = count as f32;
+            if below + count >= target && count > 0.0 {
+                // Interpolate within the bin
+                let within = (target - below) / count;
+                let width = (MAX_LOG - MIN_LOG) / BINS as f32;
+                return Some((MIN_LOG + (i as f32 + within) * width).exp2());
+            }
+            below += count;
+        }
+        Some(MAX_LOG.exp2())
+    }
+}
+
+fn bin(log_luminance: f32) -> usize {
+    let t = (log_luminance - MIN_LOG) / (MAX_LOG - MIN_LOG);
+    ((t * BINS as f32) as usize).min(BINS - 1)
+}
+
+/// Picks exposure the way the eye adapts: the luminance at `percentile` of the image is brought
+/// to `middle_grey`, gradually rather than all at once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposure {
+    /// Fraction of pixels darker than the luminance that's exposed for, 0.5 being the median
+    pub percentile: f32,
+    /// Luminance the exposed-for pixels end up at
+    pub middle_grey: f32,
+    /// Range the exposure stays in, in stops
+    pub min_ev: f32,
+    pub max_ev: f32,
+    /// How quickly the exposure follows changes, the fraction left to go shrinking by a factor
+    /// of e every `1 / speed` seconds
+    pub speed: f32,
+    /// Current exposure in stops, `None` until the first image
+    ev: Option<f32>,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            percentile: 0.5,
+            middle_grey: 0.18,
+            min_ev: -12.0,
+            max_ev: 12.0,
+            speed: 3.0,
+            ev: None,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Exposure in stops that would bring the image of `histogram` to middle grey
+    pub fn target(&self, histogram: &LuminanceHistogram) -> Option<f32> {
+        let luminance = histogram.percentile(self.percentile)?;
+        Some(
+            (self.middle_grey / luminance)
+                .log2()
+                .clamp(self.min_ev, self.max_ev),
+        )
+    }
+    /// Moves the exposure towards the target for `histogram`, `elapsed` being the time since the
+    /// last update. The first update jumps straight to it
+    pub fn update(&mut self, histogram: &LuminanceHistogram, elapsed: Duration) -> f32 {
+        let Some(target) = self.target(histogram) else {
+            return self.ev();
+        };
+        let ev = match self.ev {
+            Some(ev) => {
+                let blend = 1.0 - (-self.speed * elapsed.as_secs_f32()).exp();
+                ev + (target - ev) * blend
+            }
+            None => target,
+        };
+        self.ev = Some(ev);
+        ev
+    }
+    /// Current exposure in stops
+    pub fn ev(&self) -> f32 {
+        self.ev.unwrap_or(0.0)
+    }
+    /// Whether the exposure is within `tolerance` stops of the target for `histogram`
+    pub fn settled(&self, histogram: &LuminanceHistogram, tolerance: f32) -> bool {
+        match (self.ev, self.target(histogram)) {
+            (Some(ev), Some(target)) => (target - ev).abs() <= tolerance,
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
+use renderer_types::prelude::*;
+
+/// Image that any number of threads can add light to at once, at any pixel.
+///
+/// Integrators that trace paths from the lights use it for light that reaches the camera through
+/// a different pixel than the one being rendered
+pub struct SplatFilm {
+    /// Bits of the f32 red, green and blue sums
+    pixels: Vec<[AtomicU32; 3]>,
+    width: usize,
+    height: usize,
+}
+
+impl SplatFilm {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            pixels: (0..width * height).map(|_| Default::default()).collect(),
+            width,
+            height,
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Adds `color` to the pixel containing the continuous pixel position `raster`, ignoring
+    /// positions outside the image and colors that aren't finite
+    pub fn add(&self, raster: Vec2f, color: Colorf32) {
+        if ![color.r, color.g, color.b].iter().all(|c| c.is_finite())
+            || raster.x < 0.0
+            || raster.y < 0.0
+        {
+            return;
+        }
+        let (x, y) = (raster.x as usize, raster.y as usize);
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let pixel = &self.pixels[y * self.width + x];
+        for (sum, value) in pixel.iter().zip([color.r, color.g, color.b]) {
+            atomic_add(sum, value);
+        }
+    }
+    /// Adds everything splatted so far to `accum` and clears the film
+    pub fn drain_into(&self, accum: &mut Buffer<Colorf32>) {
+        accum
+            .inner_buf_mut()
+            .par_iter_mut()
+            .zip(self.pixels.par_iter())
+            .for_each(|(p, sum)| {
+                let [r, g, b] = sum
+                    .each_ref()
+                    .map(|c| f32::from_bits(c.swap(0, Ordering::Relaxed)));
+                *p += Color::new(r, g, b);
+            });
+    }
+}
+
+fn atomic_add(sum: &AtomicU32, value: f32) {
+    if value == 0.0 {
+        return;
+    }
+    let mut current = sum.load(Ordering::Relaxed);
+    loop {
+        let new = (f32::from_bits(current) + value).to_bits();
+        match sum.compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+}
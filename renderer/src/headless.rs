@@ -0,0 +1,111 @@
+//! Rendering straight to a file, without a window
+
+use std::{path::PathBuf, time::Instant};
+
+use renderer_types::{
+    image::{HdrFormat, ImageError},
+    prelude::*,
+};
+
+use crate::{
+    aov::Aovs,
+    camera::Camera,
+    exposure::{AutoExposure, LuminanceHistogram},
+    film::SplatFilm,
+    integrator::IntegratorKind,
+    render,
+    scene::Scene,
+    tonemap::ToneMapping,
+};
+
+/// What `renderer render` renders, and where to
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    /// Renders with different seeds have independent noise
+    pub seed: u32,
+    /// `.exr` gets the radiance along with the passes of [`Aovs`], `.hdr` and `.pfm` the
+    /// radiance, and other formats the image the viewer would show
+    pub output: PathBuf,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: 512,
+            samples_per_pixel: 64,
+            max_depth: 8,
+            seed: 0,
+            output: PathBuf::from("render.png"),
+        }
+    }
+}
+
+impl RenderSettings {
+    /// Whether the output is an EXR, which gets the passes as well
+    pub fn exr(&self) -> bool {
+        self.output
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("exr"))
+    }
+}
+
+/// Renders `scene` with all the samples at once and saves it, printing how long each step took
+pub fn render(
+    scene: &Scene,
+    kind: IntegratorKind,
+    tone_mapping: ToneMapping,
+    settings: &RenderSettings,
+) -> Result<(), ImageError> {
+    let (width, height) = (settings.width, settings.height);
+    let camera = Camera::default();
+
+    let start = Instant::now();
+    let mut integrator = kind.build(settings.max_depth);
+    integrator.preprocess(scene);
+    println!("Preprocessing for {kind} took {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut accum = Buffer::new(width, height, Color::black());
+    let film = SplatFilm::new(width, height);
+    for pass in 0..settings.samples_per_pixel {
+        // No two passes of renders with different seeds share random numbers
+        let seed = (settings.seed as u64) << 32 | pass as u64;
+        render::render_pass(scene, integrator.as_ref(), &camera, &mut accum, &film, seed);
+    }
+    let took = start.elapsed();
+    let rays = (width * height) as f64 * settings.samples_per_pixel as f64;
+    println!(
+        "Rendering {width}x{height} at {} samples per pixel took {took:?}, {:.2}M camera rays/s",
+        settings.samples_per_pixel,
+        rays / took.as_secs_f64() / 1e6
+    );
+
+    let start = Instant::now();
+    let path = &settings.output;
+    let scale = 1.0 / settings.samples_per_pixel as f32;
+    if settings.exr() {
+        let beauty = accum.map(|&c| c * scale);
+        Aovs::render(scene, &camera, width, height)
+            .to_exr(&beauty)
+            .save(path)?;
+    } else if HdrFormat::from_path(path).is_some() {
+        accum.map(|&c| c * scale).save_hdr(path)?;
+    } else {
+        // Exposed the way the viewer settles on
+        let mut developed = tone_mapping;
+        let histogram = LuminanceHistogram::new(&accum, scale);
+        developed.exposure += AutoExposure::default()
+            .target(&histogram)
+            .unwrap_or_default();
+        let mut image = Buffer::new(width, height, Rgba::black());
+        developed.develop(&accum, scale, &mut image);
+        image.save(path)?;
+    }
+    println!("Saving {path:?} took {:?}", start.elapsed());
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use renderer_types::{
+    prelude::*,
+    sampling::{sample_cosine_hemisphere, Onb, Rng},
+};
+
+use super::{spawn_ray, Integrator};
+use crate::scene::Scene;
+
+/// Shades surfaces by the fraction of the hemisphere above them that is unoccluded, for fast
+/// clay-style previews.
+///
+/// Occlusion rays are cosine-weighted, so directions near the horizon count for less
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    /// Occlusion rays cast per camera ray
+    pub samples: u32,
+    /// Geometry further away than this doesn't occlude
+    pub max_distance: f32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            samples: 16,
+            max_distance: 1.0,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32 {
+        let Some(hit) = scene.hit(ray, 0.0..) else {
+            return Color::white();
+        };
+        let frame = Onb::from_w(scene.materials[hit.material].shading_hit(&hit).normal);
+        let unoccluded = (0..self.samples)
+            .filter(|_| {
+                let direction = frame.to_world(sample_cosine_hemisphere(rng.next_vec2f()));
+                !scene.occluded(&spawn_ray(&hit, direction), self.max_distance)
+            })
+            .count();
+        Color::splat(unoccluded as f32 / self.samples.max(1) as f32)
+    }
+}
@@ -0,0 +1,589 @@
+use std::slice;
+
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::{environment_lighting, power_heuristic, spawn_ray, Integrator};
+use crate::{
+    camera::Camera,
+    film::SplatFilm,
+    light::Light,
+    material::BsdfSample,
+    object::{BoundingSphere, Hit},
+    scene::Scene,
+};
+
+/// Bidirectional path tracer (Veach, "Robust Monte Carlo Methods for Light Transport
+/// Simulation", 1997).
+///
+/// Traces one subpath from the camera and one from a light, and connects every vertex of one to
+/// every vertex of the other, weighting the ways of building each path with multiple importance
+/// sampling. Light subpaths find caustics and light coming in through small openings, which paths
+/// from the camera hardly ever connect to. The environment is only reached from the camera, the
+/// same way [`PathTracer`](super::PathTracer) does
+#[derive(Debug, Clone, Copy)]
+pub struct Bidirectional {
+    pub max_depth: u32,
+}
+
+impl Integrator for Bidirectional {
+    /// Without a film to splat onto, light subpaths are never connected straight to the camera
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32 {
+        self.trace(ray, scene, None, rng)
+    }
+    fn radiance_splatting(
+        &self,
+        ray: &Ray3f,
+        scene: &Scene,
+        camera: &Camera,
+        film: &SplatFilm,
+        rng: &mut Rng,
+    ) -> Colorf32 {
+        self.trace(ray, scene, Some(View { camera, film }), rng)
+    }
+}
+
+impl Bidirectional {
+    fn trace(&self, ray: &Ray3f, scene: &Scene, view: Option<View>, rng: &mut Rng) -> Colorf32 {
+        let ctx = Context {
+            scene,
+            bounds: scene.light_bounds(),
+            view,
+        };
+        let mut radiance = Color::black();
+        let camera = self.camera_subpath(&ctx, ray, rng, &mut radiance);
+        let light = self.light_subpath(&ctx, rng);
+
+        // s and t are the number of vertices taken from the light and camera subpaths. Every
+        // light is a point or a direction, so no path ends by hitting one and s is at least 1
+        for t in 1..=camera.len() {
+            for s in 1..=light.len() {
+                if (s == 1 && t == 1) || s + t - 2 > self.max_depth as usize {
+                    continue;
+                }
+                if t == 1 {
+                    let Some(view) = view else {
+                        continue;
+                    };
+                    if let Some((contribution, raster)) = connect_to_camera(&ctx, view, &light[..s])
+                    {
+                        view.film.add(raster, contribution);
+                    }
+                } else {
+                    radiance += connect(&ctx, &light[..s], &camera[..t], rng);
+                }
+            }
+        }
+        radiance
+    }
+
+    /// Traces a subpath from the camera along `ray`, adding the environment light it finds to
+    /// `environment`
+    fn camera_subpath(
+        &self,
+        ctx: &Context,
+        ray: &Ray3f,
+        rng: &mut Rng,
+        environment: &mut Colorf32,
+    ) -> Vec<Vertex> {
+        let scene = ctx.scene;
+        let direction = ray.direction().unit();
+        let mut path = vec![Vertex::new(
+            VertexKind::Camera,
+            *ray.origin(),
+            Color::white(),
+        )];
+        let mut ray = Ray3f::new(*ray.origin(), direction);
+        let mut beta = Color::white();
+        let mut pdf_fwd = ctx.view.map_or(1.0, |view| view.pdf_direction(direction));
+
+        let mut specular = true;
+        let mut bsdf_pdf = 0.0;
+        // Light subpaths reach any path with a vertex that can be connected straight to the
+        // camera, and sampling the sun reaches any path with a vertex that can be connected to
+        // it, so only specular chains without either get to see the sun disk
+        let mut sees_sun = true;
+        let mut prev_connectible = ctx.view.is_some();
+
+        loop {
+            let Some(hit) = scene.hit(&ray, 0.0..) else {
+                let direction = *ray.direction();
+                let env = &scene.environment;
+                let sky = env.radiance_without_sun(direction);
+                *environment += beta
+                    * if !specular {
+                        sky * power_heuristic(bsdf_pdf, env.pdf(direction))
+                    } else if sees_sun {
+                        env.radiance(direction)
+                    } else {
+                        sky
+                    };
+                break;
+            };
+            let wo = -*ray.direction();
+            let mut vertex = Vertex::surface(hit, wo, beta);
+            vertex.pdf_fwd = path[path.len() - 1].convert_density(pdf_fwd, &vertex, scene);
+            path.push(vertex);
+
+            let material = &scene.materials[hit.material];
+            *environment += beta * environment_lighting(scene, material, &hit, wo, rng);
+            if path.len() > self.max_depth as usize {
+                break;
+            }
+            let Some(sample) = scatter(&mut path, scene, rng, &mut beta, &mut pdf_fwd) else {
+                break;
+            };
+            if !sample.specular && prev_connectible {
+                sees_sun = false;
+            }
+            prev_connectible = !sample.specular;
+            specular = sample.specular;
+            bsdf_pdf = sample.pdf;
+            ray = spawn_ray(&hit, sample.direction);
+        }
+        path
+    }
+
+    /// Traces a subpath from a randomly chosen light
+    fn light_subpath(&self, ctx: &Context, rng: &mut Rng) -> Vec<Vertex> {
+        let scene = ctx.scene;
+        let Some((index, light_pdf)) = choose_light(scene, rng) else {
+            return Vec::new();
+        };
+        let emission =
+            scene.lights[index].sample_emission(rng.next_vec2f(), rng.next_vec2f(), ctx.bounds);
+        let mut beta = emission.power / light_pdf;
+        let mut origin = Vertex::new(VertexKind::Light(index), emission.origin, beta);
+        origin.pdf_fwd = emission.pdf_position * light_pdf;
+        let mut path = vec![origin];
+        let mut ray = Ray3f::new(emission.origin, emission.direction);
+        let mut pdf_fwd = emission.pdf_direction;
+
+        while let Some(hit) = scene.hit(&ray, 0.0..) {
+            let wo = -*ray.direction();
+            let mut vertex = Vertex::surface(hit, wo, beta);
+            let prev = &path[path.len() - 1];
+            vertex.pdf_fwd = if prev.is_infinite_light(scene) {
+                // The beam is made of parallel rays, so its density on a surface only depends on
+                // the angle it's hit at
+                emission.pdf_position * hit.normal.dot(wo).abs()
+            } else {
+                prev.convert_density(pdf_fwd, &vertex, scene)
+            };
+            path.push(vertex);
+
+            if path.len() > self.max_depth as usize {
+                break;
+            }
+            let Some(sample) = scatter(&mut path, scene, rng, &mut beta, &mut pdf_fwd) else {
+                break;
+            };
+            ray = spawn_ray(&hit, sample.direction);
+        }
+        path
+    }
+}
+
+/// The camera and film light subpaths are connected to
+#[derive(Clone, Copy)]
+struct View<'a> {
+    camera: &'a Camera,
+    film: &'a SplatFilm,
+}
+
+impl View<'_> {
+    fn raster(&self, point: Vec3f) -> Option<Vec2f> {
+        self.camera
+            .raster(point, self.film.width(), self.film.height())
+    }
+    fn pdf_direction(&self, direction: Vec3f) -> f32 {
+        self.camera
+            .pdf_direction(direction, self.film.width(), self.film.height())
+    }
+    fn importance(&self, direction: Vec3f) -> f32 {
+        self.camera
+            .importance(direction, self.film.width(), self.film.height())
+    }
+}
+
+struct Context<'a> {
+    scene: &'a Scene,
+    /// Where light subpaths from directional lights are aimed
+    bounds: BoundingSphere,
+    /// `None` when light subpaths aren't connected straight to the camera
+    view: Option<View<'a>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum VertexKind {
+    Camera,
+    /// Index into the scene's lights
+    Light(usize),
+    Surface,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    point: Vec3f,
+    /// Surface the vertex is on, only meaningful for surface vertices
+    hit: Hit,
+    /// Unit vector towards the previous vertex of the subpath, for surface vertices
+    wo: Vec3f,
+    /// Contribution of the subpath up to this vertex, divided by the probability of sampling it
+    beta: Colorf32,
+    /// Scattered specularly, so nothing can be connected to it
+    delta: bool,
+    /// Probability density of sampling this vertex from the previous one, per unit area
+    pdf_fwd: f32,
+    /// Probability density of sampling this vertex from the next one, had the subpath been traced
+    /// the other way around
+    pdf_rev: f32,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, point: Vec3f, beta: Colorf32) -> Self {
+        Self {
+            kind,
+            point,
+            hit: Hit::default(),
+            wo: Vec3f::splat(0.0),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }
+    }
+    fn surface(hit: Hit, wo: Vec3f, beta: Colorf32) -> Self {
+        Self {
+            hit,
+            wo,
+            ..Self::new(VertexKind::Surface, hit.point, beta)
+        }
+    }
+    fn on_surface(&self) -> bool {
+        matches!(self.kind, VertexKind::Surface)
+    }
+    fn is_infinite_light(&self, scene: &Scene) -> bool {
+        matches!(self.kind, VertexKind::Light(index) if scene.lights[index].is_infinite())
+    }
+    /// Converts a density per unit solid angle around this vertex to one per unit area at `next`
+    fn convert_density(&self, pdf: f32, next: &Vertex, scene: &Scene) -> f32 {
+        // Infinitely far away lights are sampled by direction anyway
+        if next.is_infinite_light(scene) {
+            return pdf;
+        }
+        let offset = next.point - self.point;
+        let distance_squared = offset.len_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let cos = if next.on_surface() {
+            next.hit.normal.dot(offset).abs() / distance_squared.sqrt()
+        } else {
+            1.0
+        };
+        pdf * cos / distance_squared
+    }
+    /// BSDF times cosine for light scattering between the previous vertex and `next`
+    fn f(&self, scene: &Scene, next: &Vertex) -> Colorf32 {
+        let wi = (next.point - self.point).unit();
+        scene.materials[self.hit.material].eval(&self.hit, self.wo, wi)
+    }
+    /// Probability density per unit area of sampling `next` from this vertex, when it was reached
+    /// from `prev`
+    fn pdf(&self, ctx: &Context, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        let scene = ctx.scene;
+        match self.kind {
+            VertexKind::Camera => ctx.view.map_or(0.0, |view| {
+                self.convert_density(view.pdf_direction(next.point - self.point), next, scene)
+            }),
+            VertexKind::Light(index) => {
+                let light = &scene.lights[index];
+                let pdf = light.emission_pdf(next.point, ctx.bounds);
+                match *light {
+                    Light::Directional { direction, .. } if next.on_surface() => {
+                        pdf * next.hit.normal.dot(direction).abs()
+                    }
+                    Light::Directional { .. } => pdf,
+                    Light::Point { .. } => self.convert_density(pdf, next, scene),
+                }
+            }
+            VertexKind::Surface => {
+                let Some(prev) = prev else {
+                    return 0.0;
+                };
+                let wo = (prev.point - self.point).unit();
+                let wi = (next.point - self.point).unit();
+                let pdf = scene.materials[self.hit.material].pdf(&self.hit, wo, wi);
+                self.convert_density(pdf, next, scene)
+            }
+        }
+    }
+}
+
+/// Densities of a vertex, as needed to weigh the ways of building a path
+#[derive(Clone, Copy)]
+struct Densities {
+    fwd: f32,
+    rev: f32,
+    delta: bool,
+}
+
+impl From<&Vertex> for Densities {
+    fn from(vertex: &Vertex) -> Self {
+        Self {
+            fwd: vertex.pdf_fwd,
+            rev: vertex.pdf_rev,
+            delta: vertex.delta,
+        }
+    }
+}
+
+/// Picks one of the scene's lights uniformly, returning its index and the probability of picking
+/// it
+fn choose_light(scene: &Scene, rng: &mut Rng) -> Option<(usize, f32)> {
+    let count = scene.lights.len();
+    if count == 0 {
+        return None;
+    }
+    let index = ((rng.next_f32() * count as f32) as usize).min(count - 1);
+    Some((index, 1.0 / count as f32))
+}
+
+/// Samples the direction a subpath continues in from its last vertex, updating `beta` and
+/// `pdf_fwd` for the next vertex, and the reverse density of the vertex before it
+fn scatter(
+    path: &mut [Vertex],
+    scene: &Scene,
+    rng: &mut Rng,
+    beta: &mut Colorf32,
+    pdf_fwd: &mut f32,
+) -> Option<BsdfSample> {
+    let [.., prev, vertex] = path else {
+        return None;
+    };
+    let material = &scene.materials[vertex.hit.material];
+    let sample = material.sample(&vertex.hit, vertex.wo, rng.next_vec3f())?;
+    *beta *= sample.weight;
+    if sample.specular {
+        vertex.delta = true;
+        *pdf_fwd = 0.0;
+        prev.pdf_rev = 0.0;
+    } else {
+        *pdf_fwd = sample.pdf;
+        let pdf_rev = material.pdf(&vertex.hit, sample.direction, vertex.wo);
+        prev.pdf_rev = vertex.convert_density(pdf_rev, prev, scene);
+    }
+    Some(sample)
+}
+
+/// Whether nothing blocks the straight line from the surface vertex `from` to `to`
+fn unoccluded(scene: &Scene, from: &Vertex, to: Vec3f) -> bool {
+    let offset = to - from.point;
+    let distance = offset.len();
+    // Stop just short of `to`, which may be on a surface itself
+    !scene.occluded(&spawn_ray(&from.hit, offset / distance), distance * 0.999)
+}
+
+/// Connects the last vertex of `light` (at least two vertices long) straight to the camera,
+/// returning the contribution and where it lands on the film
+fn connect_to_camera(ctx: &Context, view: View, light: &[Vertex]) -> Option<(Colorf32, Vec2f)> {
+    let scene = ctx.scene;
+    let qs = light.last()?;
+    if qs.delta {
+        return None;
+    }
+    let raster = view.raster(qs.point)?;
+    let mut camera = Vertex::new(VertexKind::Camera, view.camera.origin, Color::black());
+    let f = qs.f(scene, &camera);
+    if f == Color::black() || !unoccluded(scene, qs, camera.point) {
+        return None;
+    }
+    let to_qs = qs.point - camera.point;
+    let cos = to_qs.unit().y;
+    camera.beta = Color::splat(view.importance(to_qs) * cos / to_qs.len_squared());
+    let contribution = qs.beta * f * camera.beta;
+    Some((
+        contribution * mis_weight(ctx, light, slice::from_ref(&camera)),
+        raster,
+    ))
+}
+
+/// Connects the last vertices of `light` and `camera`, which has at least two vertices. A light
+/// subpath of one vertex is replaced by sampling a light afresh
+fn connect(ctx: &Context, light: &[Vertex], camera: &[Vertex], rng: &mut Rng) -> Colorf32 {
+    let scene = ctx.scene;
+    let pt = &camera[camera.len() - 1];
+    if pt.delta {
+        return Color::black();
+    }
+    if let [_] = light {
+        let Some((index, light_pdf)) = choose_light(scene, rng) else {
+            return Color::black();
+        };
+        let sample = scene.lights[index].sample(pt.point);
+        let material = &scene.materials[pt.hit.material];
+        let f = material.eval(&pt.hit, pt.wo, sample.direction);
+        if f == Color::black()
+            || scene.occluded(&spawn_ray(&pt.hit, sample.direction), sample.distance)
+        {
+            return Color::black();
+        }
+        // Directional lights get a vertex well outside the scene, in the light's direction
+        let distance = if sample.distance.is_finite() {
+            sample.distance
+        } else {
+            (pt.point - ctx.bounds.center).len() + 2.0 * ctx.bounds.radius
+        };
+        let vertex = Vertex::new(
+            VertexKind::Light(index),
+            pt.point + sample.direction * distance,
+            sample.contribution / light_pdf,
+        );
+        let contribution = pt.beta * f * vertex.beta;
+        return contribution * mis_weight(ctx, slice::from_ref(&vertex), camera);
+    }
+
+    let qs = &light[light.len() - 1];
+    if qs.delta {
+        return Color::black();
+    }
+    let f = qs.f(scene, pt) * pt.f(scene, qs);
+    if f == Color::black() || !unoccluded(scene, qs, pt.point) {
+        return Color::black();
+    }
+    let contribution = qs.beta * f * pt.beta / (pt.point - qs.point).len_squared();
+    contribution * mis_weight(ctx, light, camera)
+}
+
+/// Power heuristic weight of building the path made of all of `light` and `camera` by
+/// connecting their last vertices, among every other way of splitting it into two subpaths
+fn mis_weight(ctx: &Context, light: &[Vertex], camera: &[Vertex]) -> f32 {
+    let (s, t) = (light.len(), camera.len());
+    if s + t == 2 {
+        return 1.0;
+    }
+    let qs = &light[s - 1];
+    let pt = &camera[t - 1];
+    let qs_minus = s.checked_sub(2).map(|i| &light[i]);
+    let pt_minus = t.checked_sub(2).map(|i| &camera[i]);
+
+    // The densities around the connection depend on what's on the other side of it
+    let camera = Connected {
+        vertices: camera,
+        last_rev: qs.pdf(ctx, qs_minus, pt),
+        before_last_rev: pt_minus.map_or(0.0, |pt_minus| pt.pdf(ctx, Some(qs), pt_minus)),
+    };
+    let light = Connected {
+        vertices: light,
+        last_rev: pt.pdf(ctx, pt_minus, qs),
+        before_last_rev: qs_minus.map_or(0.0, |qs_minus| qs.pdf(ctx, Some(pt), qs_minus)),
+    };
+    // Move the connection towards the camera, down to connecting light subpaths straight to it if
+    // that's being done, and towards the light. Every light is a point or a direction, so paths
+    // can't be built without any light subpath vertices
+    let min_t = if ctx.view.is_some() { 1 } else { 2 };
+    1.0 / (1.0 + camera.moved_connections(min_t) + light.moved_connections(1))
+}
+
+/// A subpath ending in a connection to the other, which changes the reverse densities of its
+/// last two vertices from what they were when it was traced
+struct Connected<'a> {
+    vertices: &'a [Vertex],
+    last_rev: f32,
+    /// Meaningless for subpaths of a single vertex
+    before_last_rev: f32,
+}
+
+impl Connected<'_> {
+    fn densities(&self, i: usize) -> Densities {
+        let mut densities = Densities::from(&self.vertices[i]);
+        let len = self.vertices.len();
+        if i + 1 == len {
+            densities.rev = self.last_rev;
+            // The connected vertices can be connected to by definition
+            densities.delta = false;
+        } else if i + 2 == len {
+            densities.rev = self.before_last_rev;
+        }
+        densities
+    }
+    /// Sum of the squared ratios of the densities of building the path by moving the connection
+    /// along this subpath, no further than vertex `min`, to that of the connection being made
+    fn moved_connections(&self, min: usize) -> f32 {
+        // Densities of zero next to a specular vertex stand for a delta distribution, which
+        // cancels out. Anywhere else they mean the other subpath can't reach the vertex at all
+        let remap = |pdf: f32| if pdf != 0.0 { pdf } else { 1.0 };
+        let len = self.vertices.len();
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..len).rev() {
+            let vertex = self.densities(i);
+            let next_delta = i + 1 < len && self.densities(i + 1).delta;
+            if vertex.rev == 0.0 && !next_delta {
+                break;
+            }
+            ratio *= remap(vertex.rev) / remap(vertex.fwd);
+            if i >= min && !vertex.delta && !self.densities(i - 1).delta {
+                sum += ratio * ratio;
+            }
+        }
+        sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        environment::{Environment, EnvironmentMap},
+        integrator::PathTracer,
+        material::{Material, PbrMaterial},
+        object::Sphere,
+        render::render_pass,
+    };
+
+    /// Renders a small image from the default camera, averaging `passes` samples per pixel
+    fn render(integrator: &dyn Integrator, scene: &Scene, passes: u64) -> Buffer<Colorf32> {
+        let (width, height) = (16, 8);
+        let camera = Camera::default();
+        let mut accum = Buffer::new(width, height, Color::black());
+        let film = SplatFilm::new(width, height);
+        for pass in 0..passes {
+            render_pass(scene, integrator, &camera, &mut accum, &film, pass);
+        }
+        accum.map(|&c| c / passes as f32)
+    }
+
+    #[test]
+    fn agrees_with_path_tracing() {
+        // Only light reaching the camera through diffuse and glossy surfaces, which both find
+        let black = EnvironmentMap::new(Buffer::new(1, 1, Color::black()));
+        let mut scene = Scene::new(Environment::Map(black));
+        let gold = scene.add_material(Material::Pbr(PbrMaterial::new(
+            Color::new(1.0, 0.766, 0.336),
+            1.0,
+            0.5,
+        )));
+        let red = scene.add_material(Material::lambertian(Color::new(0.8, 0.1, 0.1)));
+        scene.lights.push(Light::point_from_temperature(
+            vec3f(-0.5, 2.2, 0.6),
+            4000.0,
+            1000.0,
+        ));
+        let scene = scene
+            .with_object(Sphere::new(vec3f(0.3, 3, 0), 0.4).with_material(gold))
+            .with_object(Sphere::new(vec3f(-0.5, 2.6, -0.2), 0.2).with_material(red))
+            .with_object(Sphere::new(vec3f(0, 3, -100.4), 100.0));
+
+        let max_depth = 4;
+        let path = render(&PathTracer { max_depth }, &scene, 256);
+        let bdpt = render(&Bidirectional { max_depth }, &scene, 256);
+        let luminance = |image: &Buffer<Colorf32>| -> f32 {
+            image.iter().map(|c| c.luminance()).sum::<f32>() / image.iter().count() as f32
+        };
+        let (path, bdpt) = (luminance(&path), luminance(&bdpt));
+        assert!(path > 0.0);
+        assert!((bdpt / path - 1.0).abs() < 0.02, "{bdpt} against {path}");
+    }
+}
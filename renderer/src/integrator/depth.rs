@@ -0,0 +1,31 @@
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::Integrator;
+use crate::{lerp, scene::Scene};
+
+/// Shades by distance from the camera, fading linearly from white up close to black at
+/// `max_distance` and beyond
+#[derive(Debug, Clone, Copy)]
+pub struct Depth {
+    pub max_distance: f32,
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self { max_distance: 10.0 }
+    }
+}
+
+impl Integrator for Depth {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, _rng: &mut Rng) -> Colorf32 {
+        let Some(hit) = scene.hit(ray, 0.0..) else {
+            return Color::black();
+        };
+        let distance = hit.t * ray.direction().len();
+        lerp(
+            Color::white(),
+            Color::black(),
+            (distance / self.max_distance).min(1.0),
+        )
+    }
+}
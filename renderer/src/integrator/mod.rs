@@ -0,0 +1,198 @@
+use std::{fmt, str::FromStr};
+
+use renderer_types::{prelude::*, sampling::Rng};
+
+use crate::{camera::Camera, film::SplatFilm, material::Material, object::Hit, scene::Scene};
+
+mod ao;
+mod bdpt;
+mod depth;
+mod normals;
+mod path;
+mod photon;
+mod spectral;
+mod whitted;
+pub use ao::*;
+pub use bdpt::*;
+pub use depth::*;
+pub use normals::*;
+pub use path::*;
+pub use photon::*;
+pub use spectral::*;
+pub use whitted::*;
+
+/// Computes the color seen along camera rays, the "shader" of the renderer
+pub trait Integrator: Send + Sync {
+    /// Runs once before `scene` is rendered, for integrators that need to look at the whole scene
+    /// first
+    fn preprocess(&mut self, scene: &Scene) {
+        let _ = scene;
+    }
+    /// Whether [`Integrator::preprocess`] has to run again when only the environment of the scene
+    /// changes, like when it's turned. Integrators that don't preprocess can say either
+    fn preprocess_uses_environment(&self) -> bool {
+        true
+    }
+    /// Estimates the radiance arriving at the camera along `ray`
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32;
+    /// Like [`Integrator::radiance`], for integrators that can also find light reaching `camera`
+    /// through other pixels than the one `ray` goes through, which they add to `film`
+    fn radiance_splatting(
+        &self,
+        ray: &Ray3f,
+        scene: &Scene,
+        camera: &Camera,
+        film: &SplatFilm,
+        rng: &mut Rng,
+    ) -> Colorf32 {
+        let _ = (camera, film);
+        self.radiance(ray, scene, rng)
+    }
+}
+
+/// Every integrator the renderer ships with, for picking one at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegratorKind {
+    Normals,
+    Depth,
+    AmbientOcclusion,
+    Whitted,
+    PathTracer,
+    Bidirectional,
+    PhotonMapper,
+    Spectral,
+}
+
+impl IntegratorKind {
+    pub const ALL: [Self; 8] = [
+        Self::Normals,
+        Self::Depth,
+        Self::AmbientOcclusion,
+        Self::Whitted,
+        Self::PathTracer,
+        Self::Bidirectional,
+        Self::PhotonMapper,
+        Self::Spectral,
+    ];
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Normals => "normals",
+            Self::Depth => "depth",
+            Self::AmbientOcclusion => "ao",
+            Self::Whitted => "whitted",
+            Self::PathTracer => "path",
+            Self::Bidirectional => "bdpt",
+            Self::PhotonMapper => "photon",
+            Self::Spectral => "spectral",
+        }
+    }
+    /// The next kind in [`IntegratorKind::ALL`], wrapping around
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&k| k == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+    /// `max_depth` limits the number of bounces of integrators that follow light paths.
+    ///
+    /// The integrator still needs [`Integrator::preprocess`] to be run before rendering
+    pub fn build(self, max_depth: u32) -> Box<dyn Integrator> {
+        match self {
+            Self::Normals => Box::new(Normals),
+            Self::Depth => Box::new(Depth::default()),
+            Self::AmbientOcclusion => Box::new(AmbientOcclusion::default()),
+            Self::Whitted => Box::new(Whitted { max_depth }),
+            Self::PathTracer => Box::new(PathTracer { max_depth }),
+            Self::Bidirectional => Box::new(Bidirectional { max_depth }),
+            Self::PhotonMapper => Box::new(PhotonMapper::new(max_depth)),
+            Self::Spectral => Box::new(SpectralPathTracer { max_depth }),
+        }
+    }
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|k| k.name()).collect();
+                format!(
+                    "Unknown integrator {s:?}, expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How far off a surface new rays start, to keep them from hitting the surface they leave
+const RAY_EPSILON: f32 = 1e-4;
+
+/// Creates a ray leaving the surface at `hit` in `direction`
+fn spawn_ray(hit: &Hit, direction: Vec3f) -> Ray3f {
+    let offset = if direction.dot(hit.normal) < 0.0 {
+        -hit.normal
+    } else {
+        hit.normal
+    };
+    Ray3f::new(hit.point + offset * RAY_EPSILON, direction)
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b <= 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+/// Light arriving directly from the scene's lights and environment and scattered towards `wo`.
+///
+/// The environment contribution is weighted for combining with BSDF sampling using the power
+/// heuristic
+fn direct_lighting(
+    scene: &Scene,
+    material: &Material,
+    hit: &Hit,
+    wo: Vec3f,
+    rng: &mut Rng,
+) -> Colorf32 {
+    let mut total = Color::black();
+    for light in &scene.lights {
+        let sample = light.sample(hit.point);
+        let f = material.eval(hit, wo, sample.direction);
+        if f == Color::black() {
+            continue;
+        }
+        if !scene.occluded(&spawn_ray(hit, sample.direction), sample.distance) {
+            total += f * sample.contribution;
+        }
+    }
+    total + environment_lighting(scene, material, hit, wo, rng)
+}
+
+/// The environment's part of [`direct_lighting`], weighted for combining with BSDF sampling
+fn environment_lighting(
+    scene: &Scene,
+    material: &Material,
+    hit: &Hit,
+    wo: Vec3f,
+    rng: &mut Rng,
+) -> Colorf32 {
+    let env = scene.environment.sample(rng.next_vec2f());
+    if env.pdf <= 0.0 {
+        return Color::black();
+    }
+    let f = material.eval(hit, wo, env.direction);
+    if f == Color::black() || scene.occluded(&spawn_ray(hit, env.direction), f32::INFINITY) {
+        return Color::black();
+    }
+    let weight = power_heuristic(env.pdf, material.pdf(hit, wo, env.direction));
+    f * env.radiance * (weight / env.pdf)
+}
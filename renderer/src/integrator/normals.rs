@@ -0,0 +1,23 @@
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::Integrator;
+use crate::scene::Scene;
+
+/// Color at the zenith of the backdrop [`Normals`] draws behind the scene
+const BACKDROP: Colorf32 = Color::new(0.529, 0.808, 0.922);
+
+/// Visualizes shading normals, mapping every component from [-1, 1] to [0, 1], in front of a
+/// backdrop fading from black straight down to sky blue straight up
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, _rng: &mut Rng) -> Colorf32 {
+        let Some(hit) = scene.hit(ray, 0.0..) else {
+            let up = ray.direction().unit().z.mul_add(0.5, 0.5);
+            return Color::black().lerp_oklab(BACKDROP, up);
+        };
+        let hit = scene.materials[hit.material].shading_hit(&hit);
+        Color::from(hit.normal.zyx().map(|n| *n += 1.) * 0.5)
+    }
+}
@@ -0,0 +1,63 @@
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::{direct_lighting, power_heuristic, spawn_ray, Integrator};
+use crate::scene::Scene;
+
+/// Unidirectional path tracer with next event estimation and multiple importance sampling of the
+/// environment
+#[derive(Debug, Clone, Copy)]
+pub struct PathTracer {
+    pub max_depth: u32,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32 {
+        let mut radiance = Color::black();
+        let mut throughput = Color::white();
+        let mut ray = Ray3f::new(*ray.origin(), ray.direction().unit());
+        // Camera rays and specular bounces can't be matched by light sampling, so they see the
+        // full environment
+        let mut specular = true;
+        let mut bsdf_pdf = 0.0;
+
+        for depth in 0..self.max_depth {
+            let Some(hit) = scene.hit(&ray, 0.0..) else {
+                let direction = *ray.direction();
+                let environment = &scene.environment;
+                radiance += throughput
+                    * if specular {
+                        environment.radiance(direction)
+                    } else {
+                        let weight = power_heuristic(bsdf_pdf, environment.pdf(direction));
+                        environment.radiance_without_sun(direction) * weight
+                    };
+                break;
+            };
+            let material = &scene.materials[hit.material];
+            let wo = -*ray.direction();
+            radiance += throughput * direct_lighting(scene, material, &hit, wo, rng);
+
+            let Some(sample) = material.sample(&hit, wo, rng.next_vec3f()) else {
+                break;
+            };
+            throughput *= sample.weight;
+            specular = sample.specular;
+            bsdf_pdf = sample.pdf;
+            ray = spawn_ray(&hit, sample.direction);
+
+            // Russian roulette, once paths are long enough to rarely matter
+            if depth >= 3 {
+                let survival = throughput
+                    .r
+                    .max(throughput.g)
+                    .max(throughput.b)
+                    .clamp(0.05, 0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance
+    }
+}
@@ -0,0 +1,163 @@
+use std::f32::consts::PI;
+
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::{direct_lighting, power_heuristic, spawn_ray, Integrator};
+use crate::{kdtree::KdTree, material::Material, object::Hit, scene::Scene};
+
+/// Path tracer that gets caustics from a photon map (Jensen, "Global Illumination using Photon
+/// Maps", 1996).
+///
+/// Before rendering, photons are shot from the lights, and the ones that reach a non-specular
+/// surface through specular ones are stored. Wherever camera paths hit such a surface, the
+/// photons around the hit estimate the caustic light there, and the rest comes from path
+/// tracing. The estimate is blurred over `radius`, which trades noise for detail
+#[derive(Debug, Clone)]
+pub struct PhotonMapper {
+    pub max_depth: u32,
+    /// Number of photons shot from the lights, only some of which end up in caustics
+    pub photons: u32,
+    /// Distance around a hit that photons are gathered from
+    pub radius: f32,
+    caustics: KdTree<Photon>,
+}
+
+/// Light that arrived at a point through specular surfaces
+#[derive(Debug, Clone, Copy)]
+struct Photon {
+    /// Unit vector towards where the photon came from
+    direction: Vec3f,
+    power: Colorf32,
+}
+
+impl PhotonMapper {
+    pub fn new(max_depth: u32) -> Self {
+        Self {
+            max_depth,
+            photons: 1_000_000,
+            radius: 0.02,
+            caustics: KdTree::new(Vec::new()),
+        }
+    }
+    /// Follows a single photon from the lights, returning where it lands if that's a caustic
+    fn shoot(&self, scene: &Scene, rng: &mut Rng) -> Option<(Vec3f, Photon)> {
+        let count = scene.lights.len();
+        let index = ((rng.next_f32() * count as f32) as usize).min(count - 1);
+        let emission = scene.lights[index].sample_emission(
+            rng.next_vec2f(),
+            rng.next_vec2f(),
+            scene.light_bounds(),
+        );
+        let mut power = emission.power * (count as f32 / self.photons as f32);
+        let mut ray = Ray3f::new(emission.origin, emission.direction);
+
+        for depth in 0..self.max_depth {
+            let hit = scene.hit(&ray, 0.0..)?;
+            let wo = -*ray.direction();
+            let sample = scene.materials[hit.material].sample(&hit, wo, rng.next_vec3f())?;
+            if !sample.specular {
+                // Photons that didn't go through anything specular are direct light, which the
+                // path tracer already handles
+                return (depth > 0).then_some((
+                    hit.point,
+                    Photon {
+                        direction: wo,
+                        power,
+                    },
+                ));
+            }
+            power *= sample.weight;
+            ray = spawn_ray(&hit, sample.direction);
+        }
+        None
+    }
+    /// Caustic light leaving `hit` towards `wo`, estimated from the photons around it
+    fn caustic_radiance(&self, material: &Material, hit: &Hit, wo: Vec3f) -> Colorf32 {
+        let normal = material.shading_hit(hit).normal;
+        let mut total = Color::black();
+        self.caustics
+            .within(hit.point, self.radius, |_, photon, _| {
+                let cos = photon.direction.dot(normal);
+                if cos > 0.0 {
+                    // The photon power already accounts for the angle it arrived at
+                    total += material.eval(hit, wo, photon.direction) / cos * photon.power;
+                }
+            });
+        total / (PI * self.radius * self.radius)
+    }
+}
+
+impl Integrator for PhotonMapper {
+    fn preprocess(&mut self, scene: &Scene) {
+        if scene.lights.is_empty() {
+            self.caustics = KdTree::new(Vec::new());
+            return;
+        }
+        let photons = (0..self.photons)
+            .into_par_iter()
+            .filter_map(|i| self.shoot(scene, &mut Rng::new(u64::MAX, i as u64)))
+            .collect();
+        self.caustics = KdTree::new(photons);
+    }
+    /// Photons only leave the lights, the environment is left to the camera paths
+    fn preprocess_uses_environment(&self) -> bool {
+        false
+    }
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32 {
+        let mut radiance = Color::black();
+        let mut throughput = Color::white();
+        let mut ray = Ray3f::new(*ray.origin(), ray.direction().unit());
+        let mut specular = true;
+        // Light reaching a non-specular surface through specular ones is in the photon map, so
+        // paths may only see the sun directly or through specular surfaces alone
+        let mut only_specular = true;
+        let mut bsdf_pdf = 0.0;
+
+        for depth in 0..self.max_depth {
+            let Some(hit) = scene.hit(&ray, 0.0..) else {
+                let direction = *ray.direction();
+                let environment = &scene.environment;
+                radiance += throughput
+                    * if !specular {
+                        let weight = power_heuristic(bsdf_pdf, environment.pdf(direction));
+                        environment.radiance_without_sun(direction) * weight
+                    } else if only_specular {
+                        environment.radiance(direction)
+                    } else {
+                        environment.radiance_without_sun(direction)
+                    };
+                break;
+            };
+            let material = &scene.materials[hit.material];
+            let wo = -*ray.direction();
+            radiance += throughput * direct_lighting(scene, material, &hit, wo, rng);
+            if !self.caustics.is_empty() {
+                radiance += throughput * self.caustic_radiance(material, &hit, wo);
+            }
+
+            let Some(sample) = material.sample(&hit, wo, rng.next_vec3f()) else {
+                break;
+            };
+            throughput *= sample.weight;
+            specular = sample.specular;
+            only_specular &= sample.specular;
+            bsdf_pdf = sample.pdf;
+            ray = spawn_ray(&hit, sample.direction);
+
+            // Russian roulette, once paths are long enough to rarely matter
+            if depth >= 3 {
+                let survival = throughput
+                    .r
+                    .max(throughput.g)
+                    .max(throughput.b)
+                    .clamp(0.05, 0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance
+    }
+}
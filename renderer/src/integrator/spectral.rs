@@ -0,0 +1,76 @@
+use renderer_types::{
+    prelude::*,
+    sampling::Rng,
+    spectrum::{SampledSpectrum, SampledWavelengths},
+};
+
+use super::{direct_lighting, power_heuristic, spawn_ray, Integrator};
+use crate::scene::Scene;
+
+/// [`PathTracer`](super::PathTracer) carrying a few wavelengths per path instead of RGB, so
+/// dispersive materials can split light into its colors.
+///
+/// Materials, lights and the environment are still described in RGB, and turned into spectra
+/// wherever the path meets them
+#[derive(Debug, Clone, Copy)]
+pub struct SpectralPathTracer {
+    pub max_depth: u32,
+}
+
+impl Integrator for SpectralPathTracer {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, rng: &mut Rng) -> Colorf32 {
+        let mut wavelengths = SampledWavelengths::sample_visible(rng.next_f32());
+        let mut radiance = SampledSpectrum::splat(0.0);
+        let mut throughput = SampledSpectrum::splat(1.0);
+        let mut ray = Ray3f::new(*ray.origin(), ray.direction().unit());
+        // Camera rays and specular bounces can't be matched by light sampling, so they see the
+        // full environment
+        let mut specular = true;
+        let mut bsdf_pdf = 0.0;
+
+        for depth in 0..self.max_depth {
+            let Some(hit) = scene.hit(&ray, 0.0..) else {
+                let direction = *ray.direction();
+                let environment = &scene.environment;
+                let light = if specular {
+                    environment.radiance(direction)
+                } else {
+                    let weight = power_heuristic(bsdf_pdf, environment.pdf(direction));
+                    environment.radiance_without_sun(direction) * weight
+                };
+                radiance += throughput * SampledSpectrum::from_illuminant(light, &wavelengths);
+                break;
+            };
+            let material = &scene.materials[hit.material];
+            let wo = -*ray.direction();
+            let direct = direct_lighting(scene, material, &hit, wo, rng);
+            radiance += throughput * SampledSpectrum::from_illuminant(direct, &wavelengths);
+
+            let u = rng.next_vec3f();
+            let sample = if material.is_dispersive() {
+                // Each wavelength refracts its own way, only the hero one can follow this path
+                wavelengths.terminate_secondary();
+                material.sample_at(&hit, wo, u, wavelengths.hero())
+            } else {
+                material.sample(&hit, wo, u)
+            };
+            let Some(sample) = sample else {
+                break;
+            };
+            throughput *= SampledSpectrum::from_reflectance(sample.weight, &wavelengths);
+            specular = sample.specular;
+            bsdf_pdf = sample.pdf;
+            ray = spawn_ray(&hit, sample.direction);
+
+            // Russian roulette, once paths are long enough to rarely matter
+            if depth >= 3 {
+                let survival = throughput.max_value().clamp(0.05, 0.95);
+                if rng.next_f32() >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        }
+        radiance.to_rgb(&wavelengths)
+    }
+}
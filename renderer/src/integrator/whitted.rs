@@ -0,0 +1,69 @@
+use std::f32::consts::PI;
+
+use renderer_types::{prelude::*, sampling::Rng};
+
+use super::{spawn_ray, Integrator};
+use crate::{
+    material::{fresnel_dielectric, refract, Material},
+    scene::Scene,
+};
+
+/// Classic recursive ray tracing: perfect reflection and refraction, plus direct light with hard
+/// shadows everywhere else
+#[derive(Debug, Clone, Copy)]
+pub struct Whitted {
+    pub max_depth: u32,
+}
+
+impl Whitted {
+    fn trace(&self, ray: &Ray3f, scene: &Scene, depth: u32) -> Colorf32 {
+        let Some(hit) = scene.hit(ray, 0.0..) else {
+            return scene.environment.radiance(*ray.direction());
+        };
+        if depth >= self.max_depth {
+            return Color::black();
+        }
+        let material = &scene.materials[hit.material];
+        let normal = material.shading_hit(&hit).normal;
+        let wo = -ray.direction().unit();
+        let reflected = || self.trace(&spawn_ray(&hit, -wo.reflect(normal)), scene, depth + 1);
+
+        match material.base() {
+            Material::Mirror { tint } => *tint * reflected(),
+            &Material::Dielectric { ior, tint } => {
+                let ior = ior.nominal();
+                let eta = if hit.front_face { 1.0 / ior } else { ior };
+                let reflectance = fresnel_dielectric(wo.dot(normal), eta);
+                let Some(refracted) = refract(wo, normal, eta) else {
+                    return reflected();
+                };
+                let transmitted = self.trace(&spawn_ray(&hit, refracted), scene, depth + 1);
+                reflected() * reflectance + tint * transmitted * (1.0 - reflectance)
+            }
+            _ => {
+                let direct = scene
+                    .lights
+                    .iter()
+                    .map(|light| light.sample(hit.point))
+                    .filter(|sample| {
+                        !scene.occluded(&spawn_ray(&hit, sample.direction), sample.distance)
+                    })
+                    .fold(Color::black(), |acc, sample| {
+                        acc + material.eval(&hit, wo, sample.direction) * sample.contribution
+                    });
+                // Crude ambient term, as if the environment was uniformly as bright as it is
+                // right above the surface
+                let ambient = material.eval(&hit, wo, normal)
+                    * scene.environment.radiance_without_sun(normal)
+                    * PI;
+                direct + ambient
+            }
+        }
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(&self, ray: &Ray3f, scene: &Scene, _rng: &mut Rng) -> Colorf32 {
+        self.trace(ray, scene, 0)
+    }
+}
@@ -0,0 +1,94 @@
+use renderer_types::prelude::*;
+
+/// Balanced kd-tree over points in space, each carrying a `T`, for finding everything near a
+/// position.
+///
+/// The tree is implicit: the median of every range of `points` is the node splitting it, with the
+/// points before it on one side of its splitting plane and the ones after it on the other
+#[derive(Debug, Clone)]
+pub struct KdTree<T> {
+    points: Vec<(Vec3f, T)>,
+    /// Axis each node splits along, 0 to 2 for x to z
+    axes: Vec<u8>,
+}
+
+impl<T> KdTree<T> {
+    pub fn new(mut points: Vec<(Vec3f, T)>) -> Self {
+        let mut axes = vec![0; points.len()];
+        build(&mut points, &mut axes);
+        Self { points, axes }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    /// Calls `f` with every point within `radius` of `center`, its data and its squared distance
+    /// to `center`
+    pub fn within(&self, center: Vec3f, radius: f32, mut f: impl FnMut(Vec3f, &T, f32)) {
+        self.visit(0, self.points.len(), center, radius * radius, &mut f);
+    }
+    fn visit(
+        &self,
+        start: usize,
+        end: usize,
+        center: Vec3f,
+        radius_squared: f32,
+        f: &mut impl FnMut(Vec3f, &T, f32),
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let (point, data) = &self.points[mid];
+        let distance_squared = (*point - center).len_squared();
+        if distance_squared <= radius_squared {
+            f(*point, data, distance_squared);
+        }
+        let axis = self.axes[mid];
+        let offset = component(center, axis) - component(*point, axis);
+        let (near, far) = if offset < 0.0 {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.visit(near.0, near.1, center, radius_squared, f);
+        // The far side can only hold points in range if the splitting plane is
+        if offset * offset <= radius_squared {
+            self.visit(far.0, far.1, center, radius_squared, f);
+        }
+    }
+}
+
+fn build<T>(points: &mut [(Vec3f, T)], axes: &mut [u8]) {
+    if points.len() <= 1 {
+        return;
+    }
+    // Split along the axis the points are spread out the most on
+    let (mut min, mut max) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+    for (point, _) in points.iter() {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(component(*point, axis as u8));
+            max[axis] = max[axis].max(component(*point, axis as u8));
+        }
+    }
+    let axis = (0..3)
+        .max_by(|&a, &b| (max[a] - min[a]).total_cmp(&(max[b] - min[b])))
+        .unwrap_or(0) as u8;
+
+    let mid = points.len() / 2;
+    points.select_nth_unstable_by(mid, |(a, _), (b, _)| {
+        component(*a, axis).total_cmp(&component(*b, axis))
+    });
+    axes[mid] = axis;
+    let (left_points, right_points) = points.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left_points, left_axes);
+    build(&mut right_points[1..], &mut right_axes[1..]);
+}
+
+fn component(v: Vec3f, axis: u8) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
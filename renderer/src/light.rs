@@ -0,0 +1,152 @@
+use std::f32::consts::PI;
+
+use renderer_types::{
+    prelude::*,
+    sampling::{sample_uniform_disk, sample_uniform_sphere, uniform_sphere_pdf, Onb},
+};
+
+use crate::object::BoundingSphere;
+
+/// Converts photometric quantities to the units the renderer works in: luminance in cd/m^2
+/// becomes radiance, and illuminance in lux becomes irradiance
+pub const LUMINANCE_SCALE: f32 = 1e-4;
+
+/// Lights that can be sampled directly, in addition to whatever the environment contributes
+#[derive(Debug, Clone, Copy)]
+pub enum Light {
+    /// Light arriving from a single direction, like the sun
+    Directional {
+        /// Unit vector pointing towards the light
+        direction: Vec3f,
+        /// Irradiance on a surface facing the light
+        irradiance: Colorf32,
+    },
+    /// Light radiating equally in every direction from a single point
+    Point {
+        position: Vec3f,
+        /// Radiant intensity, power per unit solid angle
+        intensity: Colorf32,
+    },
+}
+
+/// Light arriving at a point, as chosen by [`Light::sample`]
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    /// Unit vector pointing towards the light
+    pub direction: Vec3f,
+    /// Distance to the light, infinite for directional lights
+    pub distance: f32,
+    /// Incoming radiance, already divided by the sampling pdf
+    pub contribution: Colorf32,
+}
+
+/// A ray of light leaving a light, as chosen by [`Light::sample_emission`]
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    pub origin: Vec3f,
+    /// Unit vector pointing away from the light
+    pub direction: Vec3f,
+    /// Power carried by the ray, already divided by the sampling pdfs
+    pub power: Colorf32,
+    /// Probability density of `origin` with respect to area
+    pub pdf_position: f32,
+    /// Probability density of `direction` with respect to solid angle, 1 for directional lights
+    /// as they only emit in one direction
+    pub pdf_direction: f32,
+}
+
+impl Light {
+    /// Point light the color of a black body at `kelvin`, emitting `lumens` in total. A 60 W
+    /// incandescent bulb is around 800 lumens at 2700 K
+    pub fn point_from_temperature(position: Vec3f, kelvin: f32, lumens: f32) -> Self {
+        // Spread evenly over the sphere, in candela
+        let intensity = lumens / (4.0 * PI);
+        Light::Point {
+            position,
+            intensity: Color::from_temperature(kelvin) * (intensity * LUMINANCE_SCALE),
+        }
+    }
+    pub fn sample(&self, point: Vec3f) -> LightSample {
+        match *self {
+            Light::Directional {
+                direction,
+                irradiance,
+            } => LightSample {
+                direction,
+                distance: f32::INFINITY,
+                contribution: irradiance,
+            },
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = position - point;
+                let distance_squared = to_light.len_squared();
+                let distance = distance_squared.sqrt();
+                LightSample {
+                    direction: to_light / distance,
+                    distance,
+                    contribution: intensity / distance_squared,
+                }
+            }
+        }
+    }
+    /// Whether the light is infinitely far away, rather than somewhere in the scene
+    pub fn is_infinite(&self) -> bool {
+        matches!(self, Light::Directional { .. })
+    }
+    /// Chooses a ray of light leaving the light, `u` and `v` are pairs of uniform samples in
+    /// [0, 1).
+    ///
+    /// Directional lights shine a beam as wide as `bounds`, so every ray can reach what's
+    /// inside it
+    pub fn sample_emission(&self, u: Vec2f, v: Vec2f, bounds: BoundingSphere) -> EmissionSample {
+        match *self {
+            Light::Directional {
+                direction,
+                irradiance,
+            } => {
+                let frame = Onb::from_w(direction);
+                let disk = sample_uniform_disk(u) * bounds.radius;
+                let area = PI * bounds.radius * bounds.radius;
+                EmissionSample {
+                    origin: bounds.center
+                        + frame.u * disk.x
+                        + frame.v * disk.y
+                        + direction * bounds.radius,
+                    direction: -direction,
+                    power: irradiance * area,
+                    pdf_position: 1.0 / area,
+                    pdf_direction: 1.0,
+                }
+            }
+            Light::Point {
+                position,
+                intensity,
+            } => EmissionSample {
+                origin: position,
+                direction: sample_uniform_sphere(v),
+                power: intensity / uniform_sphere_pdf(),
+                pdf_position: 1.0,
+                pdf_direction: uniform_sphere_pdf(),
+            },
+        }
+    }
+    /// Density with which [`Light::sample_emission`] reaches `point`.
+    ///
+    /// That is with respect to solid angle around point lights, and with respect to area
+    /// perpendicular to the beam for directional lights
+    pub fn emission_pdf(&self, point: Vec3f, bounds: BoundingSphere) -> f32 {
+        match *self {
+            Light::Directional { direction, .. } => {
+                let offset = point - bounds.center;
+                let off_axis = offset - direction * offset.dot(direction);
+                if off_axis.len_squared() > bounds.radius * bounds.radius {
+                    return 0.0;
+                }
+                1.0 / (PI * bounds.radius * bounds.radius)
+            }
+            Light::Point { .. } => uniform_sphere_pdf(),
+        }
+    }
+}
@@ -1,29 +1,58 @@
 use num_traits::Float;
-use rayon::{
-    iter::{IndexedParallelIterator, ParallelIterator},
-    slice::ParallelSliceMut,
-};
 use winit::{
     dpi::PhysicalSize,
-    event::{Event, WindowEvent},
-    raw_window_handle::HasWindowHandle,
+    event::{ElementState, Event, KeyEvent, WindowEvent},
+    event_loop::{ControlFlow, EventLoop},
+    keyboard::{Key, NamedKey},
     window::Window,
 };
+mod aov;
+mod camera;
+mod distribution;
+mod environment;
+mod exposure;
+mod film;
+mod headless;
+mod integrator;
+mod kdtree;
+mod light;
+mod material;
+mod object;
+mod render;
+mod scene;
+mod scene_file;
+mod sky;
+mod texture;
+mod tonemap;
 mod winit_app;
-// use rayon::{
-//     iter::{IndexedParallelIterator, ParallelIterator},
-//     slice::ParallelSliceMut,
-// };
 use std::{
-    f32::consts::PI,
     num::NonZeroU32,
-    ops::{Add, Mul, RangeBounds},
+    ops::{Add, Mul},
+    path::PathBuf,
     rc::Rc,
-    time::{Duration, Instant},
+    str::FromStr,
+    sync::Arc,
+    time::Instant,
 };
 
-use renderer_types::{color::Color, prelude::*};
+use aov::Aovs;
+use camera::Camera;
+use environment::{Environment, EnvironmentMap};
+use exposure::{AutoExposure, LuminanceHistogram};
+use film::SplatFilm;
+use headless::RenderSettings;
+use integrator::IntegratorKind;
+use renderer_types::{
+    image::{HdrFormat, ImageFormat},
+    lut::Lut3d,
+    prelude::*,
+};
+use scene::Scene;
+use texture::Texture;
+use tonemap::ToneMapping;
 
+/// Blends linearly, which is right for light and material parameters. Gradients meant to look
+/// evenly spaced should use [`Color::lerp_oklab`] instead
 fn lerp<B, T: Float>(start: B, end: B, factor: T) -> B
 where
     B: Mul<T, Output = B> + Add<B, Output = B>,
@@ -31,171 +60,265 @@ where
     start * (T::one() - factor) + (end * factor)
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, Default)]
-pub struct Hit {
-    pub point: Vec3f,
-    pub normal: Vec3f,
-    pub t: f32,
-    pub front_face: bool,
+/// Options given on the command line
+struct Args {
+    /// Read from a scene file, the demo if `None`
+    scene: Option<Scene>,
+    integrator: IntegratorKind,
+    /// Grade applied to the image, a `.cube` file
+    lut: Option<Lut3d>,
+    /// Lights the demo instead of the sky, a `.hdr` or `.pfm` file
+    environment: Option<EnvironmentMap>,
+    /// Wrapped around a ball of the demo scene
+    texture: Option<Texture>,
+    /// Threads to render with, as many as there are cores if `None`
+    threads: Option<usize>,
+    /// Bounces a path makes at most, in the window as well as headless
+    max_depth: u32,
+    /// Renders to a file instead of opening a window
+    headless: Option<RenderSettings>,
 }
 
-impl Hit {
-    pub fn new(point: Vec3f, t: f32, ray: &Ray3f, outward_normal: Vec3f) -> Self {
-        let mut h = Self {
-            point,
-            t,
-            ..Default::default()
-        };
-        h.set_normal(ray, outward_normal);
-        h
-    }
-    /// Sets the hit record normal vector.
-    /// NOTE: the parameter `outward_normal` is assumed to have unit length.
-    pub fn set_normal(&mut self, ray: &Ray3f, outward_normal: Vec3f) {
-        self.front_face = ray.direction().dot(outward_normal) < 0.;
-        self.normal = self
-            .front_face
-            .then_some(outward_normal)
-            .unwrap_or_else(|| -outward_normal)
-    }
-}
+const USAGE: &str = "\
+Usage: renderer [demo | <scene file>] [options]
+       renderer render [demo | <scene file>] [options] [render options]
 
-pub trait Object {
-    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Option<Hit>;
-}
+Without a scene file the built-in demo is shown. A scene file holds one statement per line:
+  sky <elevation> <azimuth> <turbidity>
+  environment <file.hdr|file.pfm> [rotation]
+  material <name> lambertian <r> <g> <b> | lambertian <image file>
+  material <name> mirror <r> <g> <b> | glass <ior|bk7|diamond> [<r> <g> <b>]
+  material <name> pbr <r> <g> <b> <metallic> <roughness>
+  sphere <x> <y> <z> <radius> [material]
+  lamp <x> <y> <z> <kelvin> <lumens>
+  focus <x> <y> <z> <radius>
+with angles in degrees and # starting a comment.
 
-#[derive(Debug, PartialEq, Clone, Copy)]
-struct Sphere {
-    center: Vec3f,
-    radius: f32,
-}
+Options:
+  -i, --integrator <name>   path (default), bdpt, photon, spectral, whitted, ao, depth
+                            or normals
+  --max-depth <bounces>     longest path, 8 by default
+  --lut <file>              grades the image with a .cube file
+  --env <file>              lights the demo with a .hdr or .pfm instead of the sky
+  --env-rotation <degrees>  turns the demo's environment around the vertical
+  --texture <file>          wraps an image around a ball of the demo
+  --threads <count>         as many as there are cores by default
 
-impl Sphere {
-    pub fn new(center: Vec3f, radius: f32) -> Self {
-        Self {
-            center,
-            radius: radius.max(0.0),
-        }
-    }
+Render options:
+  --width <pixels>, --height <pixels>, --spp <samples>, --seed <seed>
+  -o, --output <file>       .exr, .hdr, .pfm or any image format, render.png by default";
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1)
 }
 
-impl Object for Sphere {
-    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32>) -> Option<Hit> {
-        let oc = self.center - *ray.origin();
-        let a = ray.direction().len_squared();
-        let h = ray.direction().dot(oc);
-        let c = oc.len_squared() - self.radius * self.radius;
+/// Fails with the usage, for arguments that don't make sense
+fn usage_error(message: String) -> ! {
+    fail(format!("{message}\n\n{USAGE}"))
+}
 
-        let discriminant = h * h - a * c;
-        if discriminant < 0. {
-            return None;
-        }
-        let sqrtd = discriminant.sqrt();
-        // Find the nearest root that lies in the acceptable range.
-        let mut root = (h - sqrtd) / a;
+/// Parses the value following `flag`
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    let value = args.next().unwrap_or_default();
+    value
+        .parse()
+        .unwrap_or_else(|_| fail(format!("Invalid {flag} {value:?}")))
+}
 
-        if !t_range.contains(&root) {
-            root = (h + sqrtd) / a;
-            if !t_range.contains(&root) {
-                return None;
+/// Parses `--integrator <name>`, `--lut <file>`, `--env <file>`, `--env-rotation <degrees>`,
+/// `--texture <file>` and `--threads <count>` from the command line, defaulting to the path
+/// tracer under the sky without grading.
+///
+/// A scene file given before or among the options replaces the built-in `demo`, which `--env`,
+/// `--env-rotation` and `--texture` only apply to.
+///
+/// `render --width <pixels> --height <pixels> --spp <samples> --seed <seed> -o <file>` renders
+/// to a file instead of opening a window. `--max-depth <bounces>` applies to both
+fn parse_args() -> Args {
+    let mut args = std::env::args().skip(1).peekable();
+    let headless = args.next_if_eq("render").is_some();
+    let mut parsed = Args {
+        scene: None,
+        integrator: IntegratorKind::PathTracer,
+        lut: None,
+        environment: None,
+        texture: None,
+        threads: None,
+        max_depth: RenderSettings::default().max_depth,
+        headless: None,
+    };
+    let mut settings = RenderSettings::default();
+    let mut env_rotation = 0.0;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                std::process::exit(0)
+            }
+            "--threads" => parsed.threads = Some(value(&mut args, &arg)),
+            "--max-depth" => parsed.max_depth = value(&mut args, &arg),
+            "--width" if headless => settings.width = value(&mut args, &arg),
+            "--height" if headless => settings.height = value(&mut args, &arg),
+            "--spp" if headless => settings.samples_per_pixel = value(&mut args, &arg),
+            "--seed" if headless => settings.seed = value(&mut args, &arg),
+            "--output" | "-o" if headless => {
+                settings.output = PathBuf::from(args.next().unwrap_or_default())
+            }
+            "demo" => {}
+            path if !path.starts_with('-') => {
+                let scene = Scene::open(path)
+                    .unwrap_or_else(|err| fail(format!("Can't load {path:?}: {err}")));
+                parsed.scene = Some(scene);
+            }
+            "--integrator" | "-i" => {
+                let name = args.next().unwrap_or_default();
+                parsed.integrator = name.parse().unwrap_or_else(|err| fail(err));
+            }
+            "--lut" => {
+                let path = args.next().unwrap_or_default();
+                let text = std::fs::read_to_string(&path)
+                    .unwrap_or_else(|err| fail(format!("Can't read {path:?}: {err}")));
+                let lut = Lut3d::parse_cube(&text)
+                    .unwrap_or_else(|err| fail(format!("Can't parse {path:?}: {err}")));
+                parsed.lut = Some(lut);
+            }
+            "--env" => {
+                let path = args.next().unwrap_or_default();
+                let image = Buffer::open_hdr(&path)
+                    .unwrap_or_else(|err| fail(format!("Can't read {path:?}: {err}")));
+                if image.width() == 0 || image.height() == 0 {
+                    fail(format!("{path:?} is empty"));
+                }
+                let map = EnvironmentMap::new(image);
+                let Vec2 { x, y } = map.dimensions();
+                println!("Loaded a {x}x{y} environment map from {path:?}");
+                parsed.environment = Some(map);
+            }
+            "--texture" => {
+                let path = args.next().unwrap_or_default();
+                let texture = Texture::open(&path)
+                    .unwrap_or_else(|err| fail(format!("Can't read {path:?}: {err}")));
+                parsed.texture = Some(texture);
             }
+            "--env-rotation" => {
+                let degrees = args.next().unwrap_or_default();
+                env_rotation = degrees
+                    .parse::<f32>()
+                    .unwrap_or_else(|_| fail(format!("Invalid rotation {degrees:?}")))
+                    .to_radians();
+            }
+            _ => usage_error(format!("Unknown argument {arg:?}")),
         }
-
-        let point = ray.at(root);
-        Some(Hit::new(
-            point,
-            root,
-            ray,
-            (point - self.center) / self.radius,
-        ))
     }
-}
-
-impl<T: Object> Object for [T] {
-    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Option<Hit> {
-        self.iter()
-            .flat_map(|o| o.hit(ray, t_range.clone()))
-            .min_by(|a, b| a.t.total_cmp(&b.t))
+    if parsed.scene.is_some()
+        && (parsed.environment.is_some() || parsed.texture.is_some() || env_rotation != 0.0)
+    {
+        usage_error("--env, --env-rotation and --texture only apply to the demo".to_owned());
     }
-}
-
-fn ray_color(ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Colorf32 {
-    fn environment(ray: &Ray3f) -> Color<f32> {
-        let a = ray.direction().unit().z.mul_add(0.5, 0.5);
-        // if a < 0.5 {
-        //     return Color::new(0.1, 0.1, 0.1);
-        // }
-        lerp(Color::new(1.0, 1.0, 1.0) * 0.0, SHADE, a)
-        // Color::from(*ray.direction())
+    parsed.environment = parsed
+        .environment
+        .map(|map| map.with_rotation(env_rotation));
+    if headless {
+        if settings.width == 0 || settings.height == 0 || settings.samples_per_pixel == 0 {
+            fail("The size and samples per pixel can't be 0".to_owned());
+        }
+        // Find out before rendering rather than after
+        let output = &settings.output;
+        if !settings.exr()
+            && ImageFormat::from_path(output).is_none()
+            && HdrFormat::from_path(output).is_none()
+        {
+            fail(format!("Can't tell which format to write {output:?} in"));
+        }
+        settings.max_depth = parsed.max_depth;
+        parsed.headless = Some(settings);
     }
-
-    const ILLUMINATED: Color<f32> = Color::new(0.953125, 0.910156, 0.605469);
-    const SHADE: Color<f32> = Color::new(0.529, 0.808, 0.922);
-    const SHADE_FACTOR: f32 = 0.0;
-    // let sun_ray = vec3f(2, 2, 8).to(vec3f(0, 0, 0)).direction().unit();
-    //
-    // // let sphere = Sphere::new(vec3f(0, 3, 0), 0.5);
-    // // Rgba::white()
-    // // // lerp(Color::black(), Color::white(), ray.at(1.).z * 0.5 + 0.5).into_rgba()
-    let objects = [
-        Sphere::new(vec3f(0, 3, 0), 0.4),
-        Sphere::new(vec3f(0.2, 1, 0), 0.1),
-    ];
-    // // let mut rec = HitRecord::default();
-    if let Some(hit) = objects.hit(ray, t_range.clone()) {
-        // let normal = hit.normal;
-        //     // let sun_angle = angle_between(&normal, &sun_ray);
-        //     // let factor = ((sun_angle / PI - SHADE_FACTOR) * (1. / (1. - SHADE_FACTOR))).clamp(0.0, 1.0);
-        //
-        //     let reflected = ray.direction().reflect(normal);
-        //     let reflected_ray = Ray3f::new(hit.point, reflected);
-        //     let fresnel = normal.angle_to(ray.direction()).mul_add(-2. / PI, 2.);
-        //
-        //     let external = ray_color(ray, t_range.clone());
-        //     let sun_angle = normal.angle_to(&sun_ray);
-        //     let factor = ((sun_angle / PI - SHADE_FACTOR) * (1. / (1. - SHADE_FACTOR))).clamp(0.0, 1.0);
-        //     // let color = lerp(SHADE * SHADE_FACTOR, ILLUMINATED, factor);
-        //     // let color = Color::black();
-        //     // let color = Color::white() * 0.4;
-        //     // let color = lerp(Color::white() * 0.2, external, lerp(0.4, fresnel, fresnel));
-        //
-        //     // let color = Color::splat((Color::splat(fresnel) > Color::splat(0.1)) as u8 as f32);
-        //     let color = external;
-        //     return color;
-        //     // return Color::from(hit.normal * -1.).into_rgba();
-        return Color::from(hit.normal.zyx().map(|n| *n += 1.) * 0.5);
-    };
-    environment(ray)
-    // Rgba::black()
+    parsed
 }
 
 fn main() {
-    rayon::ThreadPoolBuilder::new()
-        // .num_threads(8)
-        .build_global()
-        .unwrap();
     const WIDTH: usize = 1024;
     const HEIGHT: usize = 512;
     const DYNAMIC_SIZE: bool = true;
     const WIN_WIDTH: usize = 1024;
     const WIN_HEIGHT: usize = 512;
+    /// Stop refining the image after this many samples per pixel
+    const MAX_PASSES: u32 = 4096;
+
+    let args = parse_args();
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = args.threads {
+        pool = pool.num_threads(threads);
+    }
+    pool.build_global().unwrap();
+    let mut scene = match (args.scene, args.environment) {
+        (Some(scene), _) => scene,
+        (None, Some(map)) => Scene::demo_with_environment(map, args.texture),
+        (None, None) => Scene::demo(args.texture),
+    };
+    let lut = args.lut.map(Arc::new);
+    let mut tone_mapping = ToneMapping {
+        lut: lut.clone(),
+        ..ToneMapping::default()
+    };
+    if let Some(settings) = args.headless {
+        headless::render(&scene, args.integrator, tone_mapping, &settings)
+            .unwrap_or_else(|err| fail(format!("Can't save {:?}: {err}", settings.output)));
+        return;
+    }
+
+    let camera = Camera::default();
+    let mut kind = args.integrator;
+    let mut integrator = kind.build(args.max_depth);
+    integrator.preprocess(&scene);
+    let mut buf = Buffer::new(WIDTH, HEIGHT, Rgba::black());
+    let mut accum = Buffer::new(WIDTH, HEIGHT, Color::black());
+    let mut film = SplatFilm::new(WIDTH, HEIGHT);
+    let mut passes = 0;
+    let mut snapshots = 0;
+    // With auto-exposure on, the manual exposure compensates on top of it
+    let mut auto_exposure = Some(AutoExposure::default());
+    let mut last_frame = Instant::now();
+    let title = |kind: IntegratorKind, tone_mapping: &ToneMapping, auto: bool| {
+        format!(
+            "renderer - {kind} - {} {:+.1} EV{}{}",
+            tone_mapping.mapper,
+            tone_mapping.exposure,
+            if auto { " auto" } else { "" },
+            if tone_mapping.lut.is_some() {
+                " lut"
+            } else {
+                ""
+            }
+        )
+    };
+
+    let initial_title = title(kind, &tone_mapping, auto_exposure.is_some());
 
-    let mut app = winit_app::WinitAppBuilder::with_init(
-        |event_loop| {
+    let event_loop = EventLoop::new().unwrap();
+    let app = winit_app::WinitAppBuilder::with_init(
+        move |event_loop| {
             let window = event_loop
-                .create_window(Window::default_attributes())
+                .create_window(
+                    Window::default_attributes()
+                        .with_title(initial_title.clone())
+                        .with_inner_size(PhysicalSize::new(WIN_WIDTH as u32, WIN_HEIGHT as u32)),
+                )
                 .unwrap();
             Rc::new(window)
         },
-        |event_loop, win| {
+        |_event_loop, win| {
             let context = softbuffer::Context::new(win.clone()).unwrap();
             softbuffer::Surface::new(&context, win.clone()).unwrap()
         },
     )
-    .with_event_handler(|window, surface, event, event_loop| {
-        event_loop.set_control_flow(winit::event_loop::ControlFlow::Wait);
+    .with_event_handler(move |window, surface, event, event_loop| {
+        event_loop.set_control_flow(ControlFlow::Wait);
 
-        let surface = surface.unwrap();
+        let Some(surface) = surface else {
+            return;
+        };
 
         match event {
             Event::WindowEvent {
@@ -203,102 +326,170 @@ fn main() {
                 event: WindowEvent::RedrawRequested,
             } if window_id == window.id() => {
                 let PhysicalSize { width, height } = window.inner_size();
-                surface
-                    .resize(
-                        NonZeroU32::new(width).unwrap(),
-                        NonZeroU32::new(height).unwrap(),
-                    )
-                    .unwrap();
-
-                let mut buf = surface.buffer_mut().unwrap();
-                let pixels = buf
-                    .chunks_exact_mut(width as usize)
-                    .enumerate()
-                    .flat_map(|(y, row)| row.iter_mut().enumerate().map(move |(x, p)| (x, y, p)));
-
-                let aspect = width as f32 / height as f32;
-                let viewport = {
-                    let height = 0.5;
-                    vec2f(aspect * height, height)
+                let (Some(nz_width), Some(nz_height)) =
+                    (NonZeroU32::new(width), NonZeroU32::new(height))
+                else {
+                    return;
                 };
-                let focal_len: f32 = 1.0;
+                surface.resize(nz_width, nz_height).unwrap();
+                let (width, height) = (width as usize, height as usize);
+                if DYNAMIC_SIZE && buf.dimensions() != Vec2::new(width, height) {
+                    buf.resize(width, height);
+                    accum = Buffer::new(width, height, Color::black());
+                    film = SplatFilm::new(width, height);
+                    passes = 0;
+                }
 
-                let camera_origin = vec3f(0, 0, 0);
+                let start = Instant::now();
+                if passes < MAX_PASSES {
+                    render::render_pass(
+                        &scene,
+                        integrator.as_ref(),
+                        &camera,
+                        &mut accum,
+                        &film,
+                        passes as u64,
+                    );
+                    passes += 1;
+                }
+                let scale = 1.0 / passes as f32;
+                let mut developed = tone_mapping.clone();
+                let mut adapting = false;
+                if let Some(auto) = &mut auto_exposure {
+                    let histogram = LuminanceHistogram::new(&accum, scale);
+                    developed.exposure += auto.update(&histogram, last_frame.elapsed());
+                    adapting = !auto.settled(&histogram, 0.01);
+                }
+                last_frame = Instant::now();
+                developed.develop(&accum, scale, &mut buf);
+                let took = start.elapsed();
 
-                for (x, y, pixel) in pixels {}
+                let mut frame = surface.buffer_mut().unwrap();
+                // Nearest-neighbour scale the render to the window
+                frame
+                    .chunks_exact_mut(width)
+                    .enumerate()
+                    .for_each(|(y, row)| {
+                        let src_y = y * buf.height() / height;
+                        row.iter_mut().enumerate().for_each(|(x, p)| {
+                            let src_x = x * buf.width() / width;
+                            *p = buf.as_rgba()[src_y * buf.width() + src_x];
+                        })
+                    });
+                frame.present().unwrap();
+                println!(
+                    "Rendering took {took:?}, Would allow for {:.1}fps",
+                    1.0 / took.as_secs_f64()
+                );
+                // Keep drawing while the exposure adapts, even once the image is done
+                if passes < MAX_PASSES || adapting {
+                    window.request_redraw();
+                }
             }
+            Event::WindowEvent {
+                window_id,
+                event:
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key,
+                                state: ElementState::Pressed,
+                                ..
+                            },
+                        ..
+                    },
+            } if window_id == window.id() => {
+                // E saves the image so far along with the passes compositors use
+                if let Key::Character("e") = logical_key.as_ref() {
+                    let scale = 1.0 / passes.max(1) as f32;
+                    let beauty = accum.map(|&c| c * scale);
+                    let aovs = Aovs::render(&scene, &camera, accum.width(), accum.height());
+                    match aovs.to_exr(&beauty).save("render.exr") {
+                        Ok(()) => println!("Saved render.exr"),
+                        Err(err) => eprintln!("Can't save render.exr: {err}"),
+                    }
+                    return;
+                }
+                // S dumps what's on screen, quicker to write than any other format
+                if let Key::Character("s") = logical_key.as_ref() {
+                    snapshots += 1;
+                    let path = format!("snapshot-{snapshots}.qoi");
+                    match buf.save(&path) {
+                        Ok(()) => println!("Saved {path}"),
+                        Err(err) => eprintln!("Can't save {path}: {err}"),
+                    }
+                    return;
+                }
+                // T cycles through the tone mappers, + and - change the exposure by half a stop, A
+                // toggles auto-exposure and L the LUT. None of them need rendering again
+                let mut tone_mapped = true;
+                match logical_key.as_ref() {
+                    Key::Character("t") => tone_mapping.mapper = tone_mapping.mapper.next(),
+                    Key::Character("+" | "=") => tone_mapping.exposure += 0.5,
+                    Key::Character("-") => tone_mapping.exposure -= 0.5,
+                    Key::Character("l") => {
+                        tone_mapping.lut = match tone_mapping.lut {
+                            Some(_) => None,
+                            None => lut.clone(),
+                        }
+                    }
+                    Key::Character("a") => {
+                        auto_exposure = match auto_exposure {
+                            Some(_) => None,
+                            None => Some(AutoExposure::default()),
+                        }
+                    }
+                    _ => tone_mapped = false,
+                }
+                if tone_mapped {
+                    window.set_title(&title(kind, &tone_mapping, auto_exposure.is_some()));
+                    window.request_redraw();
+                    return;
+                }
+                // Tab cycles through the integrators, the number keys pick one directly
+                let selected = match logical_key.as_ref() {
+                    Key::Named(NamedKey::Tab) => Some(kind.next()),
+                    Key::Character(c) => c
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| IntegratorKind::ALL.get(n.checked_sub(1)?).copied()),
+                    _ => None,
+                };
+                // [ and ] turn the environment map by 15 degrees
+                let turn = match logical_key.as_ref() {
+                    Key::Character("[") => -15f32.to_radians(),
+                    Key::Character("]") => 15f32.to_radians(),
+                    _ => 0.0,
+                };
+                if let Environment::Map(map) = &mut scene.environment {
+                    if turn != 0.0 {
+                        map.set_rotation(map.rotation() + turn);
+                        if integrator.preprocess_uses_environment() {
+                            integrator.preprocess(&scene);
+                        }
+                        accum.fill(Color::black());
+                        passes = 0;
+                        window.request_redraw();
+                        return;
+                    }
+                }
+                if let Some(selected) = selected.filter(|&s| s != kind) {
+                    kind = selected;
+                    integrator = kind.build(args.max_depth);
+                    integrator.preprocess(&scene);
+                    accum.fill(Color::black());
+                    passes = 0;
+                    window.set_title(&title(kind, &tone_mapping, auto_exposure.is_some()));
+                    window.request_redraw();
+                }
+            }
+            Event::WindowEvent {
+                window_id,
+                event: WindowEvent::CloseRequested,
+            } if window_id == window.id() => event_loop.exit(),
             _ => (),
         }
-        todo!();
     });
 
-    // while window.is_open() && !window.is_key_down(Key::Escape) {
-    //     if DYNAMIC_SIZE {
-    //         let (width, height) = window.get_size();
-    //         let (width, height) = (
-    //             width / scale_to_int(SCALE) as usize,
-    //             height / scale_to_int(SCALE) as usize,
-    //         );
-    //         buf.resize(width, height);
-    //     }
-    //
-    //     let camera_origin = 'cam: {
-    //         let Some(mut pos) = window
-    //             .get_mouse_pos(minifb::MouseMode::Discard)
-    //             .map(<(f32, f32) as Into<Vec2<f32>>>::into)
-    //         else {
-    //             break 'cam vec3f(0, 0, 0);
-    //         };
-    //         pos.x /= buf.width() as f32;
-    //         pos.y /= buf.height() as f32;
-    //         pos -= vec2f(0.5, 0.5);
-    //         pos.x *= -2.0;
-    //         pos.y *= 2.0;
-    //         vec3f(pos.x, 0, pos.y)
-    //         // vec3f(0, 0, 0)
-    //     };
-    //     let viewport_pixel_pos = {
-    //         let delta = viewport.x / buf.width() as f32;
-    //         let origin = vec3f(-viewport.x / 2.0, focal_len, viewport.y / 2.0) + camera_origin;
-    //         move |x: usize, y: usize| origin + vec3f(delta * x as f32, 0, -delta * y as f32)
-    //     };
-    //     let dim = buf.dimensions();
-    //     let start = Instant::now();
-    //     buf.inner_buf_mut()
-    //         .par_chunks_exact_mut(dim.x)
-    //         .enumerate()
-    //         // Run scanlines in parallel
-    //         .for_each(|(y, line)| {
-    //             line.iter_mut().enumerate().for_each(move |(x, p)| {
-    //                 let ray_target = viewport_pixel_pos(x, y);
-    //                 let ray = camera_origin.to(ray_target);
-    //                 *p = ray_color(&ray, ..1000.).into_rgba();
-    //             });
-    //         });
-    //     // // Run pixels in parallel
-    //     // .flat_map(|(y, line)| line.par_iter_mut().enumerate().map(move |(x, p)| (x, y, p)))
-    //     // .for_each(|(x, y, p)| {
-    //     //     let ray_target = viewport_pixel_pos(x, y);
-    //     //     let ray = camera_origin.to(ray_target);
-    //     //     *p = ray_color(&ray);
-    //     // });
-    //
-    //     // Run pixels on one thread
-    //     // buf.iter_pos_mut().for_each(|(x, y, p)| {
-    //     //     let ray_target = viewport_pixel_pos(x, y);
-    //     //     let ray = camera_origin.to(ray_target);
-    //     //     // *p = Color::splat(ray.direction().z * 0.5 + 0.5).into_rgba()
-    //     //     *p = ray_color(&ray, ..1000.).into_rgba();
-    //     // });
-    //
-    //     let took = start.elapsed();
-    //
-    //     window
-    //         .update_with_buffer(buf.as_rgba(), buf.width(), buf.height())
-    //         .unwrap();
-    //     println!(
-    //         "Rendering took {took:?}, Would allow for {:.1}fps",
-    //         1.0 / took.as_secs_f64()
-    //     );
-    // }
+    winit_app::run_app(event_loop, app);
 }
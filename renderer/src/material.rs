@@ -0,0 +1,465 @@
+use std::f32::consts::{FRAC_1_PI, PI, TAU};
+
+use renderer_types::{
+    prelude::*,
+    sampling::{cosine_hemisphere_pdf, sample_cosine_hemisphere, Onb},
+};
+
+use crate::{lerp, object::Hit, texture::Texture};
+
+/// How light scatters off a surface.
+///
+/// Directions passed to and returned from the scattering functions are unit vectors pointing away
+/// from the surface
+#[derive(Clone)]
+pub enum Material {
+    /// Ideal diffuse reflector
+    Lambertian { albedo: Texture },
+    /// Ideal specular reflector
+    Mirror { tint: Colorf32 },
+    /// Smooth boundary between two transparent media, like glass or water.
+    ///
+    /// `ior` is the index of refraction of the inside relative to the outside, transmitted light
+    /// is multiplied by `tint`
+    Dielectric { ior: Ior, tint: Colorf32 },
+    /// Cook-Torrance microfacet model
+    Pbr(PbrMaterial),
+    /// Another material, shaded with a perturbed normal
+    Detailed {
+        base: Box<Material>,
+        detail: NormalPerturbation,
+    },
+}
+
+/// Index of refraction, which may depend on the wavelength of the light.
+///
+/// Wavelengths are in micrometers in the dispersion formulas, as they're usually quoted that way
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ior {
+    Constant(f32),
+    /// Cauchy's equation, n = a + b / λ²
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// Sellmeier equation, n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Wavelength indices of refraction are usually given for, the helium d line, in nanometers
+    pub const D_LINE: f32 = 587.56;
+    /// Schott N-BK7, the most common optical glass
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    /// Diamond, which disperses light much more than glass
+    pub const DIAMOND: Self = Self::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    /// The index of refraction for light of `lambda` nanometers
+    pub fn at(&self, lambda: f32) -> f32 {
+        let um = lambda * 1e-3;
+        match *self {
+            Self::Constant(ior) => ior,
+            Self::Cauchy { a, b } => a + b / (um * um),
+            Self::Sellmeier { b, c } => {
+                let l2 = um * um;
+                let sum: f32 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+    /// The index of refraction at [`Ior::D_LINE`], for rendering without wavelengths
+    pub fn nominal(&self) -> f32 {
+        self.at(Self::D_LINE)
+    }
+    /// Whether the index of refraction depends on the wavelength
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Self::Constant(_))
+    }
+}
+
+impl From<f32> for Ior {
+    fn from(value: f32) -> Self {
+        Self::Constant(value)
+    }
+}
+
+/// Surface detail that is faked by changing the shading normal instead of adding geometry
+#[derive(Clone)]
+pub enum NormalPerturbation {
+    /// Tangent-space normal map, with +y pointing towards increasing v (the OpenGL convention)
+    NormalMap(Texture),
+    /// Height field, read from the luminance of the texture. `scale` controls how pronounced
+    /// the bumps are
+    Bump { height: Texture, scale: f32 },
+}
+
+impl NormalPerturbation {
+    /// Replaces the normal of `hit` with the perturbed shading normal
+    pub fn apply(&self, hit: &mut Hit) {
+        let normal = hit.normal;
+        // Gram-Schmidt, in case the tangent isn't quite perpendicular to the normal
+        let mut tangent = (hit.tangent - normal * hit.tangent.dot(normal)).unit();
+        if tangent.len_squared() < 0.5 {
+            tangent = Onb::from_w(normal).u;
+        }
+        let bitangent = normal.cross(tangent);
+        let perturbed = match self {
+            NormalPerturbation::NormalMap(texture) => {
+                let Color { r, g, b } = texture.sample(hit.uv) * 2.0 - Color::white();
+                tangent * r + bitangent * g + normal * b
+            }
+            NormalPerturbation::Bump { height, scale } => {
+                let height_at = |uv: Vec2f| height.sample(uv).luminance();
+                let step = height.texel_size();
+                let h = height_at(hit.uv);
+                let dhdu = (height_at(hit.uv + vec2f(step.x, 0)) - h) / step.x;
+                let dhdv = (height_at(hit.uv + vec2f(0, step.y)) - h) / step.y;
+                normal - (tangent * dhdu + bitangent * dhdv) * *scale
+            }
+        }
+        .unit();
+        if perturbed.len_squared() > 0.0 {
+            hit.normal = perturbed;
+            hit.tangent = (tangent - perturbed * tangent.dot(perturbed)).unit();
+        }
+    }
+}
+
+/// Metallic-roughness parameterisation, as used by glTF 2.0.
+///
+/// Uses the GGX (Trowbridge-Reitz) microfacet distribution, height-correlated Smith
+/// masking-shadowing and the Schlick approximation of the Fresnel term
+#[derive(Clone)]
+pub struct PbrMaterial {
+    pub base_color: Texture,
+    /// Roughness is read from the green channel and metalness from the blue channel
+    pub metallic_roughness: Texture,
+    pub metallic: f32,
+    pub roughness: f32,
+}
+
+/// A scattered direction chosen by [`Material::sample`]
+#[derive(Debug, Clone, Copy)]
+pub struct BsdfSample {
+    pub direction: Vec3f,
+    /// BSDF times cosine, divided by the pdf
+    pub weight: Colorf32,
+    /// Probability density with respect to solid angle, meaningless for specular samples
+    pub pdf: f32,
+    /// Whether the direction was chosen from a delta distribution
+    pub specular: bool,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: impl Into<Texture>, metallic: f32, roughness: f32) -> Self {
+        Self {
+            base_color: base_color.into(),
+            metallic_roughness: Texture::Constant(Color::white()),
+            metallic,
+            roughness,
+        }
+    }
+    /// The texture is read as linear data, even if made from an sRGB image
+    pub fn with_metallic_roughness(mut self, texture: impl Into<Texture>) -> Self {
+        self.metallic_roughness = texture.into().into_data();
+        self
+    }
+    /// Resolves the textures at the hit point
+    fn params(&self, uv: Vec2f) -> GgxParams {
+        let base = self.base_color.sample(uv);
+        let packed = self.metallic_roughness.sample(uv);
+        let metallic = (self.metallic * packed.b).clamp(0.0, 1.0);
+        let roughness = (self.roughness * packed.g).clamp(0.0, 1.0);
+        GgxParams {
+            diffuse: lerp(base, Color::black(), metallic),
+            f0: lerp(Color::splat(0.04), base, metallic),
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+}
+
+impl Material {
+    pub fn lambertian(albedo: impl Into<Texture>) -> Self {
+        Material::Lambertian {
+            albedo: albedo.into(),
+        }
+    }
+    /// The normal map is read as linear data, even if made from an sRGB image
+    pub fn with_normal_map(self, normal_map: impl Into<Texture>) -> Self {
+        Material::Detailed {
+            base: Box::new(self),
+            detail: NormalPerturbation::NormalMap(normal_map.into().into_data()),
+        }
+    }
+    /// The height map is read as linear data, even if made from an sRGB image
+    pub fn with_bump_map(self, height: impl Into<Texture>, scale: f32) -> Self {
+        Material::Detailed {
+            base: Box::new(self),
+            detail: NormalPerturbation::Bump {
+                height: height.into().into_data(),
+                scale,
+            },
+        }
+    }
+    /// The material underneath any surface detail
+    pub fn base(&self) -> &Material {
+        match self {
+            Material::Detailed { base, .. } => base.base(),
+            material => material,
+        }
+    }
+    /// Overall color of the surface at `uv`, before any lighting, for the albedo pass
+    pub fn albedo(&self, uv: Vec2f) -> Colorf32 {
+        match self {
+            Material::Lambertian { albedo } => albedo.sample(uv),
+            Material::Mirror { tint } | Material::Dielectric { tint, .. } => *tint,
+            Material::Pbr(pbr) => pbr.base_color.sample(uv),
+            Material::Detailed { base, .. } => base.albedo(uv),
+        }
+    }
+    /// Returns `hit` with the normal this material shades with
+    pub fn shading_hit(&self, hit: &Hit) -> Hit {
+        let mut hit = *hit;
+        if let Material::Detailed { base, detail } = self {
+            hit = base.shading_hit(&hit);
+            detail.apply(&mut hit);
+        }
+        hit
+    }
+    /// BSDF times the cosine of the angle between `wi` and the shading normal
+    pub fn eval(&self, hit: &Hit, wo: Vec3f, wi: Vec3f) -> Colorf32 {
+        if let Material::Detailed { base, .. } = self {
+            return base.eval(&self.shading_hit(hit), wo, wi);
+        }
+        let frame = Onb::from_w(hit.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Color::black();
+        }
+        match self {
+            Material::Lambertian { albedo } => albedo.sample(hit.uv) * (wi.z * FRAC_1_PI),
+            Material::Mirror { .. } | Material::Dielectric { .. } => Color::black(),
+            Material::Pbr(pbr) => pbr.params(hit.uv).eval(wo, wi),
+            Material::Detailed { .. } => unreachable!(),
+        }
+    }
+    /// Density [`Material::sample`] would return for `wi`
+    pub fn pdf(&self, hit: &Hit, wo: Vec3f, wi: Vec3f) -> f32 {
+        if let Material::Detailed { base, .. } = self {
+            return base.pdf(&self.shading_hit(hit), wo, wi);
+        }
+        let frame = Onb::from_w(hit.normal);
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            Material::Lambertian { .. } => cosine_hemisphere_pdf(wi.z),
+            Material::Mirror { .. } | Material::Dielectric { .. } => 0.0,
+            Material::Pbr(pbr) => pbr.params(hit.uv).pdf(wo, wi),
+            Material::Detailed { .. } => unreachable!(),
+        }
+    }
+    /// Chooses an incoming direction for light leaving towards `wo`, `u` is a triple of uniform
+    /// samples in [0, 1)
+    pub fn sample(&self, hit: &Hit, wo: Vec3f, u: Vec3f) -> Option<BsdfSample> {
+        if let Material::Detailed { base, .. } = self {
+            return base.sample(&self.shading_hit(hit), wo, u);
+        }
+        let frame = Onb::from_w(hit.normal);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0.0 {
+            return None;
+        }
+        match self {
+            Material::Lambertian { albedo } => {
+                let wi = sample_cosine_hemisphere(vec2f(u.x, u.y));
+                Some(BsdfSample {
+                    direction: frame.to_world(wi),
+                    weight: albedo.sample(hit.uv),
+                    pdf: cosine_hemisphere_pdf(wi.z),
+                    specular: false,
+                })
+            }
+            Material::Mirror { tint } => Some(BsdfSample {
+                direction: -wo.reflect(hit.normal),
+                weight: *tint,
+                pdf: 1.0,
+                specular: true,
+            }),
+            &Material::Dielectric { ior, tint } => {
+                Some(sample_dielectric(hit, wo, ior.nominal(), tint, u.z))
+            }
+            Material::Pbr(pbr) => {
+                let params = pbr.params(hit.uv);
+                let wi = params.sample(wo_local, u)?;
+                let pdf = params.pdf(wo_local, wi);
+                if pdf <= 0.0 {
+                    return None;
+                }
+                Some(BsdfSample {
+                    direction: frame.to_world(wi),
+                    weight: params.eval(wo_local, wi) / pdf,
+                    pdf,
+                    specular: false,
+                })
+            }
+            Material::Detailed { .. } => unreachable!(),
+        }
+    }
+    /// Like [`Material::sample`], for light of a single wavelength of `lambda` nanometers, which
+    /// dispersive dielectrics refract by their index of refraction at that wavelength
+    pub fn sample_at(&self, hit: &Hit, wo: Vec3f, u: Vec3f, lambda: f32) -> Option<BsdfSample> {
+        match self {
+            Material::Detailed { base, .. } => {
+                base.sample_at(&self.shading_hit(hit), wo, u, lambda)
+            }
+            &Material::Dielectric { ior, tint } if wo.dot(hit.normal) > 0.0 => {
+                Some(sample_dielectric(hit, wo, ior.at(lambda), tint, u.z))
+            }
+            _ => self.sample(hit, wo, u),
+        }
+    }
+    /// Whether light of different wavelengths scatters in different directions
+    pub fn is_dispersive(&self) -> bool {
+        matches!(self.base(), Material::Dielectric { ior, .. } if ior.is_dispersive())
+    }
+}
+
+/// Reflects or refracts `wo` through a dielectric boundary, by the Fresnel reflectance and the
+/// uniform sample `u`
+fn sample_dielectric(hit: &Hit, wo: Vec3f, ior: f32, tint: Colorf32, u: f32) -> BsdfSample {
+    let eta = if hit.front_face { 1.0 / ior } else { ior };
+    let reflectance = fresnel_dielectric(wo.dot(hit.normal), eta);
+    let (direction, weight) = match refract(wo, hit.normal, eta) {
+        Some(refracted) if u >= reflectance => (refracted, tint),
+        _ => (-wo.reflect(hit.normal), Color::white()),
+    };
+    BsdfSample {
+        direction,
+        weight,
+        pdf: 1.0,
+        specular: true,
+    }
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric boundary.
+///
+/// `eta` is the ratio of the index of refraction on the incident side to the one on the
+/// transmitted side
+pub fn fresnel_dielectric(cos_incident: f32, eta: f32) -> f32 {
+    let cos_i = cos_incident.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let s = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let p = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (s * s + p * p) * 0.5
+}
+
+/// Refracts `wo` through a boundary with `normal` on its side, returns [None] on total internal
+/// reflection
+pub fn refract(wo: Vec3f, normal: Vec3f, eta: f32) -> Option<Vec3f> {
+    let cos_i = wo.dot(normal);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo * eta + normal * eta.mul_add(cos_i, -cos_t))
+}
+
+/// Textures resolved at a single point. All directions are in shading space
+struct GgxParams {
+    diffuse: Colorf32,
+    f0: Colorf32,
+    alpha: f32,
+}
+
+impl GgxParams {
+    fn d(&self, h: Vec3f) -> f32 {
+        let a2 = self.alpha * self.alpha;
+        let denom = (h.z * h.z).mul_add(a2 - 1.0, 1.0);
+        a2 / (PI * denom * denom)
+    }
+    fn lambda(&self, w: Vec3f) -> f32 {
+        let cos2 = w.z * w.z;
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((self.alpha * self.alpha).mul_add(tan2, 1.0).sqrt() - 1.0) * 0.5
+    }
+    fn g1(&self, w: Vec3f) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+    fn g2(&self, wo: Vec3f, wi: Vec3f) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+    fn fresnel(&self, cos: f32) -> Colorf32 {
+        let m = (1.0 - cos).clamp(0.0, 1.0);
+        let m5 = (m * m) * (m * m) * m;
+        self.f0 + (Color::white() - self.f0) * m5
+    }
+    /// Probability of picking the specular lobe over the diffuse one
+    fn specular_probability(&self, wo: Vec3f) -> f32 {
+        let specular = self.fresnel(wo.z).luminance();
+        let diffuse = self.diffuse.luminance() * (1.0 - specular);
+        if specular + diffuse <= 0.0 {
+            return 1.0;
+        }
+        (specular / (specular + diffuse)).clamp(0.1, 1.0)
+    }
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Colorf32 {
+        let h = (wo + wi).unit();
+        let fresnel = self.fresnel(wi.dot(h));
+        let specular = fresnel * (self.d(h) * self.g2(wo, wi) / (4.0 * wo.z));
+        let diffuse = (Color::white() - fresnel) * self.diffuse * (wi.z * FRAC_1_PI);
+        specular + diffuse
+    }
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> f32 {
+        let h = (wo + wi).unit();
+        let p = self.specular_probability(wo);
+        // Distribution of visible normals, transformed by the reflection jacobian
+        let specular = self.g1(wo) * self.d(h) / (4.0 * wo.z);
+        let diffuse = cosine_hemisphere_pdf(wi.z);
+        p.mul_add(specular, (1.0 - p) * diffuse)
+    }
+    fn sample(&self, wo: Vec3f, u: Vec3f) -> Option<Vec3f> {
+        let p = self.specular_probability(wo);
+        let wi = if u.z < p {
+            let h = self.sample_visible_normal(wo, vec2f(u.x, u.y));
+            -wo.reflect(h)
+        } else {
+            sample_cosine_hemisphere(vec2f(u.x, u.y))
+        };
+        (wi.z > 0.0).then_some(wi)
+    }
+    /// Heitz, "Sampling the GGX Distribution of Visible Normals"
+    fn sample_visible_normal(&self, wo: Vec3f, u: Vec2f) -> Vec3f {
+        let vh = vec3f(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit();
+        let len2 = vh.x.mul_add(vh.x, vh.y * vh.y);
+        let t1 = if len2 > 0.0 {
+            vec3f(-vh.y, vh.x, 0) / len2.sqrt()
+        } else {
+            vec3f(1, 0, 0)
+        };
+        let t2 = vh.cross(t1);
+        let r = u.x.sqrt();
+        let phi = TAU * u.y;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        vec3f(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(0.0)).unit()
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    f32::consts::{PI, TAU},
+    ops::RangeBounds,
+};
+
+use renderer_types::prelude::*;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Hit {
+    pub point: Vec3f,
+    pub normal: Vec3f,
+    /// Surface coordinates used for texture lookups
+    pub uv: Vec2f,
+    /// Unit vector along the direction of increasing u, perpendicular to the normal
+    pub tangent: Vec3f,
+    pub t: f32,
+    pub front_face: bool,
+    /// Index into [`Scene::materials`](crate::scene::Scene::materials)
+    pub material: usize,
+}
+
+impl Hit {
+    pub fn new(point: Vec3f, t: f32, ray: &Ray3f, outward_normal: Vec3f) -> Self {
+        let mut h = Self {
+            point,
+            t,
+            ..Default::default()
+        };
+        h.set_normal(ray, outward_normal);
+        h
+    }
+    /// Sets the hit record normal vector.
+    /// NOTE: the parameter `outward_normal` is assumed to have unit length.
+    pub fn set_normal(&mut self, ray: &Ray3f, outward_normal: Vec3f) {
+        self.front_face = ray.direction().dot(outward_normal) < 0.;
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+    }
+}
+
+pub trait Object {
+    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Option<Hit>;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sphere {
+    center: Vec3f,
+    radius: f32,
+    material: usize,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3f, radius: f32) -> Self {
+        Self {
+            center,
+            radius: radius.max(0.0),
+            material: 0,
+        }
+    }
+    pub fn with_material(mut self, material: usize) -> Self {
+        self.material = material;
+        self
+    }
+}
+
+/// Sphere enclosing some geometry
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BoundingSphere {
+    pub center: Vec3f,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// The sphere around the axis-aligned box enclosing all of `spheres`
+    pub fn around(spheres: &[Sphere]) -> Self {
+        if spheres.is_empty() {
+            return Self {
+                center: Vec3f::splat(0.0),
+                radius: 0.0,
+            };
+        }
+        let mut min = Vec3f::splat(f32::INFINITY);
+        let mut max = Vec3f::splat(f32::NEG_INFINITY);
+        for sphere in spheres {
+            let (lo, hi) = (
+                sphere.center - Vec3f::splat(sphere.radius),
+                sphere.center + Vec3f::splat(sphere.radius),
+            );
+            min = vec3f(min.x.min(lo.x), min.y.min(lo.y), min.z.min(lo.z));
+            max = vec3f(max.x.max(hi.x), max.y.max(hi.y), max.z.max(hi.z));
+        }
+        Self {
+            center: (min + max) / 2.0,
+            radius: (max - min).len() / 2.0,
+        }
+    }
+}
+
+impl Object for Sphere {
+    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32>) -> Option<Hit> {
+        let oc = self.center - *ray.origin();
+        let a = ray.direction().len_squared();
+        let h = ray.direction().dot(oc);
+        let c = oc.len_squared() - self.radius * self.radius;
+
+        let discriminant = h * h - a * c;
+        if discriminant < 0. {
+            return None;
+        }
+        let sqrtd = discriminant.sqrt();
+        // Find the nearest root that lies in the acceptable range.
+        let mut root = (h - sqrtd) / a;
+
+        if !t_range.contains(&root) {
+            root = (h + sqrtd) / a;
+            if !t_range.contains(&root) {
+                return None;
+            }
+        }
+
+        let point = ray.at(root);
+        let outward_normal = (point - self.center) / self.radius;
+        let mut hit = Hit::new(point, root, ray, outward_normal);
+        hit.uv = vec2f(
+            (outward_normal.y.atan2(outward_normal.x) / TAU).rem_euclid(1.0),
+            1.0 - outward_normal.z.clamp(-1.0, 1.0).acos() / PI,
+        );
+        // Zero at the poles, where u is undefined
+        hit.tangent = vec3f(-outward_normal.y, outward_normal.x, 0).unit();
+        hit.material = self.material;
+        Some(hit)
+    }
+}
+
+impl<T: Object> Object for [T] {
+    fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Option<Hit> {
+        self.iter()
+            .flat_map(|o| o.hit(ray, t_range.clone()))
+            .min_by(|a, b| a.t.total_cmp(&b.t))
+    }
+}
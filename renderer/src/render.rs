@@ -0,0 +1,36 @@
+use rayon::iter::ParallelIterator;
+use renderer_types::{prelude::*, sampling::Rng};
+
+use crate::{camera::Camera, film::SplatFilm, integrator::Integrator, scene::Scene};
+
+/// Adds one jittered sample per pixel to `accum`, which is as large as `film`.
+///
+/// `film` collects light integrators find for other pixels than the one they're sampling, and is
+/// added to `accum` once the pass is done.
+/// `seed` seeds the random numbers, so every pass over the same image should use a different one
+pub fn render_pass(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    camera: &Camera,
+    accum: &mut Buffer<Colorf32>,
+    film: &SplatFilm,
+    seed: u64,
+) {
+    let (width, height) = (film.width(), film.height());
+    // Tiles keep the rays of each thread close together, so they hit the same objects
+    accum.par_tiles_mut(32, 32).for_each(|mut tile| {
+        let origin = tile.origin();
+        tile.iter_pos_mut().for_each(|(x, y, p)| {
+            let (x, y) = (origin.x + x, origin.y + y);
+            let mut rng = Rng::new(seed, (y * width + x) as u64);
+            let jitter = rng.next_vec2f();
+            let ray = camera.ray(x as f32 + jitter.x, y as f32 + jitter.y, width, height);
+            let sample = integrator.radiance_splatting(&ray, scene, camera, film, &mut rng);
+            // A single NaN or infinity would otherwise poison the pixel for good
+            if [sample.r, sample.g, sample.b].iter().all(|c| c.is_finite()) {
+                *p += sample;
+            }
+        });
+    });
+    film.drain_into(accum);
+}
@@ -0,0 +1,153 @@
+use std::{f32::consts::TAU, ops::RangeBounds};
+
+use renderer_types::prelude::*;
+
+use crate::{
+    environment::{Environment, EnvironmentMap},
+    light::Light,
+    material::{Ior, Material, PbrMaterial},
+    object::{BoundingSphere, Hit, Object, Sphere},
+    sky::PhysicalSky,
+    texture::Texture,
+};
+
+pub struct Scene {
+    pub objects: Vec<Sphere>,
+    pub materials: Vec<Material>,
+    pub lights: Vec<Light>,
+    pub environment: Environment,
+    /// Region that light paths from directional lights are aimed at, all objects if `None`.
+    ///
+    /// Light paths can only reach what's inside it, so it should cover the part of the scene the
+    /// camera sees
+    pub focus: Option<BoundingSphere>,
+}
+
+impl Scene {
+    pub fn new(environment: Environment) -> Self {
+        Self {
+            objects: Vec::new(),
+            // Objects without an explicit material use this grey one
+            materials: vec![Material::lambertian(Color::splat(0.5))],
+            lights: Vec::new(),
+            environment,
+            focus: None,
+        }
+    }
+    /// Uses `sky` as the environment and adds its sun to the lights
+    pub fn with_sky(sky: PhysicalSky) -> Self {
+        let sun = sky.sun();
+        let mut scene = Self::new(Environment::Sky(sky));
+        scene.lights.push(sun);
+        scene
+    }
+    pub fn with_object(mut self, object: Sphere) -> Self {
+        self.objects.push(object);
+        self
+    }
+    /// Returns the index objects should use to refer to `material`
+    pub fn add_material(&mut self, material: Material) -> usize {
+        self.materials.push(material);
+        self.materials.len() - 1
+    }
+    /// The two spheres the renderer has always shown, joined by balls of glass, diamond, water
+    /// and mirror and a ground to stand on, under an afternoon sky.
+    ///
+    /// `texture` is wrapped around the brick ball, which is grey without one
+    pub fn demo(texture: Option<Texture>) -> Self {
+        Self::with_sky(PhysicalSky::new(
+            35f32.to_radians(),
+            200f32.to_radians(),
+            3.0,
+        ))
+        .with_demo_objects(texture)
+    }
+    /// [`Scene::demo`] lit by an environment map instead of the sky
+    pub fn demo_with_environment(map: EnvironmentMap, texture: Option<Texture>) -> Self {
+        Self::new(Environment::Map(map)).with_demo_objects(texture)
+    }
+    fn with_demo_objects(mut self, texture: Option<Texture>) -> Self {
+        // Polished bands across brushed gold, roughness being read from green
+        let bands = Buffer::new_with(1, 16, |_, y| {
+            let roughness = if y % 2 == 0 { 85 } else { 255 };
+            Rgba::new(0, roughness, 255, 255)
+        });
+        let gold = self.add_material(Material::Pbr(
+            PbrMaterial::new(Color::new(1.0, 0.766, 0.336), 1.0, 0.6)
+                .with_metallic_roughness(bands),
+        ));
+        let glass = self.add_material(Material::Dielectric {
+            ior: Ior::BK7,
+            tint: Color::white(),
+        });
+        let diamond = self.add_material(Material::Dielectric {
+            ior: Ior::DIAMOND,
+            tint: Color::white(),
+        });
+        let water = self.add_material(Material::Dielectric {
+            ior: Ior::Cauchy {
+                a: 1.3199,
+                b: 0.006_53,
+            },
+            tint: Color::new(0.9, 0.97, 1.0),
+        });
+        // Hammered into ripples, in the tangent space normal map encoding
+        let ripples = Buffer::new_with(64, 32, |x, y| {
+            let wave = |i: usize, n: usize| 0.1 * (TAU * 8.0 * i as f32 / n as f32).sin();
+            let normal = vec3f(wave(x, 64), wave(y, 32), 1.0).unit();
+            let encode = |v: f32| ((v * 0.5 + 0.5) * 255.0).round() as u8;
+            Rgba::new(encode(normal.x), encode(normal.y), encode(normal.z), 255)
+        });
+        let mirror = self.add_material(
+            Material::Mirror {
+                tint: Color::splat(0.9),
+            }
+            .with_normal_map(ripples),
+        );
+        // Bricks standing out of the mortar between them, every other row shifted by half a brick
+        let bricks = Buffer::new_with(256, 128, |x, y| {
+            let x = if y / 16 % 2 == 0 { x } else { x + 16 };
+            let mortar = x % 32 < 2 || y % 16 < 2;
+            if mortar {
+                Rgba::black()
+            } else {
+                Rgba::white()
+            }
+        });
+        let albedo = texture.unwrap_or(Texture::Constant(Color::splat(0.5)));
+        let brick = self.add_material(Material::lambertian(albedo).with_bump_map(bricks, 0.002));
+        // A warm lamp between the glass and gold balls, as bright as a floodlight to show up in
+        // daylight
+        self.lights.push(Light::point_from_temperature(
+            vec3f(-0.35, 2.3, 0.1),
+            3000.0,
+            200_000.0,
+        ));
+        // Aim light paths at the spheres rather than the whole ground
+        self.focus = Some(BoundingSphere {
+            center: vec3f(0.2, 2.7, 0),
+            radius: 2.0,
+        });
+        self.with_object(Sphere::new(vec3f(0, 3, 0), 0.4).with_material(gold))
+            .with_object(Sphere::new(vec3f(0.2, 1, 0), 0.1).with_material(brick))
+            .with_object(Sphere::new(vec3f(-0.7, 2.5, -0.2), 0.2).with_material(glass))
+            .with_object(Sphere::new(vec3f(-0.3, 2.1, -0.32), 0.08).with_material(diamond))
+            .with_object(Sphere::new(vec3f(0.45, 2.3, -0.35), 0.05).with_material(water))
+            .with_object(Sphere::new(vec3f(1.4, 3.8, -0.1), 0.3).with_material(mirror))
+            .with_object(Sphere::new(vec3f(0, 3, -100.4), 100.0))
+    }
+    /// See [`Scene::focus`]
+    pub fn light_bounds(&self) -> BoundingSphere {
+        self.focus
+            .unwrap_or_else(|| BoundingSphere::around(&self.objects))
+    }
+    pub fn hit(&self, ray: &Ray3f, t_range: impl RangeBounds<f32> + Clone) -> Option<Hit> {
+        self.objects.hit(ray, t_range)
+    }
+    /// Whether anything is in the way of `ray` before `max_t`
+    pub fn occluded(&self, ray: &Ray3f, max_t: f32) -> bool {
+        self.objects
+            .iter()
+            .any(|o| o.hit(ray, 0.0..max_t).is_some())
+    }
+}
@@ -0,0 +1,331 @@
+//! Scenes described in a text file, one statement per line:
+//!
+//! ```text
+//! # Angles are in degrees, colors linear and paths relative to the scene file
+//! sky <elevation> <azimuth> <turbidity>
+//! environment <file.hdr|file.pfm> [rotation]
+//! material <name> lambertian <r> <g> <b>
+//! material <name> lambertian <image file>
+//! material <name> mirror <r> <g> <b>
+//! material <name> glass <ior|bk7|diamond> [<r> <g> <b>]
+//! material <name> pbr <r> <g> <b> <metallic> <roughness>
+//! sphere <x> <y> <z> <radius> [material]
+//! lamp <x> <y> <z> <kelvin> <lumens>
+//! focus <x> <y> <z> <radius>
+//! ```
+//!
+//! Exactly one of `sky` and `environment` lights the scene, and materials have to be declared
+//! before the spheres using them. Spheres without a material are grey
+
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use renderer_types::prelude::*;
+
+use crate::{
+    environment::{Environment, EnvironmentMap},
+    light::Light,
+    material::{Ior, Material, PbrMaterial},
+    object::{BoundingSphere, Sphere},
+    scene::Scene,
+    sky::PhysicalSky,
+    texture::Texture,
+};
+
+impl Scene {
+    /// Reads a scene file, see [`crate::scene_file`] for the format
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+        Self::parse(&text, path.parent().unwrap_or(Path::new("")))
+    }
+    /// Parses a scene file, loading the images it refers to from `dir`
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, String> {
+        let mut builder = SceneBuilder::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = Words(line.split_whitespace());
+            let Some(statement) = words.0.next() else {
+                continue;
+            };
+            builder
+                .statement(statement, &mut words, dir)
+                .and_then(|()| match words.0.next() {
+                    Some(word) => Err(format!("unexpected {word:?}")),
+                    None => Ok(()),
+                })
+                .map_err(|err| format!("line {}: {err}", i + 1))?;
+        }
+        builder.build()
+    }
+}
+
+/// The words of a line, parsed one at a time
+struct Words<'a>(std::str::SplitWhitespace<'a>);
+
+impl Words<'_> {
+    fn next<T: FromStr>(&mut self, what: &str) -> Result<T, String> {
+        let word = self.0.next().ok_or_else(|| format!("missing {what}"))?;
+        word.parse().map_err(|_| format!("invalid {what} {word:?}"))
+    }
+    /// Like [`Words::next`] for trailing values that may be left out
+    fn optional<T: FromStr>(&mut self, what: &str) -> Result<Option<T>, String> {
+        match self.0.next() {
+            Some(word) => word
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid {what} {word:?}")),
+            None => Ok(None),
+        }
+    }
+    fn vec3(&mut self, what: &str) -> Result<Vec3f, String> {
+        Ok(Vec3::new(
+            self.next(what)?,
+            self.next(what)?,
+            self.next(what)?,
+        ))
+    }
+    fn color(&mut self) -> Result<Colorf32, String> {
+        Ok(Color::new(
+            self.next("red")?,
+            self.next("green")?,
+            self.next("blue")?,
+        ))
+    }
+}
+
+#[derive(Default)]
+struct SceneBuilder {
+    environment: Option<Environment>,
+    materials: Vec<Material>,
+    names: HashMap<String, usize>,
+    objects: Vec<Sphere>,
+    lights: Vec<Light>,
+    focus: Option<BoundingSphere>,
+}
+
+impl SceneBuilder {
+    fn statement(&mut self, statement: &str, words: &mut Words, dir: &Path) -> Result<(), String> {
+        match statement {
+            "sky" | "environment" if self.environment.is_some() => {
+                return Err("the scene already has a sky or environment".to_owned())
+            }
+            "sky" => {
+                let elevation: f32 = words.next("elevation")?;
+                let azimuth: f32 = words.next("azimuth")?;
+                let turbidity = words.next("turbidity")?;
+                let sky = PhysicalSky::new(elevation.to_radians(), azimuth.to_radians(), turbidity);
+                self.lights.push(sky.sun());
+                self.environment = Some(Environment::Sky(sky));
+            }
+            "environment" => {
+                let file: String = words.next("file")?;
+                let path = dir.join(file);
+                let image =
+                    Buffer::open_hdr(&path).map_err(|err| format!("can't read {path:?}: {err}"))?;
+                if image.width() == 0 || image.height() == 0 {
+                    return Err(format!("{path:?} is empty"));
+                }
+                let rotation: f32 = words.optional("rotation")?.unwrap_or(0.0);
+                let map = EnvironmentMap::new(image).with_rotation(rotation.to_radians());
+                self.environment = Some(Environment::Map(map));
+            }
+            "material" => {
+                let name: String = words.next("material name")?;
+                if self.names.contains_key(&name) {
+                    return Err(format!("material {name:?} is declared twice"));
+                }
+                let material = Self::material(words, dir)?;
+                self.materials.push(material);
+                self.names.insert(name, self.materials.len());
+            }
+            "sphere" => {
+                let center = words.vec3("center")?;
+                let radius: f32 = words.next("radius")?;
+                if radius <= 0.0 {
+                    return Err(format!("radius {radius} isn't positive"));
+                }
+                let material = match words.optional::<String>("material")? {
+                    // The scene's default material comes first
+                    None => 0,
+                    Some(name) => *self
+                        .names
+                        .get(&name)
+                        .ok_or_else(|| format!("unknown material {name:?}"))?,
+                };
+                self.objects
+                    .push(Sphere::new(center, radius).with_material(material));
+            }
+            "lamp" => {
+                let position = words.vec3("position")?;
+                let kelvin = words.next("temperature")?;
+                let lumens = words.next("lumens")?;
+                self.lights
+                    .push(Light::point_from_temperature(position, kelvin, lumens));
+            }
+            "focus" => {
+                let center = words.vec3("center")?;
+                let radius = words.next("radius")?;
+                self.focus = Some(BoundingSphere { center, radius });
+            }
+            _ => return Err(format!("unknown statement {statement:?}")),
+        }
+        Ok(())
+    }
+    fn material(words: &mut Words, dir: &Path) -> Result<Material, String> {
+        let kind: String = words.next("material type")?;
+        Ok(match kind.as_str() {
+            "lambertian" => {
+                let first: String = words.next("albedo")?;
+                // Either a color or an image file
+                match first.parse() {
+                    Ok(red) => Material::lambertian(Color::new(
+                        red,
+                        words.next("green")?,
+                        words.next("blue")?,
+                    )),
+                    Err(_) => {
+                        let path = dir.join(first);
+                        let texture = Texture::open(&path)
+                            .map_err(|err| format!("can't read {path:?}: {err}"))?;
+                        Material::lambertian(texture)
+                    }
+                }
+            }
+            "mirror" => Material::Mirror {
+                tint: words.color()?,
+            },
+            "glass" => {
+                let ior = match words.next::<String>("index of refraction")?.as_str() {
+                    "bk7" => Ior::BK7,
+                    "diamond" => Ior::DIAMOND,
+                    ior => Ior::Constant(
+                        ior.parse()
+                            .map_err(|_| format!("invalid index of refraction {ior:?}"))?,
+                    ),
+                };
+                let tint = match words.optional("red")? {
+                    Some(red) => Color::new(red, words.next("green")?, words.next("blue")?),
+                    None => Color::white(),
+                };
+                Material::Dielectric { ior, tint }
+            }
+            "pbr" => {
+                let base = words.color()?;
+                let metallic = words.next("metallic")?;
+                let roughness = words.next("roughness")?;
+                Material::Pbr(PbrMaterial::new(base, metallic, roughness))
+            }
+            _ => return Err(format!("unknown material type {kind:?}")),
+        })
+    }
+    fn build(self) -> Result<Scene, String> {
+        let environment = self
+            .environment
+            .ok_or("the scene has neither a sky nor an environment")?;
+        let mut scene = Scene::new(environment);
+        scene.materials.extend(self.materials);
+        scene.objects = self.objects;
+        scene.lights = self.lights;
+        scene.focus = self.focus;
+        Ok(scene)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Result<Scene, String> {
+        Scene::parse(text, Path::new(""))
+    }
+
+    #[test]
+    fn parses_a_scene() {
+        let scene = parse(
+            "# Two balls\n\
+             sky 35 200 3\n\
+             material gold pbr 1 0.766 0.336 1 0.3\n\
+             material glass glass bk7 0.9 1 1\n\
+             \n\
+             sphere 0 3 0 0.4 gold # on the ground\n\
+             sphere -0.7 2.5 -0.2 0.2 glass\n\
+             sphere 0 3 -100.4 100\n\
+             lamp -0.35 2.3 0.1 3000 200000\n\
+             focus 0.2 2.7 0 2\n",
+        )
+        .unwrap();
+        assert!(matches!(scene.environment, Environment::Sky(_)));
+        // The sun and the lamp
+        assert_eq!(scene.lights.len(), 2);
+        // The default material and the two declared ones
+        assert_eq!(scene.materials.len(), 3);
+        assert!(matches!(scene.materials[1], Material::Pbr(_)));
+        assert!(matches!(
+            scene.materials[2],
+            Material::Dielectric { ior: Ior::BK7, .. }
+        ));
+        assert_eq!(
+            scene.objects,
+            [
+                Sphere::new(vec3f(0, 3, 0), 0.4).with_material(1),
+                Sphere::new(vec3f(-0.7, 2.5, -0.2), 0.2).with_material(2),
+                Sphere::new(vec3f(0, 3, -100.4), 100.0),
+            ]
+        );
+        assert_eq!(
+            scene.focus,
+            Some(BoundingSphere {
+                center: vec3f(0.2, 2.7, 0),
+                radius: 2.0
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_bad_scenes() {
+        for (text, error) in [
+            (
+                "sphere 0 0 0 1",
+                "the scene has neither a sky nor an environment",
+            ),
+            ("sky 35 200", "line 1: missing turbidity"),
+            (
+                "sky 35 200 3\nsky 35 200 3",
+                "line 2: the scene already has",
+            ),
+            ("sky 35 200 3 4", "line 1: unexpected \"4\""),
+            (
+                "sky 35 200 3\nsphere 0 0 0 1 gold",
+                "line 2: unknown material",
+            ),
+            (
+                "sky 35 200 3\nsphere 0 0 0 -1",
+                "line 2: radius -1 isn't positive",
+            ),
+            (
+                "sky 35 200 3\nsphere 0 0 x 1",
+                "line 2: invalid center \"x\"",
+            ),
+            (
+                "sky 35 200 3\nmaterial a mirror 1 1 1\nmaterial a mirror 1 1 1",
+                "line 3: material \"a\" is declared twice",
+            ),
+            (
+                "sky 35 200 3\nmaterial a glass 1.5 1",
+                "line 2: missing green",
+            ),
+            (
+                "sky 35 200 3\nmaterial a chalk",
+                "line 2: unknown material type",
+            ),
+            (
+                "sky 35 200 3\ncube 0 0 0 1",
+                "line 2: unknown statement \"cube\"",
+            ),
+            ("environment missing.hdr", "line 1: can't read"),
+        ] {
+            let err = parse(text).err().unwrap();
+            assert!(err.starts_with(error), "{text:?} gave {err:?}");
+        }
+    }
+}
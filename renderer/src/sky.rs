@@ -0,0 +1,169 @@
+//! Preetham, Shirley and Smits' analytic daylight model
+//! ("A Practical Analytic Model for Daylight", SIGGRAPH 1999)
+use std::f32::consts::{FRAC_PI_2, PI};
+
+use renderer_types::prelude::*;
+
+use crate::light::{Light, LUMINANCE_SCALE};
+
+/// Illuminance of the sun before it enters the atmosphere, in lux
+const EXTRATERRESTRIAL_ILLUMINANCE: f32 = 128_000.0;
+/// Angular radius of the sun as seen from the earth, in radians
+const SUN_ANGULAR_RADIUS: f32 = 0.004_65;
+
+/// Coefficients of the Perez luminance distribution function
+#[derive(Debug, Clone, Copy)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    fn eval(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1.0 + a * (b / cos_theta.max(0.01)).exp())
+            * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// Procedural clear sky, lit by a sun at the given position.
+///
+/// +z points towards the zenith
+#[derive(Debug, Clone)]
+pub struct PhysicalSky {
+    /// Unit vector pointing towards the sun
+    sun_direction: Vec3f,
+    /// Zenith value for each of Y, x and y, already divided by the Perez function at the zenith
+    zenith: [f32; 3],
+    perez: [Perez; 3],
+    sun_irradiance: Colorf32,
+    ground: Colorf32,
+}
+
+impl PhysicalSky {
+    /// Creates a sky with the sun at `elevation` above the horizon and `azimuth` counterclockwise
+    /// from +x, both in radians.
+    ///
+    /// `turbidity` describes the haziness of the atmosphere, from 2 (very clear) to 10 (hazy)
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let elevation = elevation.clamp(0.0, FRAC_PI_2);
+        let (sin_el, cos_el) = elevation.sin_cos();
+        let sun_direction = vec3f(cos_el * azimuth.cos(), cos_el * azimuth.sin(), sin_el);
+        let theta_s = FRAC_PI_2 - elevation;
+
+        let t = turbidity;
+        let perez = [
+            Perez([
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ]),
+            Perez([
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ]),
+            Perez([
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ]),
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        // In kcd/m^2
+        let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let chromaticity = |m: [[f32; 4]; 3]| {
+            let thetas = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+            let ts = [t * t, t, 1.0];
+            ts.iter()
+                .zip(m)
+                .map(|(t, row)| t * row.iter().zip(thetas).map(|(m, th)| m * th).sum::<f32>())
+                .sum::<f32>()
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let zenith = [zenith_luminance * 1000.0, zenith_x, zenith_y];
+        let zenith = [0, 1, 2].map(|i| zenith[i] / perez[i].eval(1.0, theta_s));
+
+        let sun_irradiance = sun_transmittance(theta_s, turbidity)
+            * (EXTRATERRESTRIAL_ILLUMINANCE * LUMINANCE_SCALE);
+
+        let mut sky = Self {
+            sun_direction,
+            zenith,
+            perez,
+            sun_irradiance,
+            ground: Color::black(),
+        };
+        // Approximate the ground as a grey diffuse plane lit by the sun and the zenith of the sky
+        const GROUND_ALBEDO: f32 = 0.3;
+        let irradiance = sun_irradiance * sin_el + sky.sky_radiance(vec3f(0, 0, 1)) * PI;
+        sky.ground = irradiance * (GROUND_ALBEDO / PI);
+        sky
+    }
+    /// Radiance of the sky alone, without the sun disk
+    pub fn sky_radiance(&self, direction: Vec3f) -> Colorf32 {
+        let d = direction.unit();
+        if d.z < 0.0 {
+            return self.ground;
+        }
+        let gamma = d.angle_to(&self.sun_direction);
+        let [luminance, x, y] = [0, 1, 2].map(|i| self.zenith[i] * self.perez[i].eval(d.z, gamma));
+        xyy_to_rgb(x, y, luminance * LUMINANCE_SCALE)
+    }
+    /// Radiance of the sun disk, zero outside of it
+    pub fn sun_radiance(&self, direction: Vec3f) -> Colorf32 {
+        let cos_max = SUN_ANGULAR_RADIUS.cos();
+        if direction.unit().dot(self.sun_direction) < cos_max || self.sun_direction.z < 0.0 {
+            return Color::black();
+        }
+        let solid_angle = 2.0 * PI * (1.0 - cos_max);
+        self.sun_irradiance / solid_angle
+    }
+    pub fn radiance(&self, direction: Vec3f) -> Colorf32 {
+        self.sky_radiance(direction) + self.sun_radiance(direction)
+    }
+    /// The sun as a directional light, matching the disk seen in [`PhysicalSky::radiance`]
+    pub fn sun(&self) -> Light {
+        Light::Directional {
+            direction: self.sun_direction,
+            irradiance: self.sun_irradiance,
+        }
+    }
+}
+
+/// Fraction of sunlight that makes it through the atmosphere, for the wavelengths the
+/// red, green and blue primaries are centered around
+fn sun_transmittance(theta_s: f32, turbidity: f32) -> Colorf32 {
+    const WAVELENGTHS_UM: [f32; 3] = [0.680, 0.550, 0.440];
+    let theta_deg = theta_s.to_degrees();
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_deg).max(0.01).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let [r, g, b] = WAVELENGTHS_UM.map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    });
+    Color::new(r, g, b)
+}
+
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Colorf32 {
+    if y <= 0.0 {
+        return Color::black();
+    }
+    Color::from_xyz(x / y * luminance, luminance, (1.0 - x - y) / y * luminance)
+}
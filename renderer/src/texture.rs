@@ -0,0 +1,119 @@
+use std::{path::Path, sync::Arc};
+
+use renderer_types::{
+    image::{ImageError, ImageFormat, Png, Qoi},
+    prelude::*,
+};
+
+use crate::lerp;
+
+/// A color that may vary over a surface, looked up by uv coordinates
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Colorf32),
+    /// Bilinearly filtered image, repeating outside of [0, 1)
+    Image {
+        image: Arc<Buffer>,
+        /// How the texels turn into linear values
+        encoding: Encoding,
+    },
+}
+
+impl Texture {
+    pub fn sample(&self, uv: Vec2f) -> Colorf32 {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image { image, encoding } => sample_bilinear(image, *encoding, uv),
+        }
+    }
+    /// Size of a single texel in uv space, the smallest step over which the texture can change
+    pub fn texel_size(&self) -> Vec2f {
+        match self {
+            Texture::Constant(_) => vec2f(1.0 / 1024.0, 1.0 / 1024.0),
+            Texture::Image { image, .. } => {
+                vec2f(1.0 / image.width() as f32, 1.0 / image.height() as f32)
+            }
+        }
+    }
+}
+
+impl Texture {
+    /// Reads a color texture from an image file. PNGs and QOIs say whether they're sRGB encoded
+    /// or linear, other formats are taken to be sRGB
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        match ImageFormat::detect(&bytes).or_else(|| ImageFormat::from_path(path)) {
+            Some(ImageFormat::Png) => {
+                let png = Png::decode(&bytes)?;
+                Ok(Texture::Image {
+                    image: Arc::new(png.image),
+                    encoding: png.encoding,
+                })
+            }
+            Some(ImageFormat::Qoi) => {
+                let qoi = Qoi::decode(&bytes)?;
+                Ok(Texture::Image {
+                    image: Arc::new(qoi.image),
+                    encoding: qoi.encoding,
+                })
+            }
+            Some(format) => Ok(Buffer::decode(&bytes, format)?.into()),
+            None => Err(ImageError::Unsupported(format!(
+                "unknown format of {path:?}"
+            ))),
+        }
+    }
+    /// Reads images as linear data, whatever they were created as
+    pub(crate) fn into_data(self) -> Self {
+        match self {
+            Texture::Image { image, .. } => Texture::Image {
+                image,
+                encoding: Encoding::Linear,
+            },
+            texture => texture,
+        }
+    }
+}
+
+impl From<Colorf32> for Texture {
+    fn from(value: Colorf32) -> Self {
+        Texture::Constant(value)
+    }
+}
+
+/// Images are taken to be sRGB encoded colors, materials read data maps as linear
+impl From<Buffer> for Texture {
+    fn from(value: Buffer) -> Self {
+        Texture::Image {
+            image: Arc::new(value),
+            encoding: Encoding::Srgb,
+        }
+    }
+}
+
+fn sample_bilinear(image: &Buffer, encoding: Encoding, uv: Vec2f) -> Colorf32 {
+    let (width, height) = (image.width(), image.height());
+    if width == 0 || height == 0 {
+        return Color::black();
+    }
+    // Image rows go from top to bottom, while v goes up
+    let x = uv.x.mul_add(width as f32, -0.5);
+    let y = (1.0 - uv.y).mul_add(height as f32, -0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let wrap = |v: f32, len: usize| (v as isize).rem_euclid(len as isize) as usize;
+    let (x0, x1) = (wrap(x0, width), wrap(x0 + 1.0, width));
+    let (y0, y1) = (wrap(y0, height), wrap(y0 + 1.0, height));
+    // Decode before filtering, blending encoded values would darken edges
+    let texel = |x, y| {
+        encoding
+            .decode(image.get(x, y).unwrap_or(Rgba::black()))
+            .color
+    };
+
+    let top = lerp(texel(x0, y0), texel(x1, y0), fx);
+    let bottom = lerp(texel(x0, y1), texel(x1, y1), fx);
+    lerp(top, bottom, fy)
+}
@@ -0,0 +1,187 @@
+use std::{fmt, str::FromStr, sync::Arc};
+
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use renderer_types::{
+    lut::{Lut3d, LutInterpolation},
+    prelude::*,
+};
+
+/// Curve squeezing radiance of any brightness into the [0, 1] a display can show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapper {
+    /// Cuts off everything brighter than white, blowing out highlights
+    Clamp,
+    /// Reinhard et al.'s operator on luminance, extended to reach white at
+    /// [`ToneMapping::white`]
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    #[default]
+    Aces,
+    /// Sobotka's AgX, which desaturates towards white instead of skewing hues
+    Agx,
+    /// Hable's filmic curve from Uncharted 2
+    Hable,
+}
+
+impl ToneMapper {
+    pub const ALL: [Self; 5] = [
+        Self::Clamp,
+        Self::Reinhard,
+        Self::Aces,
+        Self::Agx,
+        Self::Hable,
+    ];
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Clamp => "clamp",
+            Self::Reinhard => "reinhard",
+            Self::Aces => "aces",
+            Self::Agx => "agx",
+            Self::Hable => "hable",
+        }
+    }
+    /// The next mapper in [`ToneMapper::ALL`], wrapping around
+    pub fn next(self) -> Self {
+        let idx = Self::ALL.iter().position(|&m| m == self).unwrap_or(0);
+        Self::ALL[(idx + 1) % Self::ALL.len()]
+    }
+    /// Maps linear radiance to linear values in [0, 1], `white` being the radiance Reinhard
+    /// maps to white
+    pub fn map(self, color: Colorf32, white: f32) -> Colorf32 {
+        match self {
+            Self::Clamp => color,
+            Self::Reinhard => reinhard(color, white),
+            Self::Aces => aces(color),
+            Self::Agx => agx(color),
+            Self::Hable => hable(color),
+        }
+        .clamp()
+    }
+}
+
+impl fmt::Display for ToneMapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ToneMapper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mapper| mapper.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<_> = Self::ALL.iter().map(|m| m.name()).collect();
+                format!(
+                    "Unknown tone mapper {s:?}, expected one of {}",
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// How rendered radiance becomes the 8-bit sRGB image that's shown
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToneMapping {
+    /// Exposure in stops, every one doubling the brightness
+    pub exposure: f32,
+    pub mapper: ToneMapper,
+    /// Exposed radiance that [`ToneMapper::Reinhard`] maps to white
+    pub white: f32,
+    /// Grade applied last. Like grading tools, it gets and gives sRGB encoded values
+    pub lut: Option<Arc<Lut3d>>,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            mapper: ToneMapper::default(),
+            white: 4.0,
+            lut: None,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Exposes, maps and grades linear radiance, giving linear values in [0, 1]
+    pub fn apply(&self, radiance: Colorf32) -> Colorf32 {
+        let mapped = self.mapper.map(radiance * self.exposure.exp2(), self.white);
+        match &self.lut {
+            Some(lut) => lut
+                .apply(mapped.encode_srgb(), LutInterpolation::Tetrahedral)
+                .clamp()
+                .decode_srgb(),
+            None => mapped,
+        }
+    }
+    /// Post pass from radiance to `out`, which has to be as large as `hdr`. Every value of `hdr`
+    /// is multiplied by `scale` first, to average accumulated samples
+    pub fn develop(&self, hdr: &Buffer<Colorf32>, scale: f32, out: &mut Buffer) {
+        out.inner_buf_mut()
+            .par_iter_mut()
+            .zip(hdr.inner_buf())
+            .for_each(|(p, radiance)| *p = self.apply(*radiance * scale).to_srgb8());
+    }
+}
+
+fn reinhard(color: Colorf32, white: f32) -> Colorf32 {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::black();
+    }
+    let mapped = luminance * (1.0 + luminance / (white * white)) / (1.0 + luminance);
+    color * (mapped / luminance)
+}
+
+fn aces(color: Colorf32) -> Colorf32 {
+    let curve = |x: f32| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+    Color::new(curve(color.r), curve(color.g), curve(color.b))
+}
+
+fn hable(color: Colorf32) -> Colorf32 {
+    const EXPOSURE_BIAS: f32 = 2.0;
+    /// Linear white point, the radiance that ends up white
+    const WHITE: f32 = 11.2;
+    let curve = |x: f32| {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+    };
+    let scale = 1.0 / curve(WHITE);
+    let map = |x: f32| curve(x * EXPOSURE_BIAS) * scale;
+    Color::new(map(color.r), map(color.g), map(color.b))
+}
+
+/// AgX with the default look, after Wrensch's polynomial fit of Sobotka's curve
+fn agx(color: Colorf32) -> Colorf32 {
+    const MIN_EV: f32 = -12.473_931;
+    const MAX_EV: f32 = 4.026_069;
+    const INSET: [[f32; 3]; 3] = [
+        [0.842_479_06, 0.078_433_6, 0.079_223_73],
+        [0.042_328_24, 0.878_468_6, 0.079_166_13],
+        [0.042_375_65, 0.078_843_9, 0.879_143],
+    ];
+    const OUTSET: [[f32; 3]; 3] = [
+        [1.196_879, -0.098_020_88, -0.099_029_74],
+        [-0.052_896_85, 1.151_903_1, -0.098_961_18],
+        [-0.052_971_64, -0.098_043_45, 1.151_073_7],
+    ];
+    let transform =
+        |m: [[f32; 3]; 3], [r, g, b]: [f32; 3]| m.map(|row| row[0] * r + row[1] * g + row[2] * b);
+    let sigmoid = |x: f32| {
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.002_32
+    };
+    let inset = transform(INSET, [color.r, color.g, color.b]);
+    let encoded = inset.map(|v| {
+        let log = v.max(1e-10).log2().clamp(MIN_EV, MAX_EV);
+        sigmoid((log - MIN_EV) / (MAX_EV - MIN_EV))
+    });
+    // The curve gives display values, which go back to linear for the sRGB encoding that follows
+    let [r, g, b] = transform(OUTSET, encoded).map(|v| v.max(0.0).powf(2.2));
+    Color::new(r, g, b)
+}
@@ -2,6 +2,9 @@ use bytemuck::Zeroable;
 
 use crate::prelude::*;
 
+mod view;
+pub use view::{BufferView, BufferViewMut};
+
 #[derive(bytemuck::Pod, Zeroable, Clone, Copy)]
 // Required for compatibility with the u32 type
 #[repr(align(4))]
@@ -47,21 +50,33 @@ impl Rgba {
     }
 }
 
-pub struct Buffer {
-    pixels: Vec<Rgba>,
+impl Default for Rgba {
+    fn default() -> Self {
+        Rgba::black()
+    }
+}
+
+/// Row-major image of any pixel type, like [`Rgba`] for display, [`Color<f32>`] for radiance or
+/// `f32` for depth
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffer<P = Rgba> {
+    pixels: Vec<P>,
     width: usize,
     height: usize,
 }
 
-impl Buffer {
-    pub fn new(width: usize, height: usize, fill: Rgba) -> Self {
-        Self::new_with(width, height, |_, _| fill)
+impl<P> Buffer<P> {
+    pub fn new(width: usize, height: usize, fill: P) -> Self
+    where
+        P: Clone,
+    {
+        Self {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
     }
-    pub fn new_with<F: FnMut(usize, usize) -> Rgba>(
-        width: usize,
-        height: usize,
-        mut cb: F,
-    ) -> Self {
+    pub fn new_with<F: FnMut(usize, usize) -> P>(width: usize, height: usize, mut cb: F) -> Self {
         Self {
             width,
             height,
@@ -71,16 +86,27 @@ impl Buffer {
                 .collect(),
         }
     }
-    pub fn as_rgba(&self) -> &[u32] {
-        bytemuck::cast_slice(&self.pixels)
+    /// Wraps row-major `pixels`, panicking unless there are `width * height` of them
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<P>) -> Self {
+        assert_eq!(
+            pixels.len(),
+            width * height,
+            "Expected {width}x{height} pixels, got {}",
+            pixels.len()
+        );
+        Self {
+            pixels,
+            width,
+            height,
+        }
     }
-    pub fn as_rgba_mut(&mut self) -> &mut [u32] {
-        bytemuck::cast_slice_mut(&mut self.pixels)
+    pub fn into_pixels(self) -> Vec<P> {
+        self.pixels
     }
-    pub fn inner_buf(&self) -> &[Rgba] {
+    pub fn inner_buf(&self) -> &[P] {
         &self.pixels
     }
-    pub fn inner_buf_mut(&mut self) -> &mut [Rgba] {
+    pub fn inner_buf_mut(&mut self) -> &mut [P] {
         &mut self.pixels
     }
     pub fn dimensions(&self) -> Vec2<usize> {
@@ -92,13 +118,16 @@ impl Buffer {
     pub fn height(&self) -> usize {
         self.height
     }
-    pub fn get(&self, x: usize, y: usize) -> Option<Rgba> {
+    pub fn get(&self, x: usize, y: usize) -> Option<P>
+    where
+        P: Copy,
+    {
         if x >= self.width || y >= self.height {
             return None;
         }
         Some(unsafe { *self.get_unchecked(x, y) })
     }
-    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Rgba> {
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut P> {
         if x >= self.width || y >= self.height {
             return None;
         }
@@ -107,40 +136,43 @@ impl Buffer {
     /// # Safety
     ///
     /// Requires x < width, y < height
-    pub unsafe fn get_unchecked(&self, x: usize, y: usize) -> &Rgba {
+    pub unsafe fn get_unchecked(&self, x: usize, y: usize) -> &P {
         self.pixels.get_unchecked(y * self.width + x)
     }
     /// # Safety
     ///
     /// Requires x < width, y < height
-    pub unsafe fn get_unchecked_mut(&mut self, x: usize, y: usize) -> &mut Rgba {
+    pub unsafe fn get_unchecked_mut(&mut self, x: usize, y: usize) -> &mut P {
         self.pixels.get_unchecked_mut(y * self.width + x)
     }
-    pub fn set(&mut self, x: usize, y: usize, value: Rgba) {
+    pub fn set(&mut self, x: usize, y: usize, value: P) {
+        let (width, height) = (self.width, self.height);
         let Some(pixel) = self.get_mut(x, y) else {
-            panic!(
-                "Attempted to set pixel {x}, {y} in a {width}x{height} buffer",
-                width = self.width(),
-                height = self.height()
-            )
+            panic!("Attempted to set pixel {x}, {y} in a {width}x{height} buffer")
         };
         *pixel = value;
     }
-    pub fn iter(&self) -> impl Iterator<Item = &Rgba> {
+    pub fn fill(&mut self, value: P)
+    where
+        P: Clone,
+    {
+        self.pixels.fill(value);
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &P> {
         self.pixels.iter()
     }
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Rgba> {
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut P> {
         self.pixels.iter_mut()
     }
-    pub fn iter_pos(&self) -> impl Iterator<Item = (usize, usize, &Rgba)> {
+    pub fn iter_pos(&self) -> impl Iterator<Item = (usize, usize, &P)> {
         self.pixels
-            .chunks_exact(self.width)
+            .chunks_exact(self.width.max(1))
             .enumerate()
             .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, pixel)| (x, y, pixel)))
     }
-    pub fn iter_pos_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut Rgba)> {
+    pub fn iter_pos_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut P)> {
         self.pixels
-            .chunks_exact_mut(self.width)
+            .chunks_exact_mut(self.width.max(1))
             .enumerate()
             .flat_map(|(y, row)| {
                 row.iter_mut()
@@ -148,7 +180,15 @@ impl Buffer {
                     .map(move |(x, pixel)| (x, y, pixel))
             })
     }
-    pub fn resize_and_fill<F: FnMut(usize, usize) -> Rgba>(
+    /// Applies `f` to every pixel, keeping the size
+    pub fn map<Q>(&self, f: impl FnMut(&P) -> Q) -> Buffer<Q> {
+        Buffer {
+            pixels: self.pixels.iter().map(f).collect(),
+            width: self.width,
+            height: self.height,
+        }
+    }
+    pub fn resize_and_fill<F: FnMut(usize, usize) -> P>(
         &mut self,
         width: usize,
         height: usize,
@@ -165,7 +205,67 @@ impl Buffer {
         self.width = width;
         self.height = height;
     }
-    pub fn resize(&mut self, width: usize, height: usize) {
-        self.resize_and_fill(width, height, |_, _| Rgba::black())
+    /// Resizes to `width` by `height`, filling every pixel with the default value, which is black
+    /// for colors
+    pub fn resize(&mut self, width: usize, height: usize)
+    where
+        P: Default,
+    {
+        self.resize_and_fill(width, height, |_, _| P::default())
+    }
+}
+
+impl Buffer<Rgba> {
+    pub fn as_rgba(&self) -> &[u32] {
+        bytemuck::cast_slice(&self.pixels)
+    }
+    pub fn as_rgba_mut(&mut self) -> &mut [u32] {
+        bytemuck::cast_slice_mut(&mut self.pixels)
+    }
+}
+
+#[cfg(feature = "rayon")]
+mod par {
+    use rayon::prelude::*;
+
+    use super::{Buffer, BufferViewMut};
+
+    impl<P: Send> Buffer<P> {
+        /// Rows in parallel, along with their y
+        pub fn par_rows_mut(&mut self) -> impl IndexedParallelIterator<Item = (usize, &mut [P])> {
+            self.pixels
+                .par_chunks_exact_mut(self.width.max(1))
+                .enumerate()
+        }
+        /// Pixels in parallel, along with their x and y
+        pub fn par_iter_pos_mut(&mut self) -> impl ParallelIterator<Item = (usize, usize, &mut P)> {
+            self.par_rows_mut().flat_map_iter(|(y, row)| {
+                row.iter_mut()
+                    .enumerate()
+                    .map(move |(x, pixel)| (x, y, pixel))
+            })
+        }
+        /// Splits the buffer into tiles of `tile_width` by `tile_height` pixels to work on in
+        /// parallel. Tiles along the right and bottom edges are smaller if the size isn't a
+        /// multiple of the tile size
+        pub fn par_tiles_mut(
+            &mut self,
+            tile_width: usize,
+            tile_height: usize,
+        ) -> impl IndexedParallelIterator<Item = BufferViewMut<'_, P>> {
+            assert!(tile_width > 0 && tile_height > 0, "Tiles must not be empty");
+            let mut tiles = Vec::new();
+            let mut rest = self.as_view_mut();
+            while rest.height() > 0 {
+                let (mut band, below) = rest.split_at_row(tile_height);
+                while band.width() > 0 {
+                    let (tile, right) = band.split_at_col(tile_width);
+                    tiles.push(tile);
+                    band = right;
+                }
+                rest = below;
+            }
+            tiles.into_par_iter()
+        }
     }
 }
@@ -0,0 +1,444 @@
+use std::{marker::PhantomData, ptr::NonNull};
+
+use super::Buffer;
+use crate::prelude::*;
+
+/// Rectangle of a [`Buffer`], read-only. Rows are `stride` pixels apart in the buffer, and
+/// positions are relative to the view, which starts at [`BufferView::origin`] in the buffer.
+///
+/// Only the pixels of its rows are ever read, as the ones between them may belong to a
+/// [`BufferViewMut`] being written to at the same time
+#[derive(Debug)]
+pub struct BufferView<'a, P> {
+    /// First pixel of the view, the others follow in rows `stride` apart
+    start: NonNull<P>,
+    origin: Vec2<usize>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    _buffer: PhantomData<&'a [P]>,
+}
+
+// Views can only reach their own pixels, so they're as thread safe as shared references to them
+unsafe impl<P: Sync> Send for BufferView<'_, P> {}
+unsafe impl<P: Sync> Sync for BufferView<'_, P> {}
+
+impl<P> Clone for BufferView<'_, P> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<P> Copy for BufferView<'_, P> {}
+
+/// Rectangle of a [`Buffer`] that can be written to, see [`BufferView`]. Splitting it with
+/// [`BufferViewMut::split_at_row`] or [`BufferViewMut::split_at_col`] gives views that can be
+/// written to independently, like from different threads
+#[derive(Debug)]
+pub struct BufferViewMut<'a, P> {
+    /// First pixel of the view, the others follow in rows `stride` apart
+    start: NonNull<P>,
+    origin: Vec2<usize>,
+    width: usize,
+    height: usize,
+    stride: usize,
+    _buffer: PhantomData<&'a mut [P]>,
+}
+
+// Views can only reach their own pixels, so they're as thread safe as references to them
+unsafe impl<P: Send> Send for BufferViewMut<'_, P> {}
+unsafe impl<P: Sync> Sync for BufferViewMut<'_, P> {}
+
+/// Offset of the first pixel of the `width` by `height` rectangle at `x`, `y`, which is zero for
+/// empty rectangles, so that it never points past the pixels
+fn offset(x: usize, y: usize, width: usize, height: usize, stride: usize) -> usize {
+    if width == 0 || height == 0 {
+        0
+    } else {
+        y * stride + x
+    }
+}
+
+/// Panics unless the `width` by `height` rectangle at `x`, `y` fits in `outer`
+fn check_rect(outer: Vec2<usize>, x: usize, y: usize, width: usize, height: usize) {
+    assert!(
+        x.checked_add(width).is_some_and(|end| end <= outer.x)
+            && y.checked_add(height).is_some_and(|end| end <= outer.y),
+        "A {width}x{height} view at {x}, {y} doesn't fit in {}x{}",
+        outer.x,
+        outer.y
+    );
+}
+
+impl<'a, P> BufferView<'a, P> {
+    /// Position of the top left pixel of the view in the buffer
+    pub fn origin(&self) -> Vec2<usize> {
+        self.origin
+    }
+    pub fn dimensions(&self) -> Vec2<usize> {
+        Vec2::new(self.width, self.height)
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Distance between the starts of two rows, in pixels
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    pub fn get(&self, x: usize, y: usize) -> Option<P>
+    where
+        P: Copy,
+    {
+        self.get_ref(x, y).copied()
+    }
+    pub fn get_ref(&self, x: usize, y: usize) -> Option<&'a P> {
+        self.row(y)?.get(x)
+    }
+    pub fn row(&self, y: usize) -> Option<&'a [P]> {
+        if y >= self.height {
+            return None;
+        }
+        // Safety: the row lies within the view, and nothing writes to it while the view exists
+        Some(unsafe {
+            std::slice::from_raw_parts(self.start.as_ptr().add(y * self.stride), self.width)
+        })
+    }
+    pub fn rows(&self) -> impl Iterator<Item = &'a [P]> + use<'a, P> {
+        let view = *self;
+        (0..self.height).filter_map(move |y| view.row(y))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &'a P> + use<'a, P> {
+        self.rows().flatten()
+    }
+    pub fn iter_pos(&self) -> impl Iterator<Item = (usize, usize, &'a P)> + use<'a, P> {
+        self.rows()
+            .enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate().map(move |(x, pixel)| (x, y, pixel)))
+    }
+    /// The `width` by `height` rectangle at `x`, `y` of this view
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> BufferView<'a, P> {
+        check_rect(self.dimensions(), x, y, width, height);
+        let offset = offset(x, y, width, height, self.stride);
+        BufferView {
+            // Safety: the offset is within the view, or zero for empty views
+            start: unsafe { NonNull::new_unchecked(self.start.as_ptr().add(offset)) },
+            origin: self.origin + Vec2::new(x, y),
+            width,
+            height,
+            stride: self.stride,
+            _buffer: PhantomData,
+        }
+    }
+    /// Splits into the rows above `y` and the rest
+    pub fn split_at_row(&self, y: usize) -> (BufferView<'a, P>, BufferView<'a, P>) {
+        let y = y.min(self.height);
+        (
+            self.view(0, 0, self.width, y),
+            self.view(0, y, self.width, self.height - y),
+        )
+    }
+    /// Splits into the columns left of `x` and the rest
+    pub fn split_at_col(&self, x: usize) -> (BufferView<'a, P>, BufferView<'a, P>) {
+        let x = x.min(self.width);
+        (
+            self.view(0, 0, x, self.height),
+            self.view(x, 0, self.width - x, self.height),
+        )
+    }
+    /// Copies the pixels into a buffer of their own
+    pub fn to_buffer(&self) -> Buffer<P>
+    where
+        P: Clone,
+    {
+        Buffer::from_pixels(self.width, self.height, self.iter().cloned().collect())
+    }
+}
+
+impl<'a, P> BufferViewMut<'a, P> {
+    /// Position of the top left pixel of the view in the buffer
+    pub fn origin(&self) -> Vec2<usize> {
+        self.origin
+    }
+    pub fn dimensions(&self) -> Vec2<usize> {
+        Vec2::new(self.width, self.height)
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    /// Distance between the starts of two rows, in pixels
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+    /// Read-only view of the same pixels, which `&self` keeps from being written meanwhile
+    pub fn as_view(&self) -> BufferView<'_, P> {
+        BufferView {
+            start: self.start,
+            origin: self.origin,
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            _buffer: PhantomData,
+        }
+    }
+    pub fn get(&self, x: usize, y: usize) -> Option<P>
+    where
+        P: Copy,
+    {
+        self.as_view().get(x, y)
+    }
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut P> {
+        self.row_mut(y)?.get_mut(x)
+    }
+    pub fn set(&mut self, x: usize, y: usize, value: P) {
+        let (width, height) = (self.width, self.height);
+        let Some(pixel) = self.get_mut(x, y) else {
+            panic!("Attempted to set pixel {x}, {y} in a {width}x{height} view")
+        };
+        *pixel = value;
+    }
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [P]> {
+        if y >= self.height {
+            return None;
+        }
+        // Safety: the row lies within the view, and `&mut self` makes it the only access to it
+        Some(unsafe {
+            std::slice::from_raw_parts_mut(self.start.as_ptr().add(y * self.stride), self.width)
+        })
+    }
+    /// Consumes the view for its rows, which are disjoint
+    pub fn into_rows(self) -> impl Iterator<Item = &'a mut [P]> {
+        (0..self.height).map(move |y| {
+            // Safety: rows don't overlap as the width is at most the stride, and each is only
+            // handed out once
+            unsafe {
+                std::slice::from_raw_parts_mut(self.start.as_ptr().add(y * self.stride), self.width)
+            }
+        })
+    }
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [P]> {
+        self.view_mut(0, 0, self.width, self.height).into_rows()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &P> {
+        self.as_view().iter()
+    }
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut P> {
+        self.rows_mut().flatten()
+    }
+    pub fn iter_pos(&self) -> impl Iterator<Item = (usize, usize, &P)> {
+        self.as_view().iter_pos()
+    }
+    pub fn iter_pos_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut P)> {
+        self.rows_mut().enumerate().flat_map(|(y, row)| {
+            row.iter_mut()
+                .enumerate()
+                .map(move |(x, pixel)| (x, y, pixel))
+        })
+    }
+    /// The `width` by `height` rectangle at `x`, `y` of this view, borrowing it meanwhile
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> BufferViewMut<'_, P> {
+        self.sub_view(x, y, width, height)
+    }
+    /// Like [`BufferViewMut::view_mut`], but keeping the lifetime of the buffer. Callers must
+    /// not hand out overlapping views
+    fn sub_view<'b>(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> BufferViewMut<'b, P> {
+        check_rect(self.dimensions(), x, y, width, height);
+        let offset = offset(x, y, width, height, self.stride);
+        BufferViewMut {
+            // Safety: the offset is within the view, or zero for empty views
+            start: unsafe { NonNull::new_unchecked(self.start.as_ptr().add(offset)) },
+            origin: self.origin + Vec2::new(x, y),
+            width,
+            height,
+            stride: self.stride,
+            _buffer: PhantomData,
+        }
+    }
+    /// Splits into the rows above `y` and the rest
+    pub fn split_at_row(self, y: usize) -> (BufferViewMut<'a, P>, BufferViewMut<'a, P>) {
+        let y = y.min(self.height);
+        (
+            self.sub_view(0, 0, self.width, y),
+            self.sub_view(0, y, self.width, self.height - y),
+        )
+    }
+    /// Splits into the columns left of `x` and the rest
+    pub fn split_at_col(self, x: usize) -> (BufferViewMut<'a, P>, BufferViewMut<'a, P>) {
+        let x = x.min(self.width);
+        (
+            self.sub_view(0, 0, x, self.height),
+            self.sub_view(x, 0, self.width - x, self.height),
+        )
+    }
+    /// Copies the pixels into a buffer of their own
+    pub fn to_buffer(&self) -> Buffer<P>
+    where
+        P: Clone,
+    {
+        self.as_view().to_buffer()
+    }
+}
+
+impl<P> Buffer<P> {
+    /// The whole buffer as a view
+    pub fn as_view(&self) -> BufferView<'_, P> {
+        BufferView {
+            start: NonNull::from(self.pixels.as_slice()).cast(),
+            origin: Vec2::new(0, 0),
+            width: self.width,
+            height: self.height,
+            stride: self.width,
+            _buffer: PhantomData,
+        }
+    }
+    /// The whole buffer as a view that can be split up and written to
+    pub fn as_view_mut(&mut self) -> BufferViewMut<'_, P> {
+        BufferViewMut {
+            start: NonNull::new(self.pixels.as_mut_ptr()).expect("Vec pointers are never null"),
+            origin: Vec2::new(0, 0),
+            width: self.width,
+            height: self.height,
+            stride: self.width,
+            _buffer: PhantomData,
+        }
+    }
+    /// The `width` by `height` rectangle at `x`, `y`
+    pub fn view(&self, x: usize, y: usize, width: usize, height: usize) -> BufferView<'_, P> {
+        self.as_view().view(x, y, width, height)
+    }
+    /// The `width` by `height` rectangle at `x`, `y`, to write to
+    pub fn view_mut(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> BufferViewMut<'_, P> {
+        self.as_view_mut().sub_view(x, y, width, height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Value every test writes at the pixel `x`, `y` of the buffer
+    fn value(x: usize, y: usize) -> u32 {
+        (y * 1000 + x) as u32
+    }
+
+    #[test]
+    fn views_read_their_rectangle() {
+        let buffer = Buffer::new_with(7, 5, value);
+        let view = buffer.view(2, 1, 3, 3);
+        assert_eq!(view.dimensions(), Vec2::new(3, 3));
+        assert_eq!(view.get(0, 0), Some(value(2, 1)));
+        assert_eq!(view.get(2, 2), Some(value(4, 3)));
+        assert_eq!(view.get(3, 0), None);
+        assert_eq!(
+            view.row(1),
+            Some(&[value(2, 2), value(3, 2), value(4, 2)][..])
+        );
+        let inner = view.view(1, 1, 2, 2);
+        assert_eq!(inner.origin(), Vec2::new(3, 2));
+        assert_eq!(
+            inner.iter().copied().collect::<Vec<_>>(),
+            [value(3, 2), value(4, 2), value(3, 3), value(4, 3)]
+        );
+        assert_eq!(view.view(3, 3, 0, 0).iter().count(), 0);
+    }
+
+    #[test]
+    fn splits_cover_the_view() {
+        let mut buffer = Buffer::new(6, 4, 0);
+        let (top, bottom) = buffer.as_view_mut().split_at_row(1);
+        let (mut left, mut right) = bottom.split_at_col(4);
+        let mut top = top;
+        top.iter_mut().for_each(|p| *p = 1);
+        left.iter_mut().for_each(|p| *p = 2);
+        right
+            .iter_pos_mut()
+            .for_each(|(x, y, p)| *p = 10 + x as u32 + 10 * y as u32);
+        assert_eq!(right.origin(), Vec2::new(4, 1));
+        assert_eq!(right.get(1, 2), Some(31));
+        assert_eq!(
+            buffer.inner_buf(),
+            [
+                1, 1, 1, 1, 1, 1, //
+                2, 2, 2, 2, 10, 11, //
+                2, 2, 2, 2, 20, 21, //
+                2, 2, 2, 2, 30, 31,
+            ]
+        );
+    }
+
+    /// Splits `buffer` into `tile` by `tile` views, the way `par_tiles_mut` does
+    fn tiles(buffer: &mut Buffer<u32>, tile: usize) -> Vec<BufferViewMut<'_, u32>> {
+        let mut tiles = Vec::new();
+        let mut rest = buffer.as_view_mut();
+        while rest.height() > 0 {
+            let (mut band, below) = rest.split_at_row(tile);
+            while band.width() > 0 {
+                let (left, right) = band.split_at_col(tile);
+                tiles.push(left);
+                band = right;
+            }
+            rest = below;
+        }
+        tiles
+    }
+
+    #[test]
+    fn tiles_written_from_threads_read_back() {
+        let mut buffer = Buffer::new(37, 29, u32::MAX);
+        std::thread::scope(|scope| {
+            for mut tile in tiles(&mut buffer, 8) {
+                scope.spawn(move || {
+                    let origin = tile.origin();
+                    tile.iter_pos_mut()
+                        .for_each(|(x, y, p)| *p = value(origin.x + x, origin.y + y));
+                    // Reading a tile while its neighbours are being written only sees its own
+                    assert!(tile
+                        .iter_pos()
+                        .all(|(x, y, &p)| p == value(origin.x + x, origin.y + y)));
+                    let copy = tile.to_buffer();
+                    assert_eq!(copy.dimensions(), tile.dimensions());
+                });
+            }
+        });
+        assert!(buffer.iter_pos().all(|(x, y, &p)| p == value(x, y)));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_tiles_read_back() {
+        use rayon::iter::ParallelIterator;
+
+        let mut buffer = Buffer::new(70, 45, u32::MAX);
+        let count = buffer.par_tiles_mut(32, 16).count();
+        assert_eq!(count, 9);
+        buffer.par_tiles_mut(32, 16).for_each(|mut tile| {
+            let origin = tile.origin();
+            tile.iter_pos_mut()
+                .for_each(|(x, y, p)| *p = value(origin.x + x, origin.y + y));
+            assert_eq!(tile.get(0, 0), Some(value(origin.x, origin.y)));
+        });
+        assert!(buffer.iter_pos().all(|(x, y, &p)| p == value(x, y)));
+    }
+}
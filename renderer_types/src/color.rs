@@ -1,7 +1,10 @@
 use num_traits::{AsPrimitive, Float};
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::{
+    array,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign},
+};
 
-use crate::vec::Vec3;
+use crate::{prelude::Vec3f, spectrum, vec::Vec3};
 
 use super::buf::Rgba;
 
@@ -13,8 +16,9 @@ pub struct Color<T: Float = f32> {
 }
 
 impl<T: Float> Color<T> {
+    /// Linear color of 8-bit sRGB channels, as given by color pickers
     pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
-        Self::from(Rgba::new(r, g, b, 255))
+        Self::from_srgb8(Rgba::new(r, g, b, 255))
     }
     pub const fn new(r: T, g: T, b: T) -> Self {
         Self { r, g, b }
@@ -41,37 +45,275 @@ impl<T: Float> Color<T> {
     pub fn white() -> Self {
         Self::splat(T::one())
     }
+    /// Relative luminance of a linear Rec. 709 color
+    pub fn luminance(&self) -> T {
+        let [kr, kg, kb] = [0.2126, 0.7152, 0.0722].map(|k| T::from(k).unwrap());
+        self.r.mul_add(kr, self.g.mul_add(kg, self.b * kb))
+    }
+    /// Linear sRGB color with the given CIE 1931 XYZ tristimulus values, relative to a D65 white
+    pub fn from_xyz(x: T, y: T, z: T) -> Self {
+        let [r, g, b] = transform(matrix(XYZ_TO_SRGB), [x, y, z]);
+        Self::new(r, g, b)
+    }
+    /// CIE 1931 XYZ tristimulus values of the color, relative to a D65 white
+    pub fn to_xyz(&self) -> Vec3<T> {
+        let [x, y, z] = transform(matrix(SRGB_TO_XYZ), [self.r, self.g, self.b]);
+        Vec3 { x, y, z }
+    }
+    /// Color of a black body at `kelvin`, the Planckian locus, with a luminance of one.
+    ///
+    /// Lower temperatures are warmer: candle flames are around 1900 K, incandescent bulbs 2700 K,
+    /// and daylight 5000 K to 6500 K. Below about 1200 K the locus leaves the sRGB gamut, and the
+    /// color is clamped back into it
+    pub fn from_temperature(kelvin: T) -> Self {
+        let kelvin = kelvin.to_f32().unwrap_or(0.0);
+        let mut xyz = Vec3f::default();
+        let mut lambda = spectrum::LAMBDA_MIN;
+        while lambda <= spectrum::LAMBDA_MAX {
+            xyz += spectrum::cie_xyz(lambda) * spectrum::blackbody(lambda, kelvin);
+            lambda += 5.0;
+        }
+        let rgb = Color::<f32>::from_xyz(xyz.x, xyz.y, xyz.z);
+        let rgb = Color::<f32>::new(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0));
+        let luminance = rgb.luminance();
+        if luminance <= 0.0 {
+            return Self::black();
+        }
+        let rgb = rgb / luminance;
+        let [r, g, b] = [rgb.r, rgb.g, rgb.b].map(|c| T::from(c).unwrap());
+        Self::new(r, g, b)
+    }
 }
 
-impl<T: Float> Color<T> {
-    pub fn into_rgba(self) -> Rgba {
-        let max = T::from(255).unwrap();
-        let clamp = |v: T| {
-            if !v.is_sign_positive() {
-                return T::zero();
-            }
-            if v > T::one() {
-                return T::one();
-            }
-            v
+/// Linear sRGB to CIE 1931 XYZ, for a D65 white
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175],
+    [0.019_333_9, 0.119_192, 0.950_304_1],
+];
+/// CIE 1931 XYZ to linear sRGB, for a D65 white
+const XYZ_TO_SRGB: [[f64; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+/// Cone responses from CIE 1931 XYZ, from the Bradford chromatic adaptation transform
+const XYZ_TO_BRADFORD: [[f64; 3]; 3] = [
+    [0.895_1, 0.266_4, -0.161_4],
+    [-0.750_2, 1.713_5, 0.036_7],
+    [0.038_9, -0.068_5, 1.029_6],
+];
+
+/// Linear map between linear sRGB colors that makes what was seen under one white look the way
+/// it would under another, by scaling the cone responses of the Bradford transform.
+///
+/// This is the white balance of cameras: content lit by warm light looks orange until adapted
+/// to it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChromaticAdaptation<T: Float = f32> {
+    matrix: [[T; 3]; 3],
+}
+
+impl<T: Float> ChromaticAdaptation<T> {
+    pub fn identity() -> Self {
+        let (zero, one) = (T::zero(), T::one());
+        Self {
+            matrix: [[one, zero, zero], [zero, one, zero], [zero, zero, one]],
+        }
+    }
+    /// Turns the `source` white into the `target` white, both in linear sRGB. Only their
+    /// chromaticities matter, the brightness of colors is kept
+    pub fn bradford(source: Color<T>, target: Color<T>) -> Self {
+        let to_cone = mul(matrix(XYZ_TO_BRADFORD), matrix(SRGB_TO_XYZ));
+        let from_cone = invert(to_cone);
+        let normalize = |white: Color<T>| {
+            let cone = transform(to_cone, [white.r, white.g, white.b]);
+            // Divide by luminance so the adaptation doesn't change brightness
+            cone.map(|c| c / white.luminance())
         };
-        let [r, g, b] = [self.r, self.g, self.b]
-            .map(clamp)
-            .map(|v| v * max)
-            .map(|c| c.to_u8().unwrap_or(255));
-        Rgba::new(r, g, b, 255)
-    }
-}
-
-impl<T: Float> From<Rgba> for Color<T> {
-    fn from(value: Rgba) -> Self {
-        let Rgba { b, g, r, a: _ } = value;
-        let max = T::from(256).unwrap();
-        let [r, g, b] = [r, g, b]
-            .map(T::from)
-            .map(|c| c.unwrap_or_else(|| T::zero()))
-            .map(|v| v / max);
-        Self { r, g, b }
+        let (source, target) = (normalize(source), normalize(target));
+        let zero = T::zero();
+        let scale = [
+            [target[0] / source[0], zero, zero],
+            [zero, target[1] / source[1], zero],
+            [zero, zero, target[2] / source[2]],
+        ];
+        Self {
+            matrix: mul(from_cone, mul(scale, to_cone)),
+        }
+    }
+    /// White balance for a scene lit by a black body at `kelvin`, which becomes neutral white
+    pub fn white_balance(kelvin: T) -> Self {
+        Self::bradford(Color::from_temperature(kelvin), Color::white())
+    }
+    /// The adaptation as a matrix multiplying linear sRGB column vectors
+    pub fn matrix(&self) -> [[T; 3]; 3] {
+        self.matrix
+    }
+    /// Applies `self`, then `next`
+    pub fn then(&self, next: &Self) -> Self {
+        Self {
+            matrix: mul(next.matrix, self.matrix),
+        }
+    }
+    pub fn apply(&self, color: Color<T>) -> Color<T> {
+        let [r, g, b] = transform(self.matrix, [color.r, color.g, color.b]);
+        Color::new(r, g, b)
+    }
+}
+
+pub(crate) fn matrix<T: Float>(m: [[f64; 3]; 3]) -> [[T; 3]; 3] {
+    m.map(|row| row.map(|v| T::from(v).unwrap()))
+}
+
+pub(crate) fn transform<T: Float>(m: [[T; 3]; 3], v: [T; 3]) -> [T; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+fn mul<T: Float>(a: [[T; 3]; 3], b: [[T; 3]; 3]) -> [[T; 3]; 3] {
+    array::from_fn(|r| array::from_fn(|c| (0..3).fold(T::zero(), |sum, i| sum + a[r][i] * b[i][c])))
+}
+
+/// Inverse of a 3x3 matrix, which has to be invertible
+pub(crate) fn invert<T: Float>(m: [[T; 3]; 3]) -> [[T; 3]; 3] {
+    let cofactor = |r: usize, c: usize| {
+        let (r0, r1) = ((r + 1) % 3, (r + 2) % 3);
+        let (c0, c1) = ((c + 1) % 3, (c + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = (0..3).fold(T::zero(), |sum, c| sum + m[0][c] * cofactor(0, c));
+    // The inverse is the transposed cofactor matrix over the determinant
+    array::from_fn(|r| array::from_fn(|c| cofactor(c, r) / det))
+}
+
+/// sRGB transfer function, from linear light to the perceptually even encoded value
+pub fn srgb_encode<T: Float>(linear: T) -> T {
+    let c = |v: f64| T::from(v).unwrap();
+    if linear <= c(0.003_130_8) {
+        linear * c(12.92)
+    } else {
+        c(1.055) * linear.powf(c(1.0 / 2.4)) - c(0.055)
+    }
+}
+
+/// Inverse of [`srgb_encode`], from an encoded value back to linear light
+pub fn srgb_decode<T: Float>(encoded: T) -> T {
+    let c = |v: f64| T::from(v).unwrap();
+    if encoded <= c(0.040_45) {
+        encoded / c(12.92)
+    } else {
+        ((encoded + c(0.055)) / c(1.055)).powf(c(2.4))
+    }
+}
+
+/// Rounds a value in [0, 1] to 8 bits, clamping anything outside and turning NaN into 0
+fn quantize<T: Float>(v: T) -> u8 {
+    let v = v.max(T::zero()).min(T::one());
+    (v * T::from(255).unwrap()).round().to_u8().unwrap_or(0)
+}
+
+fn dequantize<T: Float>(v: u8) -> T {
+    T::from(v).unwrap() / T::from(255).unwrap()
+}
+
+impl<T: Float> Color<T> {
+    /// Applies the sRGB transfer function to every channel, for display or storage
+    pub fn encode_srgb(self) -> Self {
+        Self::new(
+            srgb_encode(self.r),
+            srgb_encode(self.g),
+            srgb_encode(self.b),
+        )
+    }
+    /// Turns sRGB encoded channels back into linear light
+    pub fn decode_srgb(self) -> Self {
+        Self::new(
+            srgb_decode(self.r),
+            srgb_decode(self.g),
+            srgb_decode(self.b),
+        )
+    }
+    /// Linear color of 8-bit sRGB channels, like those of images and color pickers. Alpha is
+    /// dropped, see [`ColorAlpha::from_srgb8`] to keep it
+    pub fn from_srgb8(rgba: Rgba) -> Self {
+        ColorAlpha::from_srgb8(rgba).color
+    }
+    /// Encodes the color as opaque 8-bit sRGB, for display or storage
+    pub fn to_srgb8(self) -> Rgba {
+        ColorAlpha::opaque(self).to_srgb8()
+    }
+    /// Color of 8-bit channels that store linear values as is, like normal maps. Alpha is
+    /// dropped, see [`ColorAlpha::from_linear8`] to keep it
+    pub fn from_linear8(rgba: Rgba) -> Self {
+        ColorAlpha::from_linear8(rgba).color
+    }
+    /// Stores the color as opaque 8-bit channels without any transfer function
+    pub fn to_linear8(self) -> Rgba {
+        ColorAlpha::opaque(self).to_linear8()
+    }
+}
+
+/// Linear color with straight, not premultiplied, alpha
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct ColorAlpha<T: Float = f32> {
+    pub color: Color<T>,
+    pub alpha: T,
+}
+
+impl<T: Float> ColorAlpha<T> {
+    pub const fn new(color: Color<T>, alpha: T) -> Self {
+        Self { color, alpha }
+    }
+    pub fn opaque(color: Color<T>) -> Self {
+        Self::new(color, T::one())
+    }
+    /// Decodes 8-bit sRGB channels, alpha being linear as it always is
+    pub fn from_srgb8(rgba: Rgba) -> Self {
+        let linear = Self::from_linear8(rgba);
+        Self::new(linear.color.decode_srgb(), linear.alpha)
+    }
+    /// Encodes the color as 8-bit sRGB channels, exactly undoing [`ColorAlpha::from_srgb8`]
+    pub fn to_srgb8(self) -> Rgba {
+        Self::new(self.color.encode_srgb(), self.alpha).to_linear8()
+    }
+    /// Reads 8-bit channels that store linear values as is
+    pub fn from_linear8(rgba: Rgba) -> Self {
+        let Rgba { r, g, b, a } = rgba;
+        Self::new(
+            Color::new(dequantize(r), dequantize(g), dequantize(b)),
+            dequantize(a),
+        )
+    }
+    /// Stores the channels as 8 bits without any transfer function, exactly undoing
+    /// [`ColorAlpha::from_linear8`]
+    pub fn to_linear8(self) -> Rgba {
+        let Color { r, g, b } = self.color;
+        Rgba::new(quantize(r), quantize(g), quantize(b), quantize(self.alpha))
+    }
+}
+
+/// How the channels of 8-bit images relate to linear light
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Channels are linear, for data like normal or roughness maps
+    Linear,
+    /// Channels went through the sRGB transfer function, like almost every color image
+    #[default]
+    Srgb,
+}
+
+impl Encoding {
+    pub fn decode<T: Float>(self, rgba: Rgba) -> ColorAlpha<T> {
+        match self {
+            Encoding::Linear => ColorAlpha::from_linear8(rgba),
+            Encoding::Srgb => ColorAlpha::from_srgb8(rgba),
+        }
+    }
+    pub fn encode<T: Float>(self, color: ColorAlpha<T>) -> Rgba {
+        match self {
+            Encoding::Linear => color.to_linear8(),
+            Encoding::Srgb => color.to_srgb8(),
+        }
     }
 }
 
@@ -159,3 +401,35 @@ impl<T: Float> MulAssign<Color<T>> for Color<T> {
         *self = *self * rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(rgba: Rgba) -> [u8; 4] {
+        let Rgba { r, g, b, a } = rgba;
+        [r, g, b, a]
+    }
+
+    #[test]
+    fn eight_bits_round_trip() {
+        for v in 0..=255 {
+            // Every channel different, with alpha running the other way
+            let rgba = Rgba::new(v, v.wrapping_add(85), v.wrapping_add(170), 255 - v);
+            let opaque = [v, v.wrapping_add(85), v.wrapping_add(170), 255];
+            assert_eq!(channels(Color::<f32>::from_srgb8(rgba).to_srgb8()), opaque);
+            assert_eq!(
+                channels(Color::<f32>::from_linear8(rgba).to_linear8()),
+                opaque
+            );
+            let srgb = ColorAlpha::<f32>::from_srgb8(rgba);
+            assert_eq!(channels(srgb.to_srgb8()), channels(rgba));
+            let linear = ColorAlpha::<f32>::from_linear8(rgba);
+            assert_eq!(channels(linear.to_linear8()), channels(rgba));
+
+            let x = v as f32 / 255.0;
+            assert!((srgb_encode(srgb_decode(x)) - x).abs() < 1e-6, "{x}");
+            assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1e-6, "{x}");
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Color spaces that are easier to pick or blend colors in than linear RGB.
+//!
+//! Every space converts to and from linear sRGB [`Color`], and through it to the others. CIE XYZ
+//! lives on [`Color`] itself, see [`Color::from_xyz`] and [`Color::to_xyz`]
+
+use num_traits::Float;
+
+use crate::color::{matrix, transform, Color};
+
+/// Björn Ottosson's Oklab: `l` is perceived lightness from 0 to 1, `a` goes from green to red
+/// and `b` from blue to yellow. Equal distances look about equally different, which makes it the
+/// space to blend gradients in
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Oklab<T: Float = f32> {
+    pub l: T,
+    pub a: T,
+    pub b: T,
+}
+
+/// [`Oklab`] in polar form: chroma `c` is the distance from grey and `h` the hue angle in degrees
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Oklch<T: Float = f32> {
+    pub l: T,
+    pub c: T,
+    pub h: T,
+}
+
+/// Hue in degrees, with saturation and value from 0 to 1, of sRGB encoded channels like color
+/// pickers show
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Hsv<T: Float = f32> {
+    pub h: T,
+    pub s: T,
+    pub v: T,
+}
+
+/// Hue in degrees, with saturation and lightness from 0 to 1, of sRGB encoded channels like CSS
+/// uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct Hsl<T: Float = f32> {
+    pub h: T,
+    pub s: T,
+    pub l: T,
+}
+
+fn c<T: Float>(v: f64) -> T {
+    T::from(v).unwrap()
+}
+
+impl<T: Float> Oklab<T> {
+    pub const fn new(l: T, a: T, b: T) -> Self {
+        Self { l, a, b }
+    }
+    pub fn lerp(self, other: Self, factor: T) -> Self {
+        let mix = |x: T, y: T| x + (y - x) * factor;
+        Self::new(
+            mix(self.l, other.l),
+            mix(self.a, other.a),
+            mix(self.b, other.b),
+        )
+    }
+}
+
+/// Linear sRGB to cone responses, from Ottosson's "A perceptual color space for image
+/// processing", 2020
+const SRGB_TO_LMS: [[f64; 3]; 3] = [
+    [0.412_221_470_8, 0.536_332_536_3, 0.051_445_992_9],
+    [0.211_903_498_2, 0.680_699_545_1, 0.107_396_956_6],
+    [0.088_302_461_9, 0.281_718_837_6, 0.629_978_700_5],
+];
+const LMS_TO_OKLAB: [[f64; 3]; 3] = [
+    [0.210_454_255_3, 0.793_617_785, -0.004_072_046_8],
+    [1.977_998_495_1, -2.428_592_205, 0.450_593_709_9],
+    [0.025_904_037_1, 0.782_771_766_2, -0.808_675_766],
+];
+const OKLAB_TO_LMS: [[f64; 3]; 3] = [
+    [1.0, 0.396_337_777_4, 0.215_803_757_3],
+    [1.0, -0.105_561_345_8, -0.063_854_172_8],
+    [1.0, -0.089_484_177_5, -1.291_485_548],
+];
+const LMS_TO_SRGB: [[f64; 3]; 3] = [
+    [4.076_741_662_1, -3.307_711_591_3, 0.230_969_929_2],
+    [-1.268_438_004_6, 2.609_757_401_1, -0.341_319_396_5],
+    [-0.004_196_086_3, -0.703_418_614_7, 1.707_614_701],
+];
+
+impl<T: Float> From<Color<T>> for Oklab<T> {
+    fn from(color: Color<T>) -> Self {
+        let lms = transform(matrix(SRGB_TO_LMS), [color.r, color.g, color.b]);
+        let [l, a, b] = transform(matrix(LMS_TO_OKLAB), lms.map(T::cbrt));
+        Self::new(l, a, b)
+    }
+}
+
+impl<T: Float> From<Oklab<T>> for Color<T> {
+    fn from(lab: Oklab<T>) -> Self {
+        let lms = transform(matrix(OKLAB_TO_LMS), [lab.l, lab.a, lab.b]);
+        let [r, g, b] = transform(matrix(LMS_TO_SRGB), lms.map(|v| v * v * v));
+        Color::new(r, g, b)
+    }
+}
+
+impl<T: Float> Oklch<T> {
+    pub const fn new(l: T, c: T, h: T) -> Self {
+        Self { l, c, h }
+    }
+    /// Blends along the shorter way around the hue circle, so gradients between saturated
+    /// colors stay saturated
+    pub fn lerp(self, other: Self, factor: T) -> Self {
+        let mix = |x: T, y: T| x + (y - x) * factor;
+        let full = c::<T>(360.0);
+        let mut delta = (other.h - self.h) % full;
+        if delta > c(180.0) {
+            delta = delta - full;
+        } else if delta < c(-180.0) {
+            delta = delta + full;
+        }
+        Self::new(
+            mix(self.l, other.l),
+            mix(self.c, other.c),
+            wrap_hue(self.h + delta * factor),
+        )
+    }
+}
+
+impl<T: Float> From<Oklab<T>> for Oklch<T> {
+    fn from(lab: Oklab<T>) -> Self {
+        Self::new(
+            lab.l,
+            lab.a.hypot(lab.b),
+            wrap_hue(lab.b.atan2(lab.a).to_degrees()),
+        )
+    }
+}
+
+impl<T: Float> From<Oklch<T>> for Oklab<T> {
+    fn from(lch: Oklch<T>) -> Self {
+        let (sin, cos) = lch.h.to_radians().sin_cos();
+        Self::new(lch.l, lch.c * cos, lch.c * sin)
+    }
+}
+
+impl<T: Float> From<Color<T>> for Oklch<T> {
+    fn from(color: Color<T>) -> Self {
+        Oklab::from(color).into()
+    }
+}
+
+impl<T: Float> From<Oklch<T>> for Color<T> {
+    fn from(lch: Oklch<T>) -> Self {
+        Oklab::from(lch).into()
+    }
+}
+
+impl<T: Float> Hsv<T> {
+    pub const fn new(h: T, s: T, v: T) -> Self {
+        Self { h, s, v }
+    }
+}
+
+impl<T: Float> From<Color<T>> for Hsv<T> {
+    fn from(color: Color<T>) -> Self {
+        let Color { r, g, b } = color.encode_srgb();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let s = if max > T::zero() {
+            (max - min) / max
+        } else {
+            T::zero()
+        };
+        Self::new(hue(r, g, b, max, min), s, max)
+    }
+}
+
+impl<T: Float> From<Hsv<T>> for Color<T> {
+    fn from(hsv: Hsv<T>) -> Self {
+        let chroma = hsv.v * hsv.s;
+        from_hue(hsv.h, chroma, hsv.v - chroma).decode_srgb()
+    }
+}
+
+impl<T: Float> Hsl<T> {
+    pub const fn new(h: T, s: T, l: T) -> Self {
+        Self { h, s, l }
+    }
+}
+
+impl<T: Float> From<Color<T>> for Hsl<T> {
+    fn from(color: Color<T>) -> Self {
+        let Color { r, g, b } = color.encode_srgb();
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let two = c::<T>(2.0);
+        let l = (max + min) / two;
+        let s = if l > T::zero() && l < T::one() {
+            (max - min) / (T::one() - (two * l - T::one()).abs())
+        } else {
+            T::zero()
+        };
+        Self::new(hue(r, g, b, max, min), s, l)
+    }
+}
+
+impl<T: Float> From<Hsl<T>> for Color<T> {
+    fn from(hsl: Hsl<T>) -> Self {
+        let two = c::<T>(2.0);
+        let chroma = (T::one() - (two * hsl.l - T::one()).abs()) * hsl.s;
+        from_hue(hsl.h, chroma, hsl.l - chroma / two).decode_srgb()
+    }
+}
+
+/// Hue in degrees shared by HSV and HSL, zero for greys
+fn hue<T: Float>(r: T, g: T, b: T, max: T, min: T) -> T {
+    let chroma = max - min;
+    if chroma <= T::zero() {
+        return T::zero();
+    }
+    let sextant = if max == r {
+        (g - b) / chroma
+    } else if max == g {
+        (b - r) / chroma + c(2.0)
+    } else {
+        (r - g) / chroma + c(4.0)
+    };
+    wrap_hue(sextant * c(60.0))
+}
+
+/// Encoded color with the given hue and chroma, lifted by `min` on every channel
+fn from_hue<T: Float>(h: T, chroma: T, min: T) -> Color<T> {
+    let sextant = wrap_hue(h) / c(60.0);
+    let x = chroma * (T::one() - (sextant % c(2.0) - T::one()).abs());
+    let zero = T::zero();
+    let (r, g, b) = match sextant.to_u8().unwrap_or(0) {
+        0 => (chroma, x, zero),
+        1 => (x, chroma, zero),
+        2 => (zero, chroma, x),
+        3 => (zero, x, chroma),
+        4 => (x, zero, chroma),
+        _ => (chroma, zero, x),
+    };
+    Color::new(r + min, g + min, b + min)
+}
+
+/// Brings a hue in degrees into [0, 360)
+fn wrap_hue<T: Float>(h: T) -> T {
+    let full = c::<T>(360.0);
+    let h = h % full;
+    if h < T::zero() {
+        h + full
+    } else {
+        h
+    }
+}
+
+impl<T: Float> Color<T> {
+    /// Blends towards `other` in [`Oklab`], for gradients that look evenly spaced
+    pub fn lerp_oklab(self, other: Self, factor: T) -> Self {
+        Oklab::from(self).lerp(other.into(), factor).into()
+    }
+    /// Blends towards `other` in [`Oklch`], going around the hue circle rather than through grey
+    pub fn lerp_oklch(self, other: Self, factor: T) -> Self {
+        Oklch::from(self).lerp(other.into(), factor).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Linear colors spread over the RGB cube, corners and greys included
+    fn colors() -> impl Iterator<Item = Color<f64>> {
+        let steps = [0.0, 0.01, 0.2, 0.5, 0.8, 1.0];
+        steps.into_iter().flat_map(move |r| {
+            steps
+                .into_iter()
+                .flat_map(move |g| steps.into_iter().map(move |b| Color::new(r, g, b)))
+        })
+    }
+
+    fn assert_close(a: Color<f64>, b: Color<f64>, what: &str) {
+        let close =
+            (a.r - b.r).abs() < 1e-6 && (a.g - b.g).abs() < 1e-6 && (a.b - b.b).abs() < 1e-6;
+        assert!(close, "{what}: expected {a:?}, got {b:?}");
+    }
+
+    #[test]
+    fn round_trips() {
+        for color in colors() {
+            assert_close(color, Oklab::from(color).into(), "Oklab");
+            assert_close(color, Oklch::from(color).into(), "Oklch");
+            assert_close(color, Hsv::from(color).into(), "HSV");
+            assert_close(color, Hsl::from(color).into(), "HSL");
+            let xyz = color.to_xyz();
+            assert_close(color, Color::from_xyz(xyz.x, xyz.y, xyz.z), "XYZ");
+        }
+    }
+
+    #[test]
+    fn reference_values() {
+        // From Björn Ottosson's post introducing Oklab
+        let lab = Oklab::from(Color::new(1.0, 0.0, 0.0));
+        assert!((lab.l - 0.627_955).abs() < 1e-4, "{lab:?}");
+        assert!((lab.a - 0.224_863).abs() < 1e-4, "{lab:?}");
+        assert!((lab.b - 0.125_846).abs() < 1e-4, "{lab:?}");
+        let white = Oklab::from(Color::new(1.0, 1.0, 1.0));
+        assert!((white.l - 1.0).abs() < 1e-4 && white.a.abs() < 1e-4 && white.b.abs() < 1e-4);
+
+        let hsv = Hsv::from(Color::new(0.0, 1.0, 0.0));
+        assert_close(
+            Color::new(hsv.h / 360.0, hsv.s, hsv.v),
+            Color::new(1.0 / 3.0, 1.0, 1.0),
+            "HSV",
+        );
+        let hsl = Hsl::from(Color::new(0.0, 0.0, 1.0));
+        assert_close(
+            Color::new(hsl.h / 360.0, hsl.s, hsl.l),
+            Color::new(2.0 / 3.0, 1.0, 0.5),
+            "HSL",
+        );
+    }
+
+    #[test]
+    fn gradients_keep_their_ends() {
+        let (red, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+        assert_close(red, red.lerp_oklab(blue, 0.0), "lerp_oklab");
+        assert_close(blue, red.lerp_oklab(blue, 1.0), "lerp_oklab");
+        assert_close(red, red.lerp_oklch(blue, 0.0), "lerp_oklch");
+        assert_close(blue, red.lerp_oklch(blue, 1.0), "lerp_oklch");
+        // Oklch takes the short way from red at 29° to blue at 264°, through magenta
+        let middle = Oklch::from(red.lerp_oklch(blue, 0.5));
+        assert!(middle.h > 264.0 || middle.h < 29.0, "{middle:?}");
+    }
+}
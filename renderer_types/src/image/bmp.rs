@@ -0,0 +1,274 @@
+//! Windows bitmaps, 24-bit without alpha and 32-bit with it
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, ByteReader, Channels, ImageError};
+use crate::prelude::*;
+
+const FILE_HEADER_SIZE: u32 = 14;
+/// `BITMAPINFOHEADER`, enough for 24-bit images
+const INFO_HEADER_SIZE: u32 = 40;
+/// `BITMAPV4HEADER`, which can say where alpha is
+const V4_HEADER_SIZE: u32 = 108;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+/// Where 32-bit pixels keep their channels, as bytes B, G, R, A in little-endian order
+const MASKS: [u32; 4] = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0xff00_0000];
+/// `LCS_sRGB`
+const SRGB_COLOR_SPACE: u32 = u32::from_be_bytes(*b"sRGB");
+/// 72 DPI
+const PIXELS_PER_METER: i32 = 2835;
+
+impl Buffer {
+    /// Writes a bitmap, 24-bit for [`Channels::Rgb`] and 32-bit with an alpha mask for
+    /// [`Channels::Rgba`]
+    pub fn write_bmp(&self, mut out: impl Write, channels: Channels) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a BMP");
+        let (bytes_per_pixel, header_size) = match channels {
+            Channels::Rgb => (3, INFO_HEADER_SIZE),
+            Channels::Rgba => (4, V4_HEADER_SIZE),
+        };
+        // Rows are padded to whole 4 bytes
+        let row_size = (self.width() * bytes_per_pixel).next_multiple_of(4);
+        let image_size = u32::try_from(row_size * self.height()).map_err(|_| too_large())?;
+        let offset = FILE_HEADER_SIZE + header_size;
+        let file_size = offset.checked_add(image_size).ok_or_else(too_large)?;
+        let width = i32::try_from(self.width()).map_err(|_| too_large())?;
+        let height = i32::try_from(self.height()).map_err(|_| too_large())?;
+
+        let mut header = Vec::with_capacity(offset as usize);
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&file_size.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&header_size.to_le_bytes());
+        header.extend_from_slice(&width.to_le_bytes());
+        // Positive heights store the bottom row first, which every reader understands
+        header.extend_from_slice(&height.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&(bytes_per_pixel as u16 * 8).to_le_bytes());
+        let compression = match channels {
+            Channels::Rgb => BI_RGB,
+            Channels::Rgba => BI_BITFIELDS,
+        };
+        header.extend_from_slice(&compression.to_le_bytes());
+        header.extend_from_slice(&image_size.to_le_bytes());
+        header.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+        header.extend_from_slice(&PIXELS_PER_METER.to_le_bytes());
+        // No palette
+        header.extend_from_slice(&[0; 8]);
+        if channels == Channels::Rgba {
+            MASKS
+                .iter()
+                .for_each(|mask| header.extend_from_slice(&mask.to_le_bytes()));
+            header.extend_from_slice(&SRGB_COLOR_SPACE.to_le_bytes());
+            // Endpoints and gamma, unused for sRGB
+            header.extend_from_slice(&[0; 48]);
+        }
+        out.write_all(&header)?;
+
+        let mut row = Vec::with_capacity(row_size);
+        for pixels in self.inner_buf().chunks(self.width().max(1)).rev() {
+            row.clear();
+            for p in pixels {
+                match channels {
+                    Channels::Rgb => row.extend_from_slice(&[p.b, p.g, p.r]),
+                    Channels::Rgba => row.extend_from_slice(&[p.b, p.g, p.r, p.a]),
+                }
+            }
+            row.resize(row_size, 0);
+            out.write_all(&row)?;
+        }
+        Ok(())
+    }
+    /// Reads a 24 or 32-bit bitmap
+    pub fn read_bmp(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+}
+
+/// Where a channel is in a 32-bit pixel
+#[derive(Clone, Copy)]
+struct Mask {
+    shift: u32,
+    max: u32,
+}
+
+impl Mask {
+    fn new(mask: u32) -> Option<Self> {
+        (mask != 0).then(|| Self {
+            shift: mask.trailing_zeros(),
+            max: mask >> mask.trailing_zeros(),
+        })
+    }
+    fn extract(self, pixel: u32) -> u8 {
+        // Masks can be up to 32 bits wide, too wide to scale in 32 bits
+        let (value, max) = (((pixel >> self.shift) & self.max) as u64, self.max as u64);
+        ((value * 255 + max / 2) / max) as u8
+    }
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Buffer, ImageError> {
+    let mut reader = ByteReader::new(bytes);
+    if reader.take(2)? != b"BM" {
+        return Err(ImageError::invalid("not a BMP file"));
+    }
+    reader.take(8)?;
+    let offset = reader.u32_le()? as usize;
+    let header_size = reader.u32_le()?;
+    let (width, height, bits, compression) = if header_size == 12 {
+        // OS/2's BITMAPCOREHEADER
+        let width = reader.u16_le()? as i32;
+        let height = reader.u16_le()? as i32;
+        reader.u16_le()?;
+        (width, height, reader.u16_le()?, BI_RGB)
+    } else if header_size >= INFO_HEADER_SIZE {
+        let width = reader.i32_le()?;
+        let height = reader.i32_le()?;
+        reader.u16_le()?;
+        let bits = reader.u16_le()?;
+        (width, height, bits, reader.u32_le()?)
+    } else {
+        return Err(ImageError::invalid(format!("header size {header_size}")));
+    };
+
+    let masks = match (bits, compression) {
+        (24, BI_RGB) => None,
+        // The fourth byte of 32-bit pixels isn't alpha without a mask saying so
+        (32, BI_RGB) => Some([MASKS[0], MASKS[1], MASKS[2], 0]),
+        (32, BI_BITFIELDS | BI_ALPHABITFIELDS) => {
+            // Masks follow the info header, where later headers have them as fields
+            reader.seek((FILE_HEADER_SIZE + INFO_HEADER_SIZE) as usize)?;
+            let mut masks = [0; 4];
+            let count = if header_size > INFO_HEADER_SIZE || compression == BI_ALPHABITFIELDS {
+                4
+            } else {
+                3
+            };
+            for mask in &mut masks[..count] {
+                *mask = reader.u32_le()?;
+            }
+            Some(masks)
+        }
+        (24 | 32, _) => {
+            return Err(ImageError::unsupported(format!(
+                "BMP compression {compression}"
+            )))
+        }
+        _ => return Err(ImageError::unsupported(format!("{bits}-bit BMP"))),
+    };
+
+    // Negative heights store the top row first
+    let top_down = height < 0;
+    let (width, height) = (
+        width.unsigned_abs() as usize,
+        height.unsigned_abs() as usize,
+    );
+    if width == 0 {
+        return Err(ImageError::invalid("zero width"));
+    }
+    pixel_count(width, height)?;
+    let bytes_per_pixel = bits as usize / 8;
+    let row_size = (width * bytes_per_pixel).next_multiple_of(4);
+    reader.seek(offset)?;
+    let data = reader.take(
+        row_size
+            .checked_mul(height)
+            .ok_or_else(|| ImageError::invalid("unexpected end of file"))?,
+    )?;
+
+    let channel_masks = masks.map(|masks| masks.map(Mask::new));
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = if top_down { y } else { height - 1 - y };
+        let row = &data[row * row_size..][..width * bytes_per_pixel];
+        pixels.extend(
+            row.chunks_exact(bytes_per_pixel)
+                .map(|p| match channel_masks {
+                    None => Rgba::new(p[2], p[1], p[0], u8::MAX),
+                    Some([r, g, b, a]) => {
+                        let pixel = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                        let channel = |mask: Option<Mask>| mask.map_or(0, |m| m.extract(pixel));
+                        let alpha = a.map_or(u8::MAX, |m| m.extract(pixel));
+                        Rgba::new(channel(r), channel(g), channel(b), alpha)
+                    }
+                }),
+        );
+    }
+    Ok(Buffer::from_pixels(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::{channels, opaque, test_image};
+
+    /// File and info headers of a bitmap with `masks` after them and no palette
+    fn header(width: i32, height: i32, bits: u16, compression: u32, masks: &[u32]) -> Vec<u8> {
+        let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + 4 * masks.len() as u32;
+        let mut bytes = b"BM".to_vec();
+        bytes.extend([0; 8]);
+        bytes.extend(offset.to_le_bytes());
+        bytes.extend(INFO_HEADER_SIZE.to_le_bytes());
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend(compression.to_le_bytes());
+        bytes.extend([0; 20]);
+        masks
+            .iter()
+            .for_each(|mask| bytes.extend(mask.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        // Rows of 5 pixels need padding at 24 bits
+        let image = test_image(5, 3);
+        let mut bytes = Vec::new();
+        image.write_bmp(&mut bytes, Channels::Rgb).unwrap();
+        assert_eq!(bytes.len(), 54 + 16 * 3);
+        assert_eq!(channels(&decode(&bytes).unwrap()), opaque(&image));
+
+        bytes.clear();
+        image.write_bmp(&mut bytes, Channels::Rgba).unwrap();
+        assert_eq!(bytes.len(), 122 + 20 * 3);
+        assert_eq!(channels(&decode(&bytes).unwrap()), channels(&image));
+    }
+
+    #[test]
+    fn reads_masks() {
+        // Top row first, with the fourth byte of 32-bit pixels being padding without a mask
+        let mut bytes = header(2, -1, 32, BI_RGB, &[]);
+        bytes.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        let image = decode(&bytes).unwrap();
+        assert_eq!(channels(&image), [[3, 2, 1, 255], [7, 6, 5, 255]]);
+
+        // Red over all 32 bits, green over 5 and blue over none
+        let mut bytes = header(2, 1, 32, BI_BITFIELDS, &[u32::MAX, 0x1f, 0]);
+        bytes.extend(u32::MAX.to_le_bytes());
+        bytes.extend(0x8000_0010u32.to_le_bytes());
+        let image = decode(&bytes).unwrap();
+        assert_eq!(channels(&image), [[255, 255, 0, 255], [128, 132, 0, 255]]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut truncated = header(2, 2, 24, BI_RGB, &[]);
+        truncated.extend([0; 15]);
+        assert!(matches!(decode(&truncated), Err(ImageError::Invalid(_))));
+        assert!(matches!(decode(b"P6"), Err(ImageError::Invalid(_))));
+        assert!(matches!(
+            decode(&header(1, 1, 16, BI_RGB, &[])),
+            Err(ImageError::Unsupported(_))
+        ));
+        assert!(matches!(
+            decode(&header(i32::MAX, i32::MIN, 32, BI_RGB, &[])),
+            Err(ImageError::Unsupported(_) | ImageError::Invalid(_))
+        ));
+    }
+}
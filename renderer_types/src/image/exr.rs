@@ -0,0 +1,628 @@
+//! OpenEXR, scanline images of any number of named half, float or integer channels. Layers are
+//! channels sharing a prefix, like `albedo.R`, `albedo.G` and `albedo.B`
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::ImageError;
+use crate::prelude::*;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+/// Version flag for attribute and channel names longer than 31 bytes
+const LONG_NAMES: u32 = 0x400;
+/// Shortest run worth encoding as one
+const MIN_RUN: usize = 3;
+/// Longest run or literal stretch a count byte holds
+const MAX_RUN: usize = 127;
+
+/// How the samples of a float channel are stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExrPrecision {
+    /// 16-bit floats, plenty for colors
+    #[default]
+    Half,
+    /// 32-bit floats, for depth and positions
+    Float,
+}
+
+/// How each block of scanlines is compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExrCompression {
+    #[default]
+    None,
+    /// Run-length encoding of the byte differences, one scanline at a time
+    Rle,
+    /// zlib, one scanline at a time. Needs the `zlib` feature
+    Zips,
+    /// zlib, 16 scanlines at a time. Needs the `zlib` feature
+    Zip,
+}
+
+impl ExrCompression {
+    /// Value of the `compression` attribute
+    fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Rle => 1,
+            Self::Zips => 2,
+            Self::Zip => 3,
+        }
+    }
+    fn lines_per_block(self) -> usize {
+        match self {
+            Self::None | Self::Rle | Self::Zips => 1,
+            Self::Zip => 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Samples {
+    Uint(Vec<u32>),
+    Half(Vec<u16>),
+    Float(Vec<f32>),
+}
+
+impl Samples {
+    fn floats(values: impl Iterator<Item = f32>, precision: ExrPrecision) -> Self {
+        match precision {
+            ExrPrecision::Half => Self::Half(values.map(to_half).collect()),
+            ExrPrecision::Float => Self::Float(values.collect()),
+        }
+    }
+    /// Value of the pixel type in the channel list
+    fn pixel_type(&self) -> i32 {
+        match self {
+            Self::Uint(_) => 0,
+            Self::Half(_) => 1,
+            Self::Float(_) => 2,
+        }
+    }
+    /// Appends the little-endian samples of `range`
+    fn extend_bytes(&self, range: std::ops::Range<usize>, out: &mut Vec<u8>) {
+        match self {
+            Self::Uint(s) => out.extend(s[range].iter().flat_map(|v| v.to_le_bytes())),
+            Self::Half(s) => out.extend(s[range].iter().flat_map(|v| v.to_le_bytes())),
+            Self::Float(s) => out.extend(s[range].iter().flat_map(|v| v.to_le_bytes())),
+        }
+    }
+}
+
+/// A scanline EXR put together from the passes of a render, written with [`Exr::write`].
+///
+/// Channels are kept by name, so adding one twice replaces it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exr {
+    width: usize,
+    height: usize,
+    channels: BTreeMap<String, Samples>,
+    compression: ExrCompression,
+}
+
+impl Exr {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels: BTreeMap::new(),
+            compression: ExrCompression::default(),
+        }
+    }
+    pub fn with_compression(mut self, compression: ExrCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+    /// Adds `layer.R`, `layer.G` and `layer.B`, or just `R`, `G` and `B` if `layer` is empty,
+    /// which is where readers look for the main image
+    pub fn with_color(
+        self,
+        layer: &str,
+        image: &Buffer<Colorf32>,
+        precision: ExrPrecision,
+    ) -> Self {
+        self.with_components(layer, ["R", "G", "B"], image, precision, |c| {
+            [c.r, c.g, c.b]
+        })
+    }
+    /// Adds `layer.X`, `layer.Y` and `layer.Z`, like for normals or positions
+    pub fn with_vector(self, layer: &str, image: &Buffer<Vec3f>, precision: ExrPrecision) -> Self {
+        self.with_components(layer, ["X", "Y", "Z"], image, precision, |v| {
+            [v.x, v.y, v.z]
+        })
+    }
+    /// Adds a single channel, named in full like `depth.Z`
+    pub fn with_channel(
+        mut self,
+        name: &str,
+        image: &Buffer<f32>,
+        precision: ExrPrecision,
+    ) -> Self {
+        self.check_dimensions(name, image.dimensions());
+        let samples = Samples::floats(image.iter().copied(), precision);
+        self.channels.insert(name.to_owned(), samples);
+        self
+    }
+    /// Adds a channel of 32-bit unsigned integers, like object IDs
+    pub fn with_ids(mut self, name: &str, image: &Buffer<u32>) -> Self {
+        self.check_dimensions(name, image.dimensions());
+        let samples = Samples::Uint(image.inner_buf().to_vec());
+        self.channels.insert(name.to_owned(), samples);
+        self
+    }
+
+    fn with_components<P>(
+        mut self,
+        layer: &str,
+        components: [&str; 3],
+        image: &Buffer<P>,
+        precision: ExrPrecision,
+        split: impl Fn(&P) -> [f32; 3],
+    ) -> Self {
+        self.check_dimensions(layer, image.dimensions());
+        for (i, component) in components.into_iter().enumerate() {
+            let name = match layer {
+                "" => component.to_owned(),
+                layer => format!("{layer}.{component}"),
+            };
+            let samples = Samples::floats(image.iter().map(|p| split(p)[i]), precision);
+            self.channels.insert(name, samples);
+        }
+        self
+    }
+    fn check_dimensions(&self, name: &str, dimensions: Vec2<usize>) {
+        assert!(
+            dimensions == Vec2::new(self.width, self.height),
+            "{name:?} is {}x{}, but the EXR is {}x{}",
+            dimensions.x,
+            dimensions.y,
+            self.width,
+            self.height
+        );
+    }
+
+    /// Writes the file at `path`, replacing it if it exists
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+    /// Writes a single-part scanline file with the channels in alphabetical order, as the format
+    /// requires
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if self.width == 0 || self.height == 0 {
+            return Err(invalid("an EXR can't be empty"));
+        }
+        let max_x = i32::try_from(self.width - 1).map_err(|_| invalid("too large for an EXR"))?;
+        let max_y = i32::try_from(self.height - 1).map_err(|_| invalid("too large for an EXR"))?;
+        if self
+            .channels
+            .keys()
+            .any(|name| name.is_empty() || name.len() > 255 || name.contains('\0'))
+        {
+            return Err(invalid("EXR channel names are 1 to 255 bytes without NUL"));
+        }
+
+        let mut channel_list = Vec::new();
+        for (name, samples) in &self.channels {
+            channel_list.extend(name.as_bytes());
+            channel_list.push(0);
+            channel_list.extend(samples.pixel_type().to_le_bytes());
+            // Not perceptually linear, three reserved bytes, and no subsampling
+            channel_list.extend([0; 4]);
+            channel_list.extend([1i32, 1].into_iter().flat_map(i32::to_le_bytes));
+        }
+        channel_list.push(0);
+        let window: Vec<u8> = [0, 0, max_x, max_y]
+            .into_iter()
+            .flat_map(i32::to_le_bytes)
+            .collect();
+
+        let mut header = Vec::new();
+        header.extend(MAGIC);
+        let long_names = self.channels.keys().any(|name| name.len() > 31);
+        let version = VERSION | if long_names { LONG_NAMES } else { 0 };
+        header.extend(version.to_le_bytes());
+        attribute(&mut header, "channels", "chlist", &channel_list);
+        attribute(
+            &mut header,
+            "compression",
+            "compression",
+            &[self.compression.id()],
+        );
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        // Increasing y
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+
+        let lines = self.compression.lines_per_block();
+        let blocks = (0..self.height)
+            .step_by(lines)
+            .map(|y| {
+                let end = (y + lines).min(self.height);
+                let mut raw = Vec::new();
+                for row in y..end {
+                    let range = row * self.width..(row + 1) * self.width;
+                    for samples in self.channels.values() {
+                        samples.extend_bytes(range.clone(), &mut raw);
+                    }
+                }
+                Ok((y, self.compress(raw)?))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // The offset table points at every block from the start of the file
+        let mut offset = (header.len() + blocks.len() * 8) as u64;
+        for (_, data) in &blocks {
+            header.extend(offset.to_le_bytes());
+            offset += 8 + data.len() as u64;
+        }
+        out.write_all(&header)?;
+        for (y, data) in blocks {
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&(data.len() as u32).to_le_bytes())?;
+            out.write_all(&data)?;
+        }
+        Ok(())
+    }
+
+    /// Compresses a block, leaving it as it is if that doesn't make it smaller, which readers
+    /// tell from its size
+    fn compress(&self, raw: Vec<u8>) -> io::Result<Vec<u8>> {
+        let compressed = match self.compression {
+            ExrCompression::None => return Ok(raw),
+            ExrCompression::Rle => encode_runs(&predict(&raw)),
+            #[cfg(feature = "zlib")]
+            ExrCompression::Zips | ExrCompression::Zip => {
+                miniz_oxide::deflate::compress_to_vec_zlib(&predict(&raw), 6)
+            }
+            #[cfg(not(feature = "zlib"))]
+            ExrCompression::Zips | ExrCompression::Zip => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "zlib compression needs the zlib feature",
+                ))
+            }
+        };
+        Ok(if compressed.len() < raw.len() {
+            compressed
+        } else {
+            raw
+        })
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for text in [name, kind] {
+        header.extend(text.as_bytes());
+        header.push(0);
+    }
+    header.extend((value.len() as u32).to_le_bytes());
+    header.extend(value);
+}
+
+/// Puts the even bytes before the odd ones, which mostly splits the low bytes of samples from the
+/// high ones, then replaces every byte with its difference to the one before
+fn predict(raw: &[u8]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = raw
+        .iter()
+        .step_by(2)
+        .chain(raw.iter().skip(1).step_by(2))
+        .copied()
+        .collect();
+    for i in (1..bytes.len()).rev() {
+        bytes[i] = bytes[i].wrapping_sub(bytes[i - 1]).wrapping_add(128);
+    }
+    bytes
+}
+
+/// Runs of a repeated byte are a count of one less than the run followed by the byte, literal
+/// stretches are a negative count followed by the bytes
+fn encode_runs(values: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < values.len() {
+        // Find the next run long enough to be worth it
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(MAX_RUN + 1)
+                .take_while(|&&v| v == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+            run_length = 0;
+        }
+        for literal in values[i..run_start].chunks(MAX_RUN) {
+            out.push((literal.len() as i8).wrapping_neg() as u8);
+            out.extend_from_slice(literal);
+        }
+        if run_length > 0 {
+            out.extend([run_length as u8 - 1, values[run_start]]);
+        }
+        i = run_start + run_length;
+    }
+    out
+}
+
+/// Bits of the nearest half precision float, rounding ties to even. Values too large for one
+/// become infinite
+fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16) as u16 & 0x8000;
+    let exponent = (bits >> 23) as i32 & 0xff;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity, or NaN with a bit of its payload set so it stays one
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    // Rounds away the low `shift` bits of `mantissa`, to even on a tie
+    let round = |mantissa: u32, shift: u32| {
+        let kept = mantissa >> shift;
+        let rest = mantissa & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        kept + (rest > half || (rest == half && kept & 1 == 1)) as u32
+    };
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent > 0 {
+        // Rounding up can carry into the exponent, up to infinity, which is still right
+        let bits = ((exponent as u32) << 10) + round(mantissa, 13);
+        sign | bits as u16
+    } else if exponent >= -10 {
+        // Subnormal, with the implicit leading bit made explicit
+        sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16
+    } else {
+        sign
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The value of half precision bits, the way readers decode them
+    fn from_half(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = (half >> 10 & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+        sign * match exponent {
+            0 => mantissa * (-24f32).exp2(),
+            0x1f if mantissa == 0.0 => f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * ((exponent - 15) as f32).exp2(),
+        }
+    }
+
+    #[test]
+    fn converts_to_half() {
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(to_half(0.1), 0x2e66);
+        assert_eq!(to_half(-0.0), 0x8000);
+        assert_eq!(to_half(65504.0), 0x7bff);
+        assert_eq!(to_half(1e6), 0x7c00);
+        assert_eq!(to_half(f32::NEG_INFINITY), 0xfc00);
+        assert!(from_half(to_half(f32::NAN)).is_nan());
+        // Smallest subnormal, and values below half of it
+        assert_eq!(to_half((-24f32).exp2()), 0x0001);
+        assert_eq!(to_half((-26f32).exp2()), 0);
+        assert_eq!(to_half(1e-10), 0);
+
+        // Every finite half comes back as itself
+        for half in (0..=u16::MAX).filter(|h| h & 0x7c00 != 0x7c00) {
+            assert_eq!(to_half(from_half(half)), half, "{half:#06x}");
+        }
+    }
+
+    #[test]
+    fn rounds_ties_to_even() {
+        let ulp = (-10f32).exp2();
+        // Halfway between 1 and the next half, and between that and the one after
+        assert_eq!(to_half(1.0 + ulp / 2.0), 0x3c00);
+        assert_eq!(to_half(1.0 + ulp * 1.5), 0x3c02);
+        assert_eq!(to_half(1.0 + ulp * 0.51), 0x3c01);
+        // Halfway between the largest half and infinity rounds up, to an even mantissa
+        assert_eq!(to_half(65520.0), 0x7c00);
+        assert_eq!(to_half(65519.0), 0x7bff);
+        // Rounding subnormals, and up into the normal range
+        assert_eq!(to_half((-25f32).exp2()), 0);
+        assert_eq!(to_half((-25f32).exp2() * 3.0), 0x0002);
+        assert_eq!(to_half((-14f32).exp2() * (1.0 - ulp / 4.0)), 0x0400);
+    }
+
+    /// Reads the header of `bytes` up to the offset table, returning where the table starts and
+    /// the attributes by name
+    fn header(bytes: &[u8]) -> (usize, BTreeMap<String, Vec<u8>>) {
+        assert_eq!(bytes[..4], MAGIC);
+        let mut pos = 8;
+        let text = |pos: &mut usize| {
+            let end = *pos + bytes[*pos..].iter().position(|&b| b == 0).unwrap();
+            let text = String::from_utf8(bytes[*pos..end].to_vec()).unwrap();
+            *pos = end + 1;
+            text
+        };
+        let mut attributes = BTreeMap::new();
+        loop {
+            let name = text(&mut pos);
+            if name.is_empty() {
+                return (pos, attributes);
+            }
+            text(&mut pos);
+            let size = u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+            attributes.insert(name, bytes[pos + 4..pos + 4 + size].to_vec());
+            pos += 4 + size;
+        }
+    }
+
+    /// The first scanline and data of every block, found through the offset table
+    fn blocks(bytes: &[u8], count: usize) -> Vec<(i32, &[u8])> {
+        let (table, _) = header(bytes);
+        (0..count)
+            .map(|i| {
+                let at = table + i * 8;
+                let offset = u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+                let y = i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+                let size = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+                (y, &bytes[offset + 8..offset + 8 + size as usize])
+            })
+            .collect()
+    }
+
+    fn test_exr(width: usize, height: usize) -> Exr {
+        let depth = Buffer::new_with(width, height, |x, y| (x + 10 * y) as f32);
+        let ids = Buffer::new_with(width, height, |x, y| (x * y) as u32 | 0x100);
+        let color = Buffer::new_with(width, height, |x, _| Color::new(x as f32, 0.5, 2.0));
+        Exr::new(width, height)
+            .with_channel("depth.Z", &depth, ExrPrecision::Float)
+            .with_ids("id", &ids)
+            .with_color("", &color, ExrPrecision::Half)
+    }
+
+    #[test]
+    fn lays_out_scanlines() {
+        let mut bytes = Vec::new();
+        test_exr(3, 2).write(&mut bytes).unwrap();
+        let (_, attributes) = header(&bytes);
+        assert_eq!(attributes["compression"], [0]);
+        assert_eq!(
+            attributes["dataWindow"],
+            [0i32, 0, 2, 1].map(i32::to_le_bytes).concat()
+        );
+        // Channels in alphabetical order, which is uppercase first. Every name is followed by 16
+        // bytes of pixel type, flags and subsampling
+        let mut list = &attributes["channels"][..];
+        let mut names = Vec::new();
+        while list[0] != 0 {
+            let end = list.iter().position(|&b| b == 0).unwrap();
+            names.push(String::from_utf8(list[..end].to_vec()).unwrap());
+            list = &list[end + 17..];
+        }
+        assert_eq!(names, ["B", "G", "R", "depth.Z", "id"]);
+
+        let blocks = blocks(&bytes, 2);
+        for (y, (first, data)) in blocks.into_iter().enumerate() {
+            assert_eq!(first, y as i32);
+            // A row of every channel in turn
+            let mut expected = Vec::new();
+            for value in [2.0, 0.5] {
+                expected.extend([to_half(value); 3].map(u16::to_le_bytes).concat());
+            }
+            expected.extend([0.0, 1.0, 2.0].map(|x| to_half(x).to_le_bytes()).concat());
+            let depth = [0.0, 1.0, 2.0].map(|x: f32| (x + 10.0 * y as f32).to_le_bytes());
+            expected.extend(depth.concat());
+            let ids = [0, 1, 2].map(|x: u32| ((x * y as u32) | 0x100).to_le_bytes());
+            expected.extend(ids.concat());
+            assert_eq!(data, expected);
+        }
+        // The file ends with the last block
+        let (table, _) = header(&bytes);
+        assert_eq!(bytes.len(), table + 2 * 8 + 2 * (8 + 3 * (3 * 2 + 4 + 4)));
+    }
+
+    /// Undoes [`predict`]
+    fn unpredict(bytes: &[u8]) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        for i in 1..bytes.len() {
+            bytes[i] = bytes[i].wrapping_add(bytes[i - 1]).wrapping_sub(128);
+        }
+        let (even, odd) = bytes.split_at(bytes.len().div_ceil(2));
+        (0..bytes.len())
+            .map(|i| if i % 2 == 0 { even[i / 2] } else { odd[i / 2] })
+            .collect()
+    }
+
+    /// Undoes [`encode_runs`]
+    fn decode_runs(mut bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        while let [count, rest @ ..] = bytes {
+            let count = *count as i8;
+            if count < 0 {
+                let length = -(count as isize) as usize;
+                out.extend(&rest[..length]);
+                bytes = &rest[length..];
+            } else {
+                out.extend(std::iter::repeat_n(rest[0], count as usize + 1));
+                bytes = &rest[1..];
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn compresses_blocks() {
+        let (width, height) = (40, 20);
+        let mut raw = Vec::new();
+        test_exr(width, height).write(&mut raw).unwrap();
+        let raw_blocks = blocks(&raw, height);
+
+        let mut rle = Vec::new();
+        test_exr(width, height)
+            .with_compression(ExrCompression::Rle)
+            .write(&mut rle)
+            .unwrap();
+        assert_eq!(header(&rle).1["compression"], [1]);
+        for ((y, data), (raw_y, raw_data)) in blocks(&rle, height).into_iter().zip(&raw_blocks) {
+            assert_eq!(y, *raw_y);
+            assert!(data.len() < raw_data.len());
+            assert_eq!(unpredict(&decode_runs(data)), *raw_data);
+        }
+
+        // Blocks of 16 scanlines, the last one cut short
+        #[cfg(feature = "zlib")]
+        {
+            let mut zip = Vec::new();
+            test_exr(width, height)
+                .with_compression(ExrCompression::Zip)
+                .write(&mut zip)
+                .unwrap();
+            let blocks = blocks(&zip, 2);
+            for ((y, data), lines) in blocks.into_iter().zip([0..16, 16..20]) {
+                assert_eq!(y, lines.start as i32);
+                let inflated = miniz_oxide::inflate::decompress_to_vec_zlib(data).unwrap();
+                let expected: Vec<u8> = raw_blocks[lines]
+                    .iter()
+                    .flat_map(|(_, data)| data.iter().copied())
+                    .collect();
+                assert_eq!(unpredict(&inflated), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn runs_round_trip() {
+        let values: Vec<u8> = (0..1000)
+            .map(|i| if i % 300 < 200 { 7 } else { i as u8 })
+            .collect();
+        let encoded = encode_runs(&values);
+        assert!(encoded.len() < values.len() / 2);
+        assert_eq!(decode_runs(&encoded), values);
+        assert_eq!(unpredict(&predict(&values)), values);
+    }
+}
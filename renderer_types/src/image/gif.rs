@@ -0,0 +1,392 @@
+//! Animated GIFs, written a frame at a time with a palette of its own for every frame
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use super::quantize::Palette;
+use crate::prelude::*;
+
+/// Codes are at most 12 bits
+const MAX_CODES: u16 = 1 << 12;
+/// Pixels with less alpha than this are left transparent, as GIFs have no partial transparency
+const OPAQUE: u8 = 128;
+const TRAILER: u8 = 0x3b;
+
+/// How an animated GIF is written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GifOptions {
+    /// Time every frame is shown for, in hundredths of a second
+    pub delay: u16,
+    /// Whether the animation starts over after the last frame
+    pub looping: bool,
+    /// Spread the difference to the nearest palette color over neighbouring pixels, which trades
+    /// banding for noise
+    pub dither: bool,
+}
+
+impl Default for GifOptions {
+    fn default() -> Self {
+        Self {
+            delay: 4,
+            looping: true,
+            dither: true,
+        }
+    }
+}
+
+/// Writes the frames of an animated GIF as they come. The file is only complete once
+/// [`GifWriter::finish`] is called
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    options: GifOptions,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header of a `width`x`height` animation
+    pub fn new(mut out: W, width: usize, height: usize, options: GifOptions) -> io::Result<Self> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a GIF");
+        let w = u16::try_from(width).map_err(|_| too_large())?;
+        let h = u16::try_from(height).map_err(|_| too_large())?;
+        out.write_all(b"GIF89a")?;
+        out.write_all(&w.to_le_bytes())?;
+        out.write_all(&h.to_le_bytes())?;
+        // No global palette, as every frame has its own, background color 0 and square pixels
+        out.write_all(&[0, 0, 0])?;
+        if options.looping {
+            // The Netscape extension with a loop count of 0, which repeats forever
+            out.write_all(&[0x21, 0xff, 11])?;
+            out.write_all(b"NETSCAPE2.0")?;
+            out.write_all(&[3, 1, 0, 0, 0])?;
+        }
+        Ok(Self {
+            out,
+            width,
+            height,
+            options,
+        })
+    }
+    /// Quantizes `frame` to at most 256 colors with median cut and writes it
+    pub fn write_frame(&mut self, frame: &Buffer) -> io::Result<()> {
+        if frame.dimensions() != Vec2::new(self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frames must be as large as the animation",
+            ));
+        }
+        let transparent = frame.iter().any(|p| p.a < OPAQUE);
+        let rgb: Vec<[u8; 3]> = frame.iter().map(|p| [p.r, p.g, p.b]).collect();
+        let opaque = frame
+            .iter()
+            .filter(|p| p.a >= OPAQUE)
+            .map(|p| [p.r, p.g, p.b]);
+        // The last index is kept for transparent pixels
+        let mut palette = Palette::median_cut(opaque, if transparent { 255 } else { 256 });
+        let mut indices = palette.indices(&rgb, self.width, self.options.dither);
+        let transparent_index = palette.colors().len() as u8;
+        if transparent {
+            for (index, pixel) in indices.iter_mut().zip(frame.iter()) {
+                if pixel.a < OPAQUE {
+                    *index = transparent_index;
+                }
+            }
+        }
+        // Palettes have a power of two colors, at least 2
+        let used = palette.colors().len() + transparent as usize;
+        let bits = used.next_power_of_two().trailing_zeros().max(1);
+
+        // Graphic control extension with the delay, and how to clear the frame before the next.
+        // Transparent frames are cleared to the background, opaque ones cover the next anyway
+        let (disposal, flags) = if transparent { (2, 1) } else { (1, 0) };
+        let out = &mut self.out;
+        out.write_all(&[0x21, 0xf9, 4, disposal << 2 | flags])?;
+        out.write_all(&self.options.delay.to_le_bytes())?;
+        out.write_all(&[if transparent { transparent_index } else { 0 }, 0])?;
+        // Image descriptor covering the whole animation, with a palette of 2^bits colors
+        out.write_all(&[0x2c, 0, 0, 0, 0])?;
+        out.write_all(&(self.width as u16).to_le_bytes())?;
+        out.write_all(&(self.height as u16).to_le_bytes())?;
+        out.write_all(&[0x80 | (bits as u8 - 1)])?;
+        let mut table = vec![0; 3 << bits];
+        for (entry, color) in table.chunks_exact_mut(3).zip(palette.colors()) {
+            entry.copy_from_slice(color);
+        }
+        out.write_all(&table)?;
+
+        let min_code_size = bits.max(2) as u8;
+        out.write_all(&[min_code_size])?;
+        for block in compress(&indices, min_code_size).chunks(255) {
+            out.write_all(&[block.len() as u8])?;
+            out.write_all(block)?;
+        }
+        out.write_all(&[0])
+    }
+    /// Ends the file and returns what it was written to
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[TRAILER])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Variable length LZW, with codes packed from the lowest bit up. Codes grow a bit when the
+/// decoder's next entry would need it, and start over once 12 bits run out
+fn compress(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut bits = BitWriter::default();
+    let mut size = min_code_size + 1;
+    let mut next = clear + 2;
+    let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+    bits.write(clear, size);
+
+    let Some((&first, rest)) = indices.split_first() else {
+        bits.write(end, size);
+        return bits.finish();
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = codes.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+        bits.write(prefix, size);
+        if next == MAX_CODES {
+            bits.write(clear, size);
+            codes.clear();
+            size = min_code_size + 1;
+            next = clear + 2;
+        } else {
+            codes.insert((prefix, index), next);
+            next += 1;
+            // The decoder adds its entries a code later
+            if next > 1 << size && size < 12 {
+                size += 1;
+            }
+        }
+        prefix = index as u16;
+    }
+    bits.write(prefix, size);
+    // The decoder adds an entry for the last code too, which can make the end code wider
+    if next == 1 << size && size < 12 {
+        size += 1;
+    }
+    bits.write(end, size);
+    bits.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    pending: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.pending |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.bytes.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.pending as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Undoes [`compress`] the way GIF readers do
+    fn decompress(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> {
+            // Clear and end take up two entries
+            (0..clear + 2).map(|i| vec![i as u8]).collect()
+        };
+        let mut table = reset();
+        let mut size = min_code_size + 1;
+        let mut previous: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        let mut bit = 0;
+        loop {
+            let code = (0..size as usize).fold(0, |code, i| {
+                let at = bit + i;
+                code | ((data[at / 8] as usize >> (at % 8)) & 1) << i
+            });
+            bit += size as usize;
+            if code == clear {
+                table = reset();
+                size = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code), &previous) {
+                (Some(entry), _) => entry.clone(),
+                // The code being defined by this very step
+                (None, Some(previous)) if code == table.len() => {
+                    [previous.as_slice(), &previous[..1]].concat()
+                }
+                _ => panic!("code {code} isn't in the table"),
+            };
+            if let Some(previous) = previous {
+                if table.len() < MAX_CODES as usize {
+                    table.push([previous.as_slice(), &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            out.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    /// Noise that repeats rarely enough to fill the code table many times over
+    fn noise(len: usize, colors: u32) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((state >> 16) % colors) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compress_round_trips() {
+        for (indices, min_code_size) in [
+            (vec![], 2),
+            (vec![3], 2),
+            (vec![0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1], 2),
+            (vec![7; 10_000], 3),
+            // Well over 4096 codes, so the table is cleared along the way
+            (noise(50_000, 4), 2),
+            (noise(50_000, 256), 8),
+        ] {
+            let compressed = compress(&indices, min_code_size);
+            assert_eq!(decompress(&compressed, min_code_size), indices);
+        }
+    }
+
+    struct Frame {
+        transparent: Option<u8>,
+        indices: Vec<u8>,
+        palette: Vec<[u8; 3]>,
+    }
+
+    fn frames(gif: &[u8]) -> Vec<Frame> {
+        let mut pos = 13;
+        let sub_blocks = |pos: &mut usize| {
+            let mut data = Vec::new();
+            while gif[*pos] != 0 {
+                let len = gif[*pos] as usize;
+                data.extend_from_slice(&gif[*pos + 1..*pos + 1 + len]);
+                *pos += 1 + len;
+            }
+            *pos += 1;
+            data
+        };
+        let mut frames = Vec::new();
+        let mut transparent = None;
+        loop {
+            match gif[pos] {
+                0x21 => {
+                    let label = gif[pos + 1];
+                    pos += 2;
+                    let data = sub_blocks(&mut pos);
+                    if label == 0xf9 {
+                        transparent = (data[0] & 1 == 1).then_some(data[3]);
+                    }
+                }
+                0x2c => {
+                    let packed = gif[pos + 9];
+                    let colors = 2 << (packed & 7);
+                    pos += 10;
+                    let palette = gif[pos..pos + 3 * colors]
+                        .chunks_exact(3)
+                        .map(|c| [c[0], c[1], c[2]])
+                        .collect();
+                    pos += 3 * colors;
+                    let min_code_size = gif[pos];
+                    pos += 1;
+                    let indices = decompress(&sub_blocks(&mut pos), min_code_size);
+                    frames.push(Frame {
+                        transparent: transparent.take(),
+                        indices,
+                        palette,
+                    });
+                }
+                TRAILER => return frames,
+                byte => panic!("unexpected block {byte:#x}"),
+            }
+        }
+    }
+
+    #[test]
+    fn writes_frames_and_transparency() {
+        let (width, height) = (200, 150);
+        let options = GifOptions {
+            dither: false,
+            ..GifOptions::default()
+        };
+        let mut writer = GifWriter::new(Vec::new(), width, height, options).unwrap();
+        // Few enough colors to be kept exactly, scattered enough to take well over 4096 codes
+        let scattered = noise(width * height, 64);
+        let color = |i: u8| [i % 4 * 80, i / 4 % 4 * 80, i / 16 * 80];
+        let opaque = Buffer::new_with(width, height, |x, y| {
+            let [r, g, b] = color(scattered[y * width + x]);
+            Rgba::new(r, g, b, 255)
+        });
+        let holes = Buffer::new_with(width, height, |x, y| {
+            let [r, g, b] = color(scattered[y * width + x] / 2);
+            let a = if (x + y) % 3 == 0 { 0 } else { 255 };
+            Rgba::new(r, g, b, a)
+        });
+        writer.write_frame(&opaque).unwrap();
+        writer.write_frame(&holes).unwrap();
+        let gif = writer.finish().unwrap();
+
+        let frames = frames(&gif);
+        assert_eq!(frames.len(), 2);
+        let Frame {
+            transparent,
+            indices,
+            palette,
+        } = &frames[0];
+        assert_eq!(*transparent, None);
+        for (pixel, &index) in opaque.iter().zip(indices) {
+            assert_eq!(palette[index as usize], [pixel.r, pixel.g, pixel.b]);
+        }
+
+        let Frame {
+            transparent,
+            indices,
+            palette,
+        } = &frames[1];
+        let transparent = transparent.expect("the second frame has transparent pixels");
+        assert_eq!(indices.len(), width * height);
+        for (pixel, &index) in holes.iter().zip(indices) {
+            if pixel.a == 0 {
+                assert_eq!(index, transparent);
+            } else {
+                assert_ne!(index, transparent);
+                assert_eq!(palette[index as usize], [pixel.r, pixel.g, pixel.b]);
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Reading and writing [`Buffer`]s as image files. Pixels are read through the fields of
+//! [`Rgba`], so files come out the same whichever order the channels have in memory
+
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::prelude::*;
+
+mod bmp;
+mod exr;
+mod gif;
+mod netpbm;
+mod pfm;
+#[cfg(feature = "png")]
+mod png;
+mod qoi;
+mod quantize;
+mod radiance;
+mod tga;
+mod y4m;
+
+#[cfg(feature = "png")]
+pub use self::png::{Png, PngOptions};
+pub use exr::{Exr, ExrCompression, ExrPrecision};
+pub use gif::{GifOptions, GifWriter};
+pub use netpbm::PpmEncoding;
+pub use qoi::Qoi;
+pub use y4m::{ChromaSubsampling, Y4mOptions, Y4mWriter, YuvMatrix};
+
+/// Channels a writer stores, for formats that can go without alpha
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Channels {
+    Rgb,
+    #[default]
+    Rgba,
+}
+
+impl Channels {
+    /// [`Channels::Rgb`] if every pixel is opaque, so nothing is lost without alpha
+    pub fn of(buffer: &Buffer) -> Self {
+        if buffer.iter().all(|p| p.a == u8::MAX) {
+            Self::Rgb
+        } else {
+            Self::Rgba
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Binary PPM, or PGM when reading
+    Ppm,
+    /// Netpbm's format with alpha
+    Pam,
+    Bmp,
+    Tga,
+    /// Needs the `png` feature
+    Png,
+    Qoi,
+}
+
+impl ImageFormat {
+    pub const ALL: [Self; 6] = [
+        Self::Ppm,
+        Self::Pam,
+        Self::Bmp,
+        Self::Tga,
+        Self::Png,
+        Self::Qoi,
+    ];
+    /// File extensions, the first being the one to write
+    pub const fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Ppm => &["ppm", "pgm", "pnm"],
+            Self::Pam => &["pam"],
+            Self::Bmp => &["bmp"],
+            Self::Tga => &["tga"],
+            Self::Png => &["png"],
+            Self::Qoi => &["qoi"],
+        }
+    }
+    /// Format going by the extension of `path`, ignoring case
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        Self::ALL.into_iter().find(|format| {
+            format
+                .extensions()
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(extension))
+        })
+    }
+    /// Format going by the first bytes of a file. TGA has no signature to go by
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [b'P', b'7', ..] => Some(Self::Pam),
+            [b'P', b'2' | b'3' | b'5' | b'6', ..] => Some(Self::Ppm),
+            [b'B', b'M', ..] => Some(Self::Bmp),
+            [0x89, b'P', b'N', b'G', ..] => Some(Self::Png),
+            [b'q', b'o', b'i', b'f', ..] => Some(Self::Qoi),
+            _ => None,
+        }
+    }
+}
+
+/// Formats that hold floating point radiance rather than display values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrFormat {
+    /// Portable Float Map, 32-bit floats
+    Pfm,
+    /// Radiance `.hdr`, 8-bit mantissas sharing an exponent
+    Radiance,
+}
+
+impl HdrFormat {
+    pub const ALL: [Self; 2] = [Self::Pfm, Self::Radiance];
+    /// File extensions, the first being the one to write
+    pub const fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Self::Pfm => &["pfm"],
+            Self::Radiance => &["hdr", "pic", "rgbe"],
+        }
+    }
+    /// Format going by the extension of `path`, ignoring case
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        Self::ALL.into_iter().find(|format| {
+            format
+                .extensions()
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(extension))
+        })
+    }
+    /// Format going by the first bytes of a file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [b'P', b'F' | b'f', b'\n' | b'\r' | b' ', ..] => Some(Self::Pfm),
+            [b'#', b'?', ..] => Some(Self::Radiance),
+            _ => None,
+        }
+    }
+}
+
+/// Why an image couldn't be read or written
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// The file isn't what its format says it should be
+    Invalid(String),
+    /// Valid, but uses something that isn't implemented
+    Unsupported(String),
+}
+
+impl ImageError {
+    pub(crate) fn invalid(message: impl Into<String>) -> Self {
+        Self::Invalid(message.into())
+    }
+    pub(crate) fn unsupported(message: impl Into<String>) -> Self {
+        Self::Unsupported(message.into())
+    }
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => err.fmt(f),
+            Self::Invalid(message) => write!(f, "invalid image: {message}"),
+            Self::Unsupported(message) => write!(f, "unsupported image: {message}"),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Little-endian reads from the bytes of a file, failing at its end instead of panicking
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+    pub(crate) fn pos(&self) -> usize {
+        self.pos
+    }
+    /// Everything that hasn't been read yet
+    pub(crate) fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
+    }
+    pub(crate) fn seek(&mut self, pos: usize) -> Result<(), ImageError> {
+        if pos > self.bytes.len() {
+            return Err(ImageError::invalid("unexpected end of file"));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], ImageError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or_else(|| ImageError::invalid("unexpected end of file"))?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ImageError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+    pub(crate) fn u8(&mut self) -> Result<u8, ImageError> {
+        Ok(self.array::<1>()?[0])
+    }
+    pub(crate) fn u16_le(&mut self) -> Result<u16, ImageError> {
+        self.array().map(u16::from_le_bytes)
+    }
+    pub(crate) fn u32_le(&mut self) -> Result<u32, ImageError> {
+        self.array().map(u32::from_le_bytes)
+    }
+    pub(crate) fn i32_le(&mut self) -> Result<i32, ImageError> {
+        self.array().map(i32::from_le_bytes)
+    }
+}
+
+/// Number of pixels of a `width` by `height` image, if it's small enough to hold in memory
+pub(crate) fn pixel_count(width: usize, height: usize) -> Result<usize, ImageError> {
+    width
+        .checked_mul(height)
+        .filter(|&count| count <= isize::MAX as usize / 4)
+        .ok_or_else(|| ImageError::unsupported(format!("{width}x{height} is too large")))
+}
+
+impl Buffer {
+    /// Reads an image, going by its first bytes or else the extension
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let format = ImageFormat::detect(&bytes)
+            .or_else(|| ImageFormat::from_path(path))
+            .ok_or_else(|| ImageError::unsupported(format!("unknown format of {path:?}")))?;
+        Self::decode(&bytes, format)
+    }
+    /// Reads an image from the bytes of a file
+    pub fn decode(bytes: &[u8], format: ImageFormat) -> Result<Self, ImageError> {
+        match format {
+            ImageFormat::Ppm | ImageFormat::Pam => netpbm::decode(bytes),
+            ImageFormat::Bmp => bmp::decode(bytes),
+            ImageFormat::Tga => tga::decode(bytes),
+            #[cfg(feature = "png")]
+            ImageFormat::Png => Ok(Png::decode(bytes)?.image),
+            #[cfg(not(feature = "png"))]
+            ImageFormat::Png => Err(ImageError::unsupported("PNG needs the png feature")),
+            ImageFormat::Qoi => Ok(Qoi::decode(bytes)?.image),
+        }
+    }
+    /// Writes an image in the format of the extension of `path`, with alpha only if some pixel
+    /// isn't opaque
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)
+            .ok_or_else(|| ImageError::unsupported(format!("unknown extension of {path:?}")))?;
+        let mut out = BufWriter::new(File::create(path)?);
+        self.encode(&mut out, format)?;
+        out.flush()?;
+        Ok(())
+    }
+    /// Writes an image in `format`, with alpha only if some pixel isn't opaque
+    pub fn encode(&self, out: impl Write, format: ImageFormat) -> io::Result<()> {
+        let channels = Channels::of(self);
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out, PpmEncoding::Binary),
+            ImageFormat::Pam => self.write_pam(out, channels),
+            ImageFormat::Bmp => self.write_bmp(out, channels),
+            ImageFormat::Tga => self.write_tga(out, channels),
+            #[cfg(feature = "png")]
+            ImageFormat::Png => self.write_png(
+                out,
+                &PngOptions {
+                    channels,
+                    ..PngOptions::default()
+                },
+            ),
+            #[cfg(not(feature = "png"))]
+            ImageFormat::Png => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "PNG needs the png feature",
+            )),
+            ImageFormat::Qoi => self.write_qoi(out, channels, Encoding::Srgb),
+        }
+    }
+}
+
+impl Buffer<Colorf32> {
+    /// Reads radiance from an HDR image, going by its first bytes or else the extension
+    pub fn open_hdr(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let format = HdrFormat::detect(&bytes)
+            .or_else(|| HdrFormat::from_path(path))
+            .ok_or_else(|| ImageError::unsupported(format!("unknown format of {path:?}")))?;
+        Self::decode_hdr(&bytes, format)
+    }
+    /// Reads radiance from the bytes of an HDR image
+    pub fn decode_hdr(bytes: &[u8], format: HdrFormat) -> Result<Self, ImageError> {
+        match format {
+            HdrFormat::Pfm => pfm::decode(bytes),
+            HdrFormat::Radiance => radiance::decode(bytes),
+        }
+    }
+    /// Writes radiance in the HDR format of the extension of `path`
+    pub fn save_hdr(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = HdrFormat::from_path(path)
+            .ok_or_else(|| ImageError::unsupported(format!("unknown extension of {path:?}")))?;
+        let mut out = BufWriter::new(File::create(path)?);
+        self.encode_hdr(&mut out, format)?;
+        out.flush()?;
+        Ok(())
+    }
+    pub fn encode_hdr(&self, out: impl Write, format: HdrFormat) -> io::Result<()> {
+        match format {
+            HdrFormat::Pfm => self.write_pfm(out),
+            HdrFormat::Radiance => self.write_hdr(out),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::prelude::*;
+
+    /// A `width`x`height` image where no two pixels are alike, with alpha varying too
+    pub(crate) fn test_image(width: usize, height: usize) -> Buffer {
+        Buffer::new_with(width, height, |x, y| {
+            Rgba::new(
+                (x * 40) as u8,
+                (y * 50) as u8,
+                (x * y + 7) as u8,
+                255u8.wrapping_sub((x * 9) as u8),
+            )
+        })
+    }
+
+    /// Channels of every pixel, which can be compared unlike [`Rgba`]
+    pub(crate) fn channels(image: &Buffer) -> Vec<[u8; 4]> {
+        image.iter().map(|p| [p.r, p.g, p.b, p.a]).collect()
+    }
+
+    /// [`channels`] with alpha replaced by opaque, as formats without alpha read it
+    pub(crate) fn opaque(image: &Buffer) -> Vec<[u8; 4]> {
+        image.iter().map(|p| [p.r, p.g, p.b, u8::MAX]).collect()
+    }
+}
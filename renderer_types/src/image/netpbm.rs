@@ -0,0 +1,310 @@
+//! Netpbm's PPM, PGM and PAM, headers of decimal numbers followed by the samples
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, Channels, ImageError};
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PpmEncoding {
+    /// `P6`, a byte per sample
+    #[default]
+    Binary,
+    /// `P3`, samples as decimal numbers that can be read and diffed
+    Ascii,
+}
+
+impl Buffer {
+    /// Writes a PPM, which has no alpha
+    pub fn write_ppm(&self, mut out: impl Write, encoding: PpmEncoding) -> io::Result<()> {
+        let magic = match encoding {
+            PpmEncoding::Binary => "P6",
+            PpmEncoding::Ascii => "P3",
+        };
+        write!(out, "{magic}\n{} {}\n255\n", self.width(), self.height())?;
+        for row in self.inner_buf().chunks(self.width().max(1)) {
+            match encoding {
+                PpmEncoding::Binary => {
+                    let bytes: Vec<u8> = row.iter().flat_map(|p| [p.r, p.g, p.b]).collect();
+                    out.write_all(&bytes)?;
+                }
+                // Lines are meant to stay under 70 characters, which 5 pixels always do
+                PpmEncoding::Ascii => {
+                    for pixels in row.chunks(5) {
+                        let line: Vec<_> = pixels
+                            .iter()
+                            .map(|p| format!("{} {} {}", p.r, p.g, p.b))
+                            .collect();
+                        writeln!(out, "{}", line.join("  "))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Writes a PAM, the Netpbm format that can hold alpha
+    pub fn write_pam(&self, mut out: impl Write, channels: Channels) -> io::Result<()> {
+        let (depth, tuple_type) = match channels {
+            Channels::Rgb => (3, "RGB"),
+            Channels::Rgba => (4, "RGB_ALPHA"),
+        };
+        write!(
+            out,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {depth}\nMAXVAL 255\nTUPLTYPE {tuple_type}\nENDHDR\n",
+            self.width(),
+            self.height()
+        )?;
+        for row in self.inner_buf().chunks(self.width().max(1)) {
+            let bytes: Vec<u8> = match channels {
+                Channels::Rgb => row.iter().flat_map(|p| [p.r, p.g, p.b]).collect(),
+                Channels::Rgba => row.iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect(),
+            };
+            out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+    /// Reads a binary or ASCII PPM or PGM, or a PAM, with up to 16 bits per sample. Grey images
+    /// come out grey
+    pub fn read_netpbm(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+}
+
+/// Whitespace separated tokens, skipping `#` comments
+struct Tokens<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn skip_space(&mut self) {
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            match byte {
+                b'#' => {
+                    while self.bytes.get(self.pos).is_some_and(|&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ if byte.is_ascii_whitespace() => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+    fn token(&mut self) -> Result<&'a [u8], ImageError> {
+        self.skip_space();
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(ImageError::invalid("unexpected end of file"));
+        }
+        Ok(&self.bytes[start..self.pos])
+    }
+    fn number(&mut self) -> Result<u32, ImageError> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| {
+                ImageError::invalid(format!(
+                    "expected a number, found {:?}",
+                    String::from_utf8_lossy(token)
+                ))
+            })
+    }
+    /// Skips the single whitespace byte between the header and binary samples
+    fn end_header(&mut self) -> Result<(), ImageError> {
+        match self.bytes.get(self.pos) {
+            Some(b) if b.is_ascii_whitespace() => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(ImageError::invalid("expected whitespace after the header")),
+        }
+    }
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    /// Samples per pixel, grey, grey and alpha, RGB or RGBA
+    depth: usize,
+    max_value: u32,
+    ascii: bool,
+}
+
+fn ppm_header(tokens: &mut Tokens, depth: usize, ascii: bool) -> Result<Header, ImageError> {
+    let width = tokens.number()? as usize;
+    let height = tokens.number()? as usize;
+    let max_value = tokens.number()?;
+    tokens.end_header()?;
+    Ok(Header {
+        width,
+        height,
+        depth,
+        max_value,
+        ascii,
+    })
+}
+
+fn pam_header(tokens: &mut Tokens) -> Result<Header, ImageError> {
+    let (mut width, mut height, mut depth, mut max_value) = (None, None, None, None);
+    loop {
+        match tokens.token()? {
+            b"ENDHDR" => break,
+            b"WIDTH" => width = Some(tokens.number()? as usize),
+            b"HEIGHT" => height = Some(tokens.number()? as usize),
+            b"DEPTH" => depth = Some(tokens.number()? as usize),
+            b"MAXVAL" => max_value = Some(tokens.number()?),
+            // The depth already says what the samples are
+            b"TUPLTYPE" => {
+                tokens.token()?;
+            }
+            other => {
+                return Err(ImageError::invalid(format!(
+                    "unknown PAM header field {:?}",
+                    String::from_utf8_lossy(other)
+                )))
+            }
+        }
+    }
+    tokens.end_header()?;
+    let missing = |field| ImageError::invalid(format!("PAM header without {field}"));
+    Ok(Header {
+        width: width.ok_or_else(|| missing("WIDTH"))?,
+        height: height.ok_or_else(|| missing("HEIGHT"))?,
+        depth: depth.ok_or_else(|| missing("DEPTH"))?,
+        max_value: max_value.ok_or_else(|| missing("MAXVAL"))?,
+        ascii: false,
+    })
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Buffer, ImageError> {
+    let mut tokens = Tokens { bytes, pos: 0 };
+    let header = match tokens.token()? {
+        b"P2" => ppm_header(&mut tokens, 1, true)?,
+        b"P3" => ppm_header(&mut tokens, 3, true)?,
+        b"P5" => ppm_header(&mut tokens, 1, false)?,
+        b"P6" => ppm_header(&mut tokens, 3, false)?,
+        b"P7" => pam_header(&mut tokens)?,
+        b"P1" | b"P4" => return Err(ImageError::unsupported("PBM bitmaps")),
+        _ => return Err(ImageError::invalid("not a Netpbm file")),
+    };
+    let Header {
+        width,
+        height,
+        depth,
+        max_value,
+        ascii,
+    } = header;
+    if !(1..=4).contains(&depth) {
+        return Err(ImageError::unsupported(format!(
+            "{depth} samples per pixel"
+        )));
+    }
+    if !(1..=u16::MAX as u32).contains(&max_value) {
+        return Err(ImageError::invalid(format!("maximum value {max_value}")));
+    }
+    let samples = pixel_count(width, height)?
+        .checked_mul(depth)
+        .ok_or_else(|| ImageError::unsupported(format!("{width}x{height} is too large")))?;
+
+    let values: Vec<u32> = if ascii {
+        (0..samples)
+            .map(|_| tokens.number())
+            .collect::<Result<_, _>>()?
+    } else {
+        let wide = max_value > u8::MAX as u32;
+        let size = if wide { 2 } else { 1 };
+        let data = bytes
+            .get(tokens.pos..)
+            .filter(|data| data.len() >= samples * size)
+            .ok_or_else(|| ImageError::invalid("unexpected end of file"))?;
+        if wide {
+            data.chunks_exact(2)
+                .take(samples)
+                .map(|s| u16::from_be_bytes([s[0], s[1]]) as u32)
+                .collect()
+        } else {
+            data[..samples].iter().map(|&s| s as u32).collect()
+        }
+    };
+    let scale = |v: u32| ((v.min(max_value) * 255 + max_value / 2) / max_value) as u8;
+    let pixels = values
+        .chunks_exact(depth)
+        .map(|s| match *s {
+            [v] => Rgba::new(scale(v), scale(v), scale(v), u8::MAX),
+            [v, a] => Rgba::new(scale(v), scale(v), scale(v), scale(a)),
+            [r, g, b] => Rgba::new(scale(r), scale(g), scale(b), u8::MAX),
+            [r, g, b, a] => Rgba::new(scale(r), scale(g), scale(b), scale(a)),
+            _ => unreachable!("the depth was checked"),
+        })
+        .collect();
+    Ok(Buffer::from_pixels(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::{channels, opaque, test_image};
+
+    #[test]
+    fn round_trips() {
+        let image = test_image(7, 3);
+        for encoding in [PpmEncoding::Binary, PpmEncoding::Ascii] {
+            let mut bytes = Vec::new();
+            image.write_ppm(&mut bytes, encoding).unwrap();
+            assert_eq!(channels(&decode(&bytes).unwrap()), opaque(&image));
+        }
+        let mut bytes = Vec::new();
+        image.write_ppm(&mut bytes, PpmEncoding::Binary).unwrap();
+        assert!(bytes.starts_with(b"P6\n7 3\n255\n"));
+        assert_eq!(bytes.len(), 11 + 7 * 3 * 3);
+
+        for (channels_written, expected) in [
+            (Channels::Rgb, opaque(&image)),
+            (Channels::Rgba, channels(&image)),
+        ] {
+            let mut bytes = Vec::new();
+            image.write_pam(&mut bytes, channels_written).unwrap();
+            assert_eq!(channels(&decode(&bytes).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn reads_grey_and_wide_samples() {
+        let mut bytes = b"P5 # a comment\n2 1\n# another\n1000\n".to_vec();
+        bytes.extend(1000u16.to_be_bytes());
+        bytes.extend(500u16.to_be_bytes());
+        let image = decode(&bytes).unwrap();
+        assert_eq!(channels(&image), [[255; 4], [128, 128, 128, 255]]);
+
+        let image = decode(b"P2 2 1 3 0 3").unwrap();
+        assert_eq!(channels(&image), [[0, 0, 0, 255], [255; 4]]);
+
+        let mut bytes = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nENDHDR\n".to_vec();
+        bytes.extend([60, 70]);
+        assert_eq!(channels(&decode(&bytes).unwrap()), [[60, 60, 60, 70]]);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let invalid = |bytes: &[u8]| matches!(decode(bytes), Err(ImageError::Invalid(_)));
+        assert!(invalid(b"P6\n2 2\n255\n\0\0\0"));
+        assert!(invalid(b"P3\n1 1\n255\n1 2"));
+        assert!(invalid(b"P6\n1 1\n0\n\0\0\0"));
+        assert!(invalid(b"P7\nWIDTH 1\nDEPTH 3\nMAXVAL 255\nENDHDR\n\0\0\0"));
+        assert!(invalid(b"BM"));
+        assert!(matches!(
+            decode(b"P4\n1 1\n\0"),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
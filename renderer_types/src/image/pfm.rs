@@ -0,0 +1,129 @@
+//! Portable Float Map, Netpbm's cousin holding 32-bit floats, bottom row first
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, ImageError};
+use crate::prelude::*;
+
+impl Buffer<Colorf32> {
+    /// Writes a color PFM in little-endian order
+    pub fn write_pfm(&self, mut out: impl Write) -> io::Result<()> {
+        // A negative scale says the floats are little-endian
+        write!(out, "PF\n{} {}\n-1.0\n", self.width(), self.height())?;
+        for row in self.inner_buf().chunks(self.width().max(1)).rev() {
+            let bytes: Vec<u8> = row
+                .iter()
+                .flat_map(|c| [c.r, c.g, c.b])
+                .flat_map(f32::to_le_bytes)
+                .collect();
+            out.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+    /// Reads a color or grey PFM of either byte order. The scale is only read for the byte order,
+    /// as most writers leave it at 1
+    pub fn read_pfm(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Buffer<Colorf32>, ImageError> {
+    // The header is three lines of text
+    let mut lines = bytes.splitn(4, |&b| b == b'\n');
+    let mut line = || {
+        lines
+            .next()
+            .and_then(|line| std::str::from_utf8(line).ok())
+            .map(str::trim)
+            .ok_or_else(|| ImageError::invalid("unexpected end of file"))
+    };
+    let channels = match line()? {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(ImageError::invalid("not a PFM file")),
+    };
+    let size = line()?;
+    let (width, height) = size
+        .split_once(char::is_whitespace)
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.trim().parse().ok()?)))
+        .ok_or_else(|| ImageError::invalid(format!("size {size:?}")))?;
+    let scale = line()?;
+    let scale: f32 = scale
+        .parse()
+        .map_err(|_| ImageError::invalid(format!("scale {scale:?}")))?;
+    let data = lines.next().unwrap_or_default();
+
+    let count = pixel_count(width, height)?;
+    if count == 0 {
+        return Ok(Buffer::from_pixels(width, height, Vec::new()));
+    }
+    let data = count
+        .checked_mul(channels * 4)
+        .and_then(|length| data.get(..length))
+        .ok_or_else(|| ImageError::invalid("unexpected end of file"))?;
+    let samples: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|s| {
+            let s = [s[0], s[1], s[2], s[3]];
+            if scale < 0.0 {
+                f32::from_le_bytes(s)
+            } else {
+                f32::from_be_bytes(s)
+            }
+        })
+        .collect();
+    let mut pixels = Vec::with_capacity(count);
+    for row in samples.chunks(width.max(1) * channels).rev() {
+        pixels.extend(row.chunks_exact(channels).map(|s| match *s {
+            [v] => Color::splat(v),
+            [r, g, b] => Color::new(r, g, b),
+            _ => unreachable!("PFMs have 1 or 3 channels"),
+        }));
+    }
+    Ok(Buffer::from_pixels(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let image = Buffer::new_with(3, 2, |x, y| Color::new(x as f32, -(y as f32), 1e-20));
+        let mut bytes = Vec::new();
+        image.write_pfm(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"PF\n3 2\n-1.0\n"));
+        assert_eq!(bytes.len(), 12 + 3 * 2 * 12);
+        assert_eq!(Buffer::read_pfm(&bytes[..]).unwrap(), image);
+    }
+
+    #[test]
+    fn reads_big_endian_grey() {
+        let mut bytes = b"Pf\n1 2\n1.0\n".to_vec();
+        bytes.extend(0.5f32.to_be_bytes());
+        bytes.extend(2.0f32.to_be_bytes());
+        let image = decode(&bytes).unwrap();
+        // Bottom row first
+        assert_eq!(image.get(0, 0), Some(Color::splat(2.0)));
+        assert_eq!(image.get(0, 1), Some(Color::splat(0.5)));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let invalid = |bytes: &[u8]| matches!(decode(bytes), Err(ImageError::Invalid(_)));
+        let mut bytes = b"PF\n2 1\n-1.0\n".to_vec();
+        bytes.extend([0; 23]);
+        assert!(invalid(&bytes));
+        assert!(invalid(b"PF\n2\n-1.0\n"));
+        assert!(invalid(b"PF\n2 1\nscale\n"));
+        assert!(invalid(b"P6\n2 1\n255\n"));
+        // Small enough to count the pixels of, but not their bytes
+        let huge = format!("PF\n{} 1\n-1.0\n", isize::MAX as usize / 4);
+        assert!(invalid(huge.as_bytes()));
+        // No pixels, however wide
+        let empty = format!("PF\n{} 0\n-1.0\n", usize::MAX);
+        assert_eq!(decode(empty.as_bytes()).unwrap().iter().count(), 0);
+    }
+}
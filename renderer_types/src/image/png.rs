@@ -0,0 +1,326 @@
+//! PNG through the `png` crate, 8 or 16 bits per channel with the transfer function recorded in
+//! sRGB or gAMA chunks
+
+use std::io::{self, Cursor, Read, Write};
+
+use png::{
+    BitDepth, ColorType, Decoder, Limits, ScaledFloat, SrgbRenderingIntent, Transformations,
+};
+
+use super::{pixel_count, Channels, ImageError};
+use crate::{color::srgb_encode, prelude::*};
+
+/// gAMA of sRGB, which is written along with the sRGB chunk for older readers
+const SRGB_GAMMA: u32 = 45_455;
+/// Largest image the decoder allocates, in bytes
+const MAX_DECODED_SIZE: usize = 1 << 30;
+
+/// How a PNG is written
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PngOptions {
+    pub channels: Channels,
+    /// Transfer function the channels went through, recorded in the file
+    pub encoding: Encoding,
+    /// Keyword and text pairs, like the settings of a render
+    pub text: Vec<(String, String)>,
+}
+
+/// A PNG along with what it says about itself
+#[derive(Debug, Clone, PartialEq)]
+pub struct Png<P = Rgba> {
+    pub image: Buffer<P>,
+    /// Whether the channels are sRGB encoded or linear. Other gammas are converted to sRGB
+    pub encoding: Encoding,
+    pub text: Vec<(String, String)>,
+}
+
+impl Png {
+    /// Reads a PNG of any color type and depth into 8 bits per channel
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImageError> {
+        let decoded = decode_samples(bytes)?;
+        let quantize = |v: f32| (v * 255.0).round() as u8;
+        let image = decoded.image.map(|p| {
+            let [r, g, b, a] = p.map(quantize);
+            Rgba::new(r, g, b, a)
+        });
+        Ok(Self {
+            image,
+            encoding: decoded.encoding,
+            text: decoded.text,
+        })
+    }
+}
+
+impl Png<ColorAlpha<f32>> {
+    /// Reads a PNG of any color type and depth into linear colors with straight alpha
+    pub fn decode_linear(bytes: &[u8]) -> Result<Self, ImageError> {
+        let decoded = decode_samples(bytes)?;
+        let encoding = decoded.encoding;
+        let image = decoded.image.map(|&[r, g, b, a]| {
+            let color = Color::new(r, g, b);
+            let color = match encoding {
+                Encoding::Linear => color,
+                Encoding::Srgb => color.decode_srgb(),
+            };
+            ColorAlpha::new(color, a)
+        });
+        Ok(Self {
+            image,
+            encoding,
+            text: decoded.text,
+        })
+    }
+}
+
+impl Buffer {
+    /// Writes an 8-bit PNG. The pixels are stored as they are, [`PngOptions::encoding`] only says
+    /// how to read them
+    pub fn write_png(&self, out: impl Write, options: &PngOptions) -> io::Result<()> {
+        let data: Vec<u8> = match options.channels {
+            Channels::Rgb => self.iter().flat_map(|p| [p.r, p.g, p.b]).collect(),
+            Channels::Rgba => self.iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect(),
+        };
+        encode(out, self.dimensions(), BitDepth::Eight, &data, options)
+    }
+    /// Reads a PNG of any color type and depth into 8 bits per channel
+    pub fn read_png(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        Ok(Png::decode(&bytes)?.image)
+    }
+}
+
+impl Buffer<ColorAlpha<f32>> {
+    /// Writes a 16-bit PNG of linear colors in [0, 1], encoded as [`PngOptions::encoding`] says
+    pub fn write_png16(&self, out: impl Write, options: &PngOptions) -> io::Result<()> {
+        let quantize = |v: f32| ((v.clamp(0.0, 1.0) * 65535.0).round() as u16).to_be_bytes();
+        let mut data = Vec::with_capacity(self.width() * self.height() * 8);
+        for pixel in self.iter() {
+            let Color { r, g, b } = match options.encoding {
+                Encoding::Linear => pixel.color,
+                Encoding::Srgb => pixel.color.encode_srgb(),
+            };
+            data.extend([r, g, b].into_iter().flat_map(quantize));
+            if options.channels == Channels::Rgba {
+                data.extend(quantize(pixel.alpha));
+            }
+        }
+        encode(out, self.dimensions(), BitDepth::Sixteen, &data, options)
+    }
+}
+
+fn encode(
+    out: impl Write,
+    dimensions: Vec2<usize>,
+    depth: BitDepth,
+    data: &[u8],
+    options: &PngOptions,
+) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a PNG");
+    let width = u32::try_from(dimensions.x).map_err(|_| too_large())?;
+    let height = u32::try_from(dimensions.y).map_err(|_| too_large())?;
+    let mut encoder = png::Encoder::new(out, width, height);
+    encoder.set_color(match options.channels {
+        Channels::Rgb => ColorType::Rgb,
+        Channels::Rgba => ColorType::Rgba,
+    });
+    encoder.set_depth(depth);
+    match options.encoding {
+        Encoding::Srgb => {
+            encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+            encoder.set_source_gamma(ScaledFloat::from_scaled(SRGB_GAMMA));
+        }
+        Encoding::Linear => encoder.set_source_gamma(ScaledFloat::new(1.0)),
+    }
+    for (keyword, text) in &options.text {
+        // tEXt only holds Latin-1, iTXt takes anything
+        let added = if text.is_ascii() {
+            encoder.add_text_chunk(keyword.clone(), text.clone())
+        } else {
+            encoder.add_itxt_chunk(keyword.clone(), text.clone())
+        };
+        added.map_err(io::Error::other)?;
+    }
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// Samples of a PNG in [0, 1], before anything is done about their encoding
+struct Decoded {
+    image: Buffer<[f32; 4]>,
+    encoding: Encoding,
+    text: Vec<(String, String)>,
+}
+
+fn decode_samples(bytes: &[u8]) -> Result<Decoded, ImageError> {
+    let error = |err: png::DecodingError| match err {
+        png::DecodingError::IoError(err) => ImageError::Io(err),
+        png::DecodingError::LimitsExceeded => ImageError::unsupported("too large"),
+        err => ImageError::invalid(err.to_string()),
+    };
+    let mut decoder = Decoder::new_with_limits(
+        Cursor::new(bytes),
+        Limits {
+            bytes: MAX_DECODED_SIZE,
+        },
+    );
+    // Palettes, transparency chunks and grey below 8 bits become plain channels
+    decoder.set_transformations(Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(error)?;
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| ImageError::unsupported("too large"))?;
+    let mut data = vec![0; size];
+    let frame = reader.next_frame(&mut data).map_err(error)?;
+    let (width, height) = (frame.width as usize, frame.height as usize);
+    pixel_count(width, height)?;
+
+    let samples: Vec<f32> = match frame.bit_depth {
+        BitDepth::Sixteen => data[..frame.buffer_size()]
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]) as f32 / 65535.0)
+            .collect(),
+        _ => data[..frame.buffer_size()]
+            .iter()
+            .map(|&s| s as f32 / 255.0)
+            .collect(),
+    };
+    let channels = frame.color_type.samples();
+    // Rows have no padding once they're at least 8 bits per sample
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|s| match *s {
+            [v] => [v, v, v, 1.0],
+            [v, a] => [v, v, v, a],
+            [r, g, b] => [r, g, b, 1.0],
+            [r, g, b, a] => [r, g, b, a],
+            _ => unreachable!("PNGs have 1 to 4 channels"),
+        })
+        .collect();
+    let mut image = Buffer::from_pixels(width, height, pixels);
+
+    let info = reader.info();
+    let gamma = info.gama_chunk.map(ScaledFloat::into_value);
+    let encoding = match (info.srgb, gamma) {
+        (None, Some(gamma)) if (gamma - 1.0).abs() < 0.01 => Encoding::Linear,
+        (None, Some(gamma)) if (gamma - SRGB_GAMMA as f32 / 1e5).abs() > 0.01 => {
+            // Samples are linear values raised to `gamma`, which are re-encoded as sRGB
+            image.iter_mut().for_each(|p| {
+                for v in &mut p[..3] {
+                    *v = srgb_encode(v.powf(1.0 / gamma));
+                }
+            });
+            Encoding::Srgb
+        }
+        _ => Encoding::Srgb,
+    };
+
+    let mut text: Vec<_> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect();
+    for chunk in &info.compressed_latin1_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().map_err(error)?));
+    }
+    for chunk in &info.utf8_text {
+        text.push((chunk.keyword.clone(), chunk.get_text().map_err(error)?));
+    }
+    Ok(Decoded {
+        image,
+        encoding,
+        text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::{channels, opaque, test_image};
+
+    #[test]
+    fn round_trips() {
+        let image = test_image(5, 3);
+        for (channels_written, expected) in [
+            (Channels::Rgb, opaque(&image)),
+            (Channels::Rgba, channels(&image)),
+        ] {
+            let options = PngOptions {
+                channels: channels_written,
+                encoding: Encoding::Linear,
+                text: vec![
+                    ("Software".to_owned(), "renderer".to_owned()),
+                    ("Comment".to_owned(), "λ in nm".to_owned()),
+                ],
+            };
+            let mut bytes = Vec::new();
+            image.write_png(&mut bytes, &options).unwrap();
+            let png = Png::decode(&bytes).unwrap();
+            assert_eq!(channels(&png.image), expected);
+            assert_eq!(png.encoding, Encoding::Linear);
+            assert_eq!(png.text, options.text);
+        }
+    }
+
+    #[test]
+    fn round_trips_16_bits() {
+        let image = Buffer::new_with(4, 2, |x, y| {
+            ColorAlpha::new(Color::new(x as f32 / 3.0, y as f32, 0.001), 0.25)
+        });
+        for encoding in [Encoding::Srgb, Encoding::Linear] {
+            let options = PngOptions {
+                encoding,
+                ..PngOptions::default()
+            };
+            let mut bytes = Vec::new();
+            image.write_png16(&mut bytes, &options).unwrap();
+            let png = Png::decode_linear(&bytes).unwrap();
+            assert_eq!(png.encoding, encoding);
+            let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+            assert!(png.image.iter().zip(image.iter()).all(|(a, b)| {
+                close(a.color.r, b.color.r)
+                    && close(a.color.g, b.color.g)
+                    && close(a.color.b, b.color.b)
+                    && close(a.alpha, b.alpha)
+            }));
+        }
+    }
+
+    #[test]
+    fn reads_other_gammas_and_depths() {
+        // 2-bit grey with a gamma of 1 / 1.8, written by the png crate itself
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 4, 1);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_depth(BitDepth::Two);
+        encoder.set_source_gamma(ScaledFloat::new(1.0 / 1.8));
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0b00_01_10_11]).unwrap();
+        writer.finish().unwrap();
+
+        let png = Png::decode(&bytes).unwrap();
+        assert_eq!(png.encoding, Encoding::Srgb);
+        let grey: Vec<_> = png.image.iter().map(|p| p.g).collect();
+        // A third and two thirds, linearized by gamma 1.8 and encoded again as sRGB
+        let srgb = |v: f32| (srgb_encode(v.powf(1.8)) * 255.0).round() as u8;
+        assert_eq!(grey, [0, srgb(1.0 / 3.0), srgb(2.0 / 3.0), 255]);
+        assert!(png
+            .image
+            .iter()
+            .all(|p| p.r == p.g && p.b == p.g && p.a == 255));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut bytes = Vec::new();
+        test_image(8, 8)
+            .write_png(&mut bytes, &PngOptions::default())
+            .unwrap();
+        assert!(Png::decode(&bytes[..bytes.len() - 20]).is_err());
+        assert!(matches!(
+            Png::decode(b"\x89PNG\r\n\x1a\nnot really"),
+            Err(ImageError::Invalid(_) | ImageError::Io(_))
+        ));
+    }
+}
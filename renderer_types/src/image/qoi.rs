@@ -0,0 +1,303 @@
+//! The Quite OK Image format, lossless and much faster to write than PNG while not much larger
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, ByteReader, Channels, ImageError};
+use crate::prelude::*;
+
+const MAGIC: &[u8; 4] = b"qoif";
+/// Marks the end of the pixels
+const END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+/// The two high bits of a byte say which of the small ops it is
+const OP_MASK: u8 = 0xc0;
+/// Longest run one op holds, as longer ones would look like [`OP_RGB`] and [`OP_RGBA`]
+const MAX_RUN: usize = 62;
+/// Largest image the format allows
+const MAX_PIXELS: usize = 400_000_000;
+
+/// A QOI along with whether it says its colors are sRGB encoded or linear
+#[derive(Clone)]
+pub struct Qoi {
+    pub image: Buffer,
+    pub encoding: Encoding,
+}
+
+impl Qoi {
+    pub fn decode(bytes: &[u8]) -> Result<Self, ImageError> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.take(4)? != MAGIC {
+            return Err(ImageError::invalid("not a QOI file"));
+        }
+        let width = u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+        let channels = reader.u8()?;
+        if !matches!(channels, 3 | 4) {
+            return Err(ImageError::invalid(format!("{channels} channels")));
+        }
+        // The alpha of sRGB images is still linear
+        let encoding = match reader.u8()? {
+            0 => Encoding::Srgb,
+            1 => Encoding::Linear,
+            space => return Err(ImageError::invalid(format!("color space {space}"))),
+        };
+        let count = pixel_count(width, height)?;
+        if count > MAX_PIXELS {
+            return Err(ImageError::unsupported(format!(
+                "{width}x{height} is too large"
+            )));
+        }
+
+        // A byte can stand for many pixels, so only trust the size so far
+        let mut pixels = Vec::with_capacity(count.min(bytes.len()));
+        let mut index = [[0u8; 4]; 64];
+        let mut previous = [0, 0, 0, u8::MAX];
+        while pixels.len() < count {
+            let op = reader.u8()?;
+            let mut pixel = previous;
+            match op {
+                OP_RGB => pixel[..3].copy_from_slice(reader.take(3)?),
+                OP_RGBA => pixel.copy_from_slice(reader.take(4)?),
+                _ => match op & OP_MASK {
+                    OP_INDEX => pixel = index[op as usize],
+                    OP_DIFF => {
+                        for (i, v) in pixel[..3].iter_mut().enumerate() {
+                            let diff = (op >> (4 - 2 * i)) & 0x03;
+                            *v = v.wrapping_add(diff).wrapping_sub(2);
+                        }
+                    }
+                    OP_LUMA => {
+                        let green = (op & 0x3f).wrapping_sub(32);
+                        let next = reader.u8()?;
+                        let red = green.wrapping_add(next >> 4).wrapping_sub(8);
+                        let blue = green.wrapping_add(next & 0x0f).wrapping_sub(8);
+                        for (v, diff) in pixel[..3].iter_mut().zip([red, green, blue]) {
+                            *v = v.wrapping_add(diff);
+                        }
+                    }
+                    _ => {
+                        // Runs may not go past the last pixel
+                        let run = (op & 0x3f) as usize + 1;
+                        if pixels.len() + run > count {
+                            return Err(ImageError::invalid("run past the last pixel"));
+                        }
+                        pixels.extend(std::iter::repeat_n(previous, run).map(to_rgba));
+                        continue;
+                    }
+                },
+            }
+            index[hash(pixel)] = pixel;
+            pixels.push(to_rgba(pixel));
+            previous = pixel;
+        }
+        if reader.take(END.len())? != END {
+            return Err(ImageError::invalid("missing end marker"));
+        }
+        Ok(Self {
+            image: Buffer::from_pixels(width, height, pixels),
+            encoding,
+        })
+    }
+}
+
+impl Buffer {
+    /// Writes a QOI, recording `encoding` in its header. [`Channels::Rgb`] drops alpha
+    pub fn write_qoi(
+        &self,
+        mut out: impl Write,
+        channels: Channels,
+        encoding: Encoding,
+    ) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a QOI");
+        let width = u32::try_from(self.width()).map_err(|_| too_large())?;
+        let height = u32::try_from(self.height()).map_err(|_| too_large())?;
+        if self.width() * self.height() > MAX_PIXELS {
+            return Err(too_large());
+        }
+        let mut data = Vec::with_capacity(14 + self.width() * self.height() + END.len());
+        data.extend(MAGIC);
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.push(match channels {
+            Channels::Rgb => 3,
+            Channels::Rgba => 4,
+        });
+        data.push(match encoding {
+            Encoding::Srgb => 0,
+            Encoding::Linear => 1,
+        });
+
+        let mut index = [[0u8; 4]; 64];
+        let mut previous = [0, 0, 0, u8::MAX];
+        let mut run = 0;
+        for p in self.iter() {
+            let alpha = match channels {
+                Channels::Rgb => u8::MAX,
+                Channels::Rgba => p.a,
+            };
+            let pixel = [p.r, p.g, p.b, alpha];
+            if pixel == previous {
+                run += 1;
+                if run == MAX_RUN {
+                    data.push(OP_RUN | (run - 1) as u8);
+                    run = 0;
+                }
+                continue;
+            }
+            if run > 0 {
+                data.push(OP_RUN | (run - 1) as u8);
+                run = 0;
+            }
+
+            let slot = hash(pixel);
+            if index[slot] == pixel {
+                data.push(OP_INDEX | slot as u8);
+            } else if pixel[3] != previous[3] {
+                data.push(OP_RGBA);
+                data.extend(pixel);
+            } else {
+                let [red, green, blue] =
+                    [0, 1, 2].map(|i| pixel[i].wrapping_sub(previous[i]) as i8);
+                let (green_red, green_blue) = (red.wrapping_sub(green), blue.wrapping_sub(green));
+                let small = |v: i8| (-2..=1).contains(&v);
+                if small(red) && small(green) && small(blue) {
+                    let bias = |v: i8| (v + 2) as u8;
+                    data.push(OP_DIFF | bias(red) << 4 | bias(green) << 2 | bias(blue));
+                } else if (-32..=31).contains(&green)
+                    && (-8..=7).contains(&green_red)
+                    && (-8..=7).contains(&green_blue)
+                {
+                    data.push(OP_LUMA | (green + 32) as u8);
+                    data.push(((green_red + 8) as u8) << 4 | (green_blue + 8) as u8);
+                } else {
+                    data.push(OP_RGB);
+                    data.extend(&pixel[..3]);
+                }
+            }
+            index[slot] = pixel;
+            previous = pixel;
+        }
+        if run > 0 {
+            data.push(OP_RUN | (run - 1) as u8);
+        }
+        data.extend(END);
+        out.write_all(&data)
+    }
+    /// Reads a QOI, whichever color space it says it's in
+    pub fn read_qoi(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        Ok(Qoi::decode(&bytes)?.image)
+    }
+}
+
+/// Slot of `pixel` in the array of recently seen pixels
+fn hash([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+fn to_rgba([r, g, b, a]: [u8; 4]) -> Rgba {
+    Rgba::new(r, g, b, a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::{channels, opaque, test_image};
+
+    fn encode(image: &Buffer, channels: Channels, encoding: Encoding) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_qoi(&mut bytes, channels, encoding).unwrap();
+        bytes
+    }
+
+    /// The ops of the specification for [`reference_image`], as the reference encoder writes them
+    fn reference() -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([0, 0, 0, 6, 0, 0, 0, 1, 4, 0]);
+        // Luma from the starting black, then a run of 2
+        bytes.extend([0xa2, 0x79, 0xc1]);
+        // Luma back to black, which isn't in the index as it never was a pixel
+        bytes.extend([0x9e, 0x97]);
+        // The first color again, from slot 23 of the index, then with alpha
+        bytes.extend([0x17, OP_RGBA, 1, 2, 3, 0]);
+        bytes.extend(END);
+        bytes
+    }
+
+    fn reference_image() -> Buffer {
+        let pixels = [[1, 2, 3, 255]; 3]
+            .into_iter()
+            .chain([[0, 0, 0, 255], [1, 2, 3, 255], [1, 2, 3, 0]])
+            .map(to_rgba)
+            .collect();
+        Buffer::from_pixels(6, 1, pixels)
+    }
+
+    #[test]
+    fn matches_the_specification() {
+        let image = reference_image();
+        assert_eq!(encode(&image, Channels::Rgba, Encoding::Srgb), reference());
+        let decoded = Qoi::decode(&reference()).unwrap();
+        assert_eq!(channels(&decoded.image), channels(&image));
+    }
+
+    #[test]
+    fn round_trips() {
+        // Long runs, small and large differences, and colors seen before
+        let image = Buffer::new_with(70, 9, |x, y| match y % 3 {
+            0 => Rgba::new(10, 20, 30, 255),
+            1 => test_image(70, 9).get(x, y).unwrap(),
+            _ => Rgba::new(x as u8 % 4, 20 + x as u8 % 3, 30, 200),
+        });
+        let bytes = encode(&image, Channels::Rgba, Encoding::Srgb);
+        assert!(bytes.len() < 70 * 9 * 4);
+        assert_eq!(
+            channels(&Qoi::decode(&bytes).unwrap().image),
+            channels(&image)
+        );
+        let bytes = encode(&image, Channels::Rgb, Encoding::Srgb);
+        assert_eq!(bytes[12], 3);
+        assert_eq!(
+            channels(&Buffer::read_qoi(&bytes[..]).unwrap()),
+            opaque(&image)
+        );
+    }
+
+    #[test]
+    fn records_the_color_space() {
+        let image = test_image(2, 2);
+        for (encoding, byte) in [(Encoding::Srgb, 0), (Encoding::Linear, 1)] {
+            let bytes = encode(&image, Channels::Rgba, encoding);
+            assert_eq!(bytes[13], byte);
+            assert_eq!(Qoi::decode(&bytes).unwrap().encoding, encoding);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let invalid = |bytes: &[u8]| matches!(Qoi::decode(bytes), Err(ImageError::Invalid(_)));
+        let with = |at: usize, byte: u8| {
+            let mut bytes = reference();
+            bytes[at] = byte;
+            bytes
+        };
+        assert!(invalid(&with(0, b'Q')));
+        assert!(invalid(&with(12, 5)));
+        assert!(invalid(&with(13, 2)));
+        let reference = reference();
+        assert!(invalid(&reference[..reference.len() - 1]));
+        assert!(invalid(&reference[..20]));
+        // A run of 6 after the first pixel
+        assert!(invalid(&with(16, OP_RUN | 5)));
+        assert!(matches!(
+            Qoi::decode(&with(4, 0xff)),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
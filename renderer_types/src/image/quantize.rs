@@ -0,0 +1,181 @@
+//! Reducing images to a few colors, for formats that store indices into a palette
+
+use std::collections::HashMap;
+
+/// Up to 256 colors an image is drawn with, remembering the nearest one to colors already looked
+/// up
+pub(crate) struct Palette {
+    colors: Vec<[u8; 3]>,
+    nearest: HashMap<[u8; 3], u8>,
+}
+
+impl Palette {
+    /// Median cut: starting from a box around all colors, splits the box with the widest range of
+    /// a channel at the median of that channel, until there are `max_colors` boxes or no box has
+    /// more than one color. The colors are the averages of the boxes
+    pub(crate) fn median_cut(pixels: impl Iterator<Item = [u8; 3]>, max_colors: usize) -> Self {
+        let mut histogram = HashMap::new();
+        for pixel in pixels {
+            *histogram.entry(pixel).or_insert(0u64) += 1;
+        }
+        let mut boxes = vec![histogram.into_iter().collect::<Vec<_>>()];
+        while boxes.len() < max_colors.clamp(1, 256) {
+            // Widest channel of every box that can still be split
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, colors)| colors.len() > 1)
+                .map(|(i, colors)| {
+                    let (channel, range) = (0..3)
+                        .map(|c| {
+                            let values = colors.iter().map(|(color, _)| color[c]);
+                            let range = values.clone().max().unwrap() - values.min().unwrap();
+                            (c, range)
+                        })
+                        .max_by_key(|&(_, range)| range)
+                        .unwrap();
+                    (i, channel, range)
+                })
+                .max_by_key(|&(_, _, range)| range);
+            let Some((i, channel, _)) = widest else {
+                break;
+            };
+            let mut colors = boxes.swap_remove(i);
+            colors.sort_unstable_by_key(|(color, _)| color[channel]);
+            let total: u64 = colors.iter().map(|(_, count)| count).sum();
+            let mut seen = 0;
+            let median = colors
+                .iter()
+                .position(|(_, count)| {
+                    seen += count;
+                    seen * 2 >= total
+                })
+                .unwrap();
+            // Both halves keep at least one color
+            let split = (median + 1).min(colors.len() - 1);
+            boxes.push(colors.split_off(split));
+            boxes.push(colors);
+        }
+
+        let colors = boxes
+            .iter()
+            .filter(|colors| !colors.is_empty())
+            .map(|colors| {
+                let total: u64 = colors.iter().map(|(_, count)| count).sum();
+                let mean = |c: usize| {
+                    let sum: u64 = colors.iter().map(|(color, n)| color[c] as u64 * n).sum();
+                    ((sum + total / 2) / total) as u8
+                };
+                [mean(0), mean(1), mean(2)]
+            })
+            .collect::<Vec<_>>();
+        Self {
+            colors: if colors.is_empty() {
+                vec![[0; 3]]
+            } else {
+                colors
+            },
+            nearest: HashMap::new(),
+        }
+    }
+    pub(crate) fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+    fn nearest(&mut self, color: [u8; 3]) -> u8 {
+        let colors = &self.colors;
+        *self.nearest.entry(color).or_insert_with(|| {
+            let distance = |p: &[u8; 3]| -> i32 {
+                (0..3).map(|c| (p[c] as i32 - color[c] as i32).pow(2)).sum()
+            };
+            let (index, _) = colors
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| distance(p))
+                .unwrap();
+            index as u8
+        })
+    }
+    /// Indices of the nearest colors to the pixels of a `width` wide image. Dithering spreads the
+    /// difference to the nearest color over the pixels right of and below each one, Floyd and
+    /// Steinberg's way, which trades banding for noise
+    pub(crate) fn indices(&mut self, pixels: &[[u8; 3]], width: usize, dither: bool) -> Vec<u8> {
+        if !dither {
+            return pixels.iter().map(|&p| self.nearest(p)).collect();
+        }
+        let width = width.max(1);
+        // Errors for this row and the next, with a pixel of padding on either side
+        let mut errors = vec![[0.0f32; 3]; width + 2];
+        let mut next_errors = errors.clone();
+        let mut indices = Vec::with_capacity(pixels.len());
+        for row in pixels.chunks(width) {
+            for (x, pixel) in row.iter().enumerate() {
+                let wanted =
+                    [0, 1, 2].map(|c| (pixel[c] as f32 + errors[x + 1][c]).clamp(0.0, 255.0));
+                let index = self.nearest(wanted.map(|v| v.round() as u8));
+                indices.push(index);
+                let got = self.colors[index as usize];
+                for c in 0..3 {
+                    let error = wanted[c] - got[c] as f32;
+                    errors[x + 2][c] += error * 7.0 / 16.0;
+                    next_errors[x][c] += error * 3.0 / 16.0;
+                    next_errors[x + 1][c] += error * 5.0 / 16.0;
+                    next_errors[x + 2][c] += error / 16.0;
+                }
+            }
+            std::mem::swap(&mut errors, &mut next_errors);
+            next_errors.fill([0.0; 3]);
+        }
+        indices
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every color of a 16 step cube, more than any palette holds
+    fn cube() -> impl Iterator<Item = [u8; 3]> {
+        (0..16 * 16 * 16).map(|i: usize| [i % 16, i / 16 % 16, i / 256].map(|c| (c * 17) as u8))
+    }
+
+    #[test]
+    fn median_cut_stays_within_the_limit() {
+        for max_colors in [1, 2, 7, 16, 255, 256, 1000] {
+            let palette = Palette::median_cut(cube(), max_colors);
+            // No more than asked for, and no fewer either as splits only stop early once every
+            // box holds a single color
+            assert_eq!(palette.colors().len(), max_colors.min(256));
+        }
+    }
+
+    #[test]
+    fn median_cut_keeps_few_colors_exactly() {
+        let colors = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [10, 10, 10],
+            [11, 10, 10],
+        ];
+        // Repeated unevenly, which mustn't blend neighbours together
+        let pixels = colors
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &color)| std::iter::repeat_n(color, 1 + i * 10));
+        let mut palette = Palette::median_cut(pixels, 256);
+        let mut kept = palette.colors().to_vec();
+        kept.sort_unstable();
+        let mut expected = colors.to_vec();
+        expected.sort_unstable();
+        assert_eq!(kept, expected);
+        // And every color maps to itself
+        let indices = palette.indices(&colors, colors.len(), false);
+        for (color, index) in colors.iter().zip(indices) {
+            assert_eq!(palette.colors()[index as usize], *color);
+        }
+        assert_eq!(
+            Palette::median_cut(std::iter::empty(), 16).colors(),
+            [[0; 3]]
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Radiance's `.hdr`, also called RGBE: 8-bit mantissas sharing an exponent, run-length encoded
+//! one channel at a time
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, ByteReader, ImageError};
+use crate::prelude::*;
+
+/// Scanlines of this many pixels can be run-length encoded
+const RLE_WIDTHS: std::ops::Range<usize> = 8..0x8000;
+/// Shortest run worth encoding as one
+const MIN_RUN: usize = 4;
+
+impl Buffer<Colorf32> {
+    /// Writes a Radiance `.hdr`, run-length encoding scanlines where the format allows
+    pub fn write_hdr(&self, mut out: impl Write) -> io::Result<()> {
+        write!(
+            out,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height(),
+            self.width()
+        )?;
+        let mut encoded = Vec::new();
+        for row in self.inner_buf().chunks(self.width().max(1)) {
+            let rgbe: Vec<[u8; 4]> = row.iter().map(|&c| to_rgbe(c)).collect();
+            encoded.clear();
+            if RLE_WIDTHS.contains(&row.len()) {
+                encoded.extend([2, 2]);
+                encoded.extend((row.len() as u16).to_be_bytes());
+                for channel in 0..4 {
+                    let values: Vec<u8> = rgbe.iter().map(|p| p[channel]).collect();
+                    encode_runs(&values, &mut encoded);
+                }
+            } else {
+                encoded.extend(rgbe.iter().flatten());
+            }
+            out.write_all(&encoded)?;
+        }
+        Ok(())
+    }
+    /// Reads a Radiance `.hdr`, flat or run-length encoded, dividing out any `EXPOSURE`
+    pub fn read_hdr(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+}
+
+/// Shares the exponent of the brightest channel between all three
+fn to_rgbe(color: Colorf32) -> [u8; 4] {
+    // Exponents above 127 don't fit in a byte
+    let clamp = |v: f32| v.clamp(0.0, 1e38);
+    let (r, g, b) = (clamp(color.r), clamp(color.g), clamp(color.b));
+    let brightest = r.max(g).max(b);
+    if brightest.is_nan() || brightest < 1e-32 {
+        return [0; 4];
+    }
+    // The brightest channel becomes a mantissa in [128, 256)
+    let exponent = brightest.log2().floor() as i32 + 1;
+    let scale = 256.0 / (exponent as f32).exp2();
+    let mantissa = |v: f32| (v * scale).min(255.0) as u8;
+    [
+        mantissa(r),
+        mantissa(g),
+        mantissa(b),
+        (exponent + 128) as u8,
+    ]
+}
+
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Colorf32 {
+    if e == 0 {
+        return Color::black();
+    }
+    // Mantissas are rounded down, so the middle of their range is the best guess
+    let scale = (e as f32 - 136.0).exp2();
+    Color::new(
+        (r as f32 + 0.5) * scale,
+        (g as f32 + 0.5) * scale,
+        (b as f32 + 0.5) * scale,
+    )
+}
+
+/// Appends `values` as runs of a repeated byte, a count above 128 followed by the byte, and
+/// literal stretches, a count of at most 128 followed by the bytes
+fn encode_runs(values: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < values.len() {
+        // Find the next run long enough to be worth it
+        let mut run_start = i;
+        let mut run_length = 0;
+        while run_start < values.len() {
+            run_length = values[run_start..]
+                .iter()
+                .take(127)
+                .take_while(|&&v| v == values[run_start])
+                .count();
+            if run_length >= MIN_RUN {
+                break;
+            }
+            run_start += run_length;
+            run_length = 0;
+        }
+        for literal in values[i..run_start].chunks(128) {
+            out.push(literal.len() as u8);
+            out.extend_from_slice(literal);
+        }
+        if run_length > 0 {
+            out.extend([128 + run_length as u8, values[run_start]]);
+        }
+        i = run_start + run_length;
+    }
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Buffer<Colorf32>, ImageError> {
+    let mut reader = ByteReader::new(bytes);
+    let mut line = || read_line(&mut reader);
+    if !matches!(line()?, "#?RADIANCE" | "#?RGBE") {
+        return Err(ImageError::invalid("not a Radiance HDR file"));
+    }
+    let mut exposure = 1.0;
+    loop {
+        let header = line()?;
+        if header.is_empty() {
+            break;
+        }
+        if let Some(format) = header.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(ImageError::unsupported(format!("format {format}")));
+            }
+        } else if let Some(value) = header.strip_prefix("EXPOSURE=") {
+            exposure *= value
+                .trim()
+                .parse::<f32>()
+                .map_err(|_| ImageError::invalid(format!("exposure {value:?}")))?;
+        }
+    }
+    let resolution = line()?;
+    let (bottom_up, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        [y @ ("-Y" | "+Y"), height, "+X", width] => (y == "+Y", height, width),
+        _ => {
+            return Err(ImageError::unsupported(format!(
+                "orientation {resolution:?}"
+            )))
+        }
+    };
+    let parse = |v: &str| {
+        v.parse::<usize>()
+            .map_err(|_| ImageError::invalid(format!("size {v:?}")))
+    };
+    let (width, height) = (parse(width)?, parse(height)?);
+    let count = pixel_count(width, height)?;
+    if count == 0 {
+        return Ok(Buffer::from_pixels(width, height, Vec::new()));
+    }
+    // Even run-length encoded scanlines take a byte for every 16 pixels
+    if width / 16 > reader.rest().len() {
+        return Err(ImageError::invalid("unexpected end of file"));
+    }
+
+    let mut pixels = Vec::with_capacity(count.min(bytes.len()));
+    let mut scanline = vec![[0; 4]; width];
+    for _ in 0..height {
+        read_scanline(&mut reader, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&p| from_rgbe(p) / exposure));
+    }
+    let mut image = Buffer::from_pixels(width, height, pixels);
+    if bottom_up {
+        let rows: Vec<_> = image
+            .inner_buf()
+            .chunks(width.max(1))
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        image = Buffer::from_pixels(width, height, rows);
+    }
+    Ok(image)
+}
+
+fn read_line<'a>(reader: &mut ByteReader<'a>) -> Result<&'a str, ImageError> {
+    let length = reader
+        .rest()
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| ImageError::invalid("unexpected end of file"))?;
+    let line = reader.take(length)?;
+    reader.take(1)?;
+    std::str::from_utf8(line).map_err(|_| ImageError::invalid("header isn't text"))
+}
+
+fn read_scanline(reader: &mut ByteReader, scanline: &mut [[u8; 4]]) -> Result<(), ImageError> {
+    let width = scanline.len();
+    let start = reader.pos();
+    let first = reader.take(4.min(width * 4))?;
+    if RLE_WIDTHS.contains(&width) && first[..2] == [2, 2] && first[2] & 0x80 == 0 {
+        if u16::from_be_bytes([first[2], first[3]]) as usize != width {
+            return Err(ImageError::invalid("scanline of the wrong width"));
+        }
+        for channel in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = reader.u8()? as usize;
+                let (length, run) = if count > 128 {
+                    (count - 128, true)
+                } else {
+                    (count, false)
+                };
+                if length == 0 || x + length > width {
+                    return Err(ImageError::invalid("run past the end of a scanline"));
+                }
+                if run {
+                    let value = reader.u8()?;
+                    scanline[x..x + length]
+                        .iter_mut()
+                        .for_each(|p| p[channel] = value);
+                } else {
+                    for (p, &value) in scanline[x..x + length].iter_mut().zip(reader.take(length)?)
+                    {
+                        p[channel] = value;
+                    }
+                }
+                x += length;
+            }
+        }
+        return Ok(());
+    }
+
+    // Flat pixels, where the old run-length encoding repeats the previous pixel for pixels of
+    // 1, 1, 1, count, with consecutive ones adding higher bits to the count
+    reader.seek(start)?;
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let pixel: [u8; 4] = reader.take(4)?.try_into().unwrap();
+        if pixel[..3] == [1, 1, 1] && x > 0 {
+            // The count can't have more bits than a usize
+            if pixel[3] == 0 || shift > usize::BITS - 8 {
+                return Err(ImageError::invalid("bad run of the previous pixel"));
+            }
+            let repeat = (pixel[3] as usize) << shift;
+            if repeat > width - x {
+                return Err(ImageError::invalid("run past the end of a scanline"));
+            }
+            let previous = scanline[x - 1];
+            scanline[x..x + repeat].fill(previous);
+            x += repeat;
+            shift += 8;
+        } else {
+            scanline[x] = pixel;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(image: &Buffer<Colorf32>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_hdr(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Whether `decoded` is as close to `original` as eight bits of mantissa get
+    fn close(original: &Buffer<Colorf32>, decoded: &Buffer<Colorf32>) -> bool {
+        original.dimensions() == decoded.dimensions()
+            && original.iter().zip(decoded.iter()).all(|(a, b)| {
+                let brightest = a.r.max(a.g).max(a.b);
+                [(a.r, b.r), (a.g, b.g), (a.b, b.b)]
+                    .iter()
+                    .all(|(a, b)| (a - b).abs() <= brightest / 128.0)
+            })
+    }
+
+    #[test]
+    fn round_trips() {
+        // Wide enough to be run-length encoded, with runs and literals, and too narrow to be
+        for width in [40, 5] {
+            let image = Buffer::new_with(width, 3, |x, y| {
+                if x % 10 < 6 {
+                    Color::new(0.25, 1.5, 1000.0)
+                } else {
+                    Color::new(x as f32 * 0.1, y as f32, 0.01)
+                }
+            });
+            let decoded = Buffer::read_hdr(&encode(&image)[..]).unwrap();
+            assert!(close(&image, &decoded));
+        }
+        let black = Buffer::new(10, 2, Color::black());
+        assert_eq!(Buffer::read_hdr(&encode(&black)[..]).unwrap(), black);
+    }
+
+    #[test]
+    fn reads_headers() {
+        let mut bytes = b"#?RGBE\nEXPOSURE=2\n\n+Y 2 +X 1\n".to_vec();
+        bytes.extend([128, 128, 128, 129, 64, 64, 64, 129]);
+        let image = decode(&bytes).unwrap();
+        // Bottom row first, with the exposure divided out
+        assert_eq!(image.get(0, 0), Some(Color::splat(0.251_953_12)));
+        assert_eq!(image.get(0, 1), Some(Color::splat(0.501_953_1)));
+    }
+
+    #[test]
+    fn reads_old_runs() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 1 +X 6\n".to_vec();
+        bytes.extend([128, 128, 128, 129, 1, 1, 1, 4, 0, 0, 0, 0]);
+        let image = decode(&bytes).unwrap();
+        assert!(image
+            .iter()
+            .take(5)
+            .all(|&c| c == Color::splat(1.003_906_2)));
+        assert_eq!(image.get(5, 0), Some(Color::black()));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let invalid = |bytes: &[u8]| matches!(decode(bytes), Err(ImageError::Invalid(_)));
+        let image = encode(&Buffer::new(16, 2, Color::splat(0.5)));
+        assert!(invalid(&image[..image.len() - 1]));
+        assert!(invalid(b"#?RADIANCE\n\n-Y 1 +X 1\n"));
+        assert!(invalid(b"P6\n"));
+
+        let header = b"#?RADIANCE\n\n-Y 1 +X 4\n";
+        // Runs of nothing, which would otherwise shift the count out of a usize
+        let mut zero_runs = header.to_vec();
+        zero_runs.extend([1, 2, 3, 4]);
+        zero_runs.extend([1, 1, 1, 0].repeat(9));
+        assert!(invalid(&zero_runs));
+        let mut long_run = header.to_vec();
+        long_run.extend([1, 2, 3, 4, 1, 1, 1, 4]);
+        assert!(invalid(&long_run));
+
+        // A new style run of 9 in a scanline of 8
+        let mut long_rle = b"#?RADIANCE\n\n-Y 1 +X 8\n".to_vec();
+        long_rle.extend([2, 2, 0, 8, 137, 0]);
+        assert!(invalid(&long_rle));
+
+        // No pixels, which mustn't make room for a scanline as wide as claimed
+        let empty = decode(b"#?RADIANCE\n\n-Y 0 +X 18446744073709551615\n").unwrap();
+        assert_eq!(empty.iter().count(), 0);
+        assert!(invalid(b"#?RADIANCE\n\n-Y 1 +X 1000000000000\n\x02\x02"));
+    }
+}
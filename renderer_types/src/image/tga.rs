@@ -0,0 +1,210 @@
+//! Truevision TGA, uncompressed when writing, also run-length encoded when reading
+
+use std::io::{self, Read, Write};
+
+use super::{pixel_count, ByteReader, Channels, ImageError};
+use crate::prelude::*;
+
+const TRUE_COLOR: u8 = 2;
+const GREY: u8 = 3;
+/// Added to the image type for run-length encoding
+const RLE: u8 = 8;
+/// Image descriptor bit for the top row coming first
+const TOP_TO_BOTTOM: u8 = 0x20;
+const RIGHT_TO_LEFT: u8 = 0x10;
+/// Marks the file as TGA 2.0, without the extension and developer areas
+const FOOTER: &[u8; 26] = b"\0\0\0\0\0\0\0\0TRUEVISION-XFILE.\0";
+
+impl Buffer {
+    /// Writes an uncompressed TGA, 24-bit for [`Channels::Rgb`] and 32-bit for
+    /// [`Channels::Rgba`]
+    pub fn write_tga(&self, mut out: impl Write, channels: Channels) -> io::Result<()> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a TGA");
+        let width = u16::try_from(self.width()).map_err(|_| too_large())?;
+        let height = u16::try_from(self.height()).map_err(|_| too_large())?;
+        let (bits, alpha_bits) = match channels {
+            Channels::Rgb => (24, 0),
+            Channels::Rgba => (32, 8),
+        };
+        let mut header = [0; 18];
+        header[2] = TRUE_COLOR;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = bits;
+        header[17] = alpha_bits | TOP_TO_BOTTOM;
+        out.write_all(&header)?;
+        for pixels in self.inner_buf().chunks(self.width().max(1)) {
+            let row: Vec<u8> = match channels {
+                Channels::Rgb => pixels.iter().flat_map(|p| [p.b, p.g, p.r]).collect(),
+                Channels::Rgba => pixels.iter().flat_map(|p| [p.b, p.g, p.r, p.a]).collect(),
+            };
+            out.write_all(&row)?;
+        }
+        out.write_all(FOOTER)
+    }
+    /// Reads a true color or grey TGA, uncompressed or run-length encoded
+    pub fn read_tga(mut input: impl Read) -> Result<Self, ImageError> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        decode(&bytes)
+    }
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Buffer, ImageError> {
+    let mut reader = ByteReader::new(bytes);
+    let id_length = reader.u8()?;
+    let color_map_type = reader.u8()?;
+    let image_type = reader.u8()?;
+    // The index of the first palette entry doesn't matter for skipping it
+    reader.u16_le()?;
+    let color_map_length = reader.u16_le()?;
+    let color_map_bits = reader.u8()?;
+    reader.take(4)?;
+    let width = reader.u16_le()? as usize;
+    let height = reader.u16_le()? as usize;
+    let bits = reader.u8()?;
+    let descriptor = reader.u8()?;
+    reader.take(id_length as usize)?;
+    // True color images can still carry a palette, which they don't use
+    if color_map_type == 1 {
+        reader.take((color_map_length as usize * color_map_bits as usize).div_ceil(8))?;
+    }
+
+    let (grey, rle) = match image_type {
+        TRUE_COLOR => (false, false),
+        GREY => (true, false),
+        t if t == TRUE_COLOR + RLE => (false, true),
+        t if t == GREY + RLE => (true, true),
+        _ => {
+            return Err(ImageError::unsupported(format!(
+                "TGA image type {image_type}"
+            )))
+        }
+    };
+    let has_alpha = descriptor & 0x0f > 0;
+    let pixel: fn(&[u8], bool) -> Rgba = match (grey, bits) {
+        (false, 24) => |p, _| Rgba::new(p[2], p[1], p[0], u8::MAX),
+        (false, 32) => |p, alpha| Rgba::new(p[2], p[1], p[0], if alpha { p[3] } else { u8::MAX }),
+        (true, 8) => |p, _| Rgba::new(p[0], p[0], p[0], u8::MAX),
+        (true, 16) => |p, alpha| Rgba::new(p[0], p[0], p[0], if alpha { p[1] } else { u8::MAX }),
+        _ => return Err(ImageError::unsupported(format!("{bits}-bit TGA"))),
+    };
+    let bytes_per_pixel = bits as usize / 8;
+    let count = pixel_count(width, height)?;
+
+    // Run-length encoded files can claim more pixels than they hold
+    let mut pixels = Vec::with_capacity(count.min(bytes.len()));
+    if rle {
+        while pixels.len() < count {
+            let packet = reader.u8()?;
+            let length = (packet & 0x7f) as usize + 1;
+            if packet & 0x80 != 0 {
+                let repeated = pixel(reader.take(bytes_per_pixel)?, has_alpha);
+                pixels.extend(std::iter::repeat_n(repeated, length));
+            } else {
+                let raw = reader.take(length * bytes_per_pixel)?;
+                pixels.extend(
+                    raw.chunks_exact(bytes_per_pixel)
+                        .map(|p| pixel(p, has_alpha)),
+                );
+            }
+        }
+        // Packets may run past the last pixel
+        pixels.truncate(count);
+    } else {
+        let data = reader.take(count * bytes_per_pixel)?;
+        pixels.extend(
+            data.chunks_exact(bytes_per_pixel)
+                .map(|p| pixel(p, has_alpha)),
+        );
+    }
+
+    let mut image = Buffer::from_pixels(width, height, pixels);
+    if descriptor & TOP_TO_BOTTOM == 0 {
+        let rows: Vec<_> = image
+            .inner_buf()
+            .chunks(width.max(1))
+            .rev()
+            .flatten()
+            .copied()
+            .collect();
+        image = Buffer::from_pixels(width, height, rows);
+    }
+    if descriptor & RIGHT_TO_LEFT != 0 {
+        image
+            .inner_buf_mut()
+            .chunks_mut(width.max(1))
+            .for_each(|row| row.reverse());
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::tests::{channels, opaque, test_image};
+
+    /// Header of a `width`x`height` TGA without an ID or palette
+    fn header(image_type: u8, width: u16, height: u16, bits: u8, descriptor: u8) -> Vec<u8> {
+        let mut bytes = vec![0, 0, image_type, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend([bits, descriptor]);
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let image = test_image(5, 3);
+        let mut bytes = Vec::new();
+        image.write_tga(&mut bytes, Channels::Rgb).unwrap();
+        assert_eq!(bytes.len(), 18 + 5 * 3 * 3 + FOOTER.len());
+        assert!(bytes.ends_with(FOOTER));
+        assert_eq!(channels(&decode(&bytes).unwrap()), opaque(&image));
+
+        bytes.clear();
+        image.write_tga(&mut bytes, Channels::Rgba).unwrap();
+        assert_eq!(channels(&decode(&bytes).unwrap()), channels(&image));
+    }
+
+    #[test]
+    fn reads_run_length_encoding() {
+        // Bottom row first, a run of 3 spilling over into the next row and then 3 literal pixels
+        let mut bytes = header(GREY + RLE, 2, 3, 16, 8);
+        bytes.extend([0x82, 10, 255, 0x02, 20, 128, 30, 0, 40, 64]);
+        let rows = [
+            [30, 0],
+            [40, 64],
+            [10, 255],
+            [20, 128],
+            [10, 255],
+            [10, 255],
+        ];
+        let expected: Vec<_> = rows.iter().map(|&[v, a]| [v, v, v, a]).collect();
+        assert_eq!(channels(&decode(&bytes).unwrap()), expected);
+
+        // Right to left, where a run past the last pixel is cut short
+        let mut bytes = header(TRUE_COLOR + RLE, 2, 1, 24, TOP_TO_BOTTOM | RIGHT_TO_LEFT);
+        bytes.extend([0x00, 1, 2, 3, 0x81, 4, 5, 6]);
+        let expected = [[6, 5, 4, 255], [3, 2, 1, 255]];
+        assert_eq!(channels(&decode(&bytes).unwrap()), expected);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let mut truncated = header(TRUE_COLOR, 2, 2, 24, 0);
+        truncated.extend([0; 11]);
+        assert!(matches!(decode(&truncated), Err(ImageError::Invalid(_))));
+        let mut short_run = header(TRUE_COLOR + RLE, 4, 1, 24, 0);
+        short_run.extend([0x81, 1, 2, 3]);
+        assert!(matches!(decode(&short_run), Err(ImageError::Invalid(_))));
+        assert!(matches!(
+            decode(&header(1, 1, 1, 8, 0)),
+            Err(ImageError::Unsupported(_))
+        ));
+        assert!(matches!(
+            decode(&header(TRUE_COLOR, 1, 1, 16, 0)),
+            Err(ImageError::Unsupported(_))
+        ));
+    }
+}
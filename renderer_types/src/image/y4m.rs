@@ -0,0 +1,213 @@
+//! YUV4MPEG2, uncompressed Y'CbCr video that ffmpeg and most encoders read from a file or a pipe
+
+use std::io::{self, Write};
+
+use crate::prelude::*;
+
+/// How many pixels share the Cb and Cr samples
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChromaSubsampling {
+    /// Every pixel has its own
+    C444,
+    /// Pairs of pixels side by side share them
+    C422,
+    /// Squares of two by two pixels share them, which is what most video is
+    #[default]
+    C420,
+}
+
+impl ChromaSubsampling {
+    /// Pixels across and down sharing a sample
+    fn block(self) -> (usize, usize) {
+        match self {
+            Self::C444 => (1, 1),
+            Self::C422 => (2, 1),
+            Self::C420 => (2, 2),
+        }
+    }
+    /// Value of the `C` parameter of the header
+    fn tag(self) -> &'static str {
+        match self {
+            Self::C444 => "444",
+            Self::C422 => "422",
+            // Chroma sited between the pixels it covers, as averaging them gives
+            Self::C420 => "420jpeg",
+        }
+    }
+}
+
+/// Weights of red and blue in luma. The file doesn't say which one it uses, so the reader has to
+/// be told if it isn't [`YuvMatrix::Bt601`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum YuvMatrix {
+    /// Standard definition video, and what readers assume
+    #[default]
+    Bt601,
+    /// High definition video
+    Bt709,
+}
+
+impl YuvMatrix {
+    /// Weights of red and blue
+    fn weights(self) -> (f32, f32) {
+        match self {
+            Self::Bt601 => (0.299, 0.114),
+            Self::Bt709 => (0.2126, 0.0722),
+        }
+    }
+}
+
+/// How a YUV4MPEG2 stream is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Y4mOptions {
+    /// Frames per second as a fraction, like 30000 / 1001 for NTSC
+    pub frame_rate: (u32, u32),
+    pub chroma: ChromaSubsampling,
+    pub matrix: YuvMatrix,
+}
+
+impl Default for Y4mOptions {
+    fn default() -> Self {
+        Self {
+            frame_rate: (24, 1),
+            chroma: ChromaSubsampling::default(),
+            matrix: YuvMatrix::default(),
+        }
+    }
+}
+
+/// Writes frames of sRGB pixels as limited range Y'CbCr as they come, dropping alpha
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    options: Y4mOptions,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the header of a `width`x`height` stream
+    pub fn new(mut out: W, width: usize, height: usize, options: Y4mOptions) -> io::Result<Self> {
+        let (rate, scale) = options.frame_rate;
+        if width == 0 || height == 0 || rate == 0 || scale == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a YUV4MPEG2 stream needs a size and a frame rate",
+            ));
+        }
+        writeln!(
+            out,
+            "YUV4MPEG2 W{width} H{height} F{rate}:{scale} Ip A1:1 C{} XCOLORRANGE=LIMITED",
+            options.chroma.tag()
+        )?;
+        Ok(Self {
+            out,
+            width,
+            height,
+            options,
+        })
+    }
+    pub fn write_frame(&mut self, frame: &Buffer) -> io::Result<()> {
+        if frame.dimensions() != Vec2::new(self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frames must be as large as the stream",
+            ));
+        }
+        let (red, blue) = self.options.matrix.weights();
+        let green = 1.0 - red - blue;
+        // Luma and chroma in [0, 1] and [-0.5, 0.5], from the gamma encoded channels
+        let ycbcr: Vec<[f32; 3]> = frame
+            .iter()
+            .map(|p| {
+                let [r, g, b] = [p.r, p.g, p.b].map(|c| c as f32 / 255.0);
+                let y = red * r + green * g + blue * b;
+                [y, (b - y) / (2.0 - 2.0 * blue), (r - y) / (2.0 - 2.0 * red)]
+            })
+            .collect();
+
+        let (block_x, block_y) = self.options.chroma.block();
+        let (chroma_width, chroma_height) =
+            (self.width.div_ceil(block_x), self.height.div_ceil(block_y));
+        let mut data =
+            Vec::with_capacity(self.width * self.height + 2 * chroma_width * chroma_height);
+        data.extend(ycbcr.iter().map(|[y, ..]| (16.0 + 219.0 * y).round() as u8));
+        for channel in [1, 2] {
+            for cy in 0..chroma_height {
+                for cx in 0..chroma_width {
+                    // Blocks at the right and bottom edges may be cut short
+                    let rows = cy * block_y..((cy + 1) * block_y).min(self.height);
+                    let columns = cx * block_x..((cx + 1) * block_x).min(self.width);
+                    let count = rows.len() * columns.len();
+                    let sum: f32 = rows
+                        .flat_map(|y| columns.clone().map(move |x| (x, y)))
+                        .map(|(x, y)| ycbcr[y * self.width + x][channel])
+                        .sum();
+                    data.push((128.0 + 224.0 * sum / count as f32).round() as u8);
+                }
+            }
+        }
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&data)
+    }
+    /// Flushes the stream and returns what it was written to
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_planes_of_the_right_size() {
+        let (width, height) = (5, 3);
+        for (chroma, tag, chroma_size) in [
+            (ChromaSubsampling::C444, "444", 5 * 3),
+            (ChromaSubsampling::C422, "422", 3 * 3),
+            (ChromaSubsampling::C420, "420jpeg", 3 * 2),
+        ] {
+            let options = Y4mOptions {
+                frame_rate: (30000, 1001),
+                chroma,
+                ..Y4mOptions::default()
+            };
+            let mut writer = Y4mWriter::new(Vec::new(), width, height, options).unwrap();
+            let white = Buffer::new(width, height, Rgba::white());
+            writer.write_frame(&white).unwrap();
+            writer
+                .write_frame(&Buffer::new(width, height, Rgba::black()))
+                .unwrap();
+            let stream = writer.finish().unwrap();
+
+            let header =
+                format!("YUV4MPEG2 W5 H3 F30000:1001 Ip A1:1 C{tag} XCOLORRANGE=LIMITED\n");
+            let frame_size = width * height + 2 * chroma_size;
+            assert_eq!(
+                stream.len(),
+                header.len() + 2 * (b"FRAME\n".len() + frame_size)
+            );
+            let (start, frames) = stream.split_at(header.len());
+            assert_eq!(start, header.as_bytes());
+            for (frame, luma) in frames.chunks(6 + frame_size).zip([235, 16]) {
+                let (tag, planes) = frame.split_at(6);
+                assert_eq!(tag, b"FRAME\n");
+                let (y, cbcr) = planes.split_at(width * height);
+                assert!(y.iter().all(|&v| v == luma), "{y:?}");
+                // Greys have no chroma
+                assert!(cbcr.iter().all(|&v| v == 128), "{cbcr:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_cant_write() {
+        let options = Y4mOptions::default();
+        assert!(Y4mWriter::new(Vec::new(), 0, 3, options).is_err());
+        let mut writer = Y4mWriter::new(Vec::new(), 5, 3, options).unwrap();
+        assert!(writer
+            .write_frame(&Buffer::new(3, 5, Rgba::black()))
+            .is_err());
+    }
+}
@@ -1,5 +1,10 @@
 pub mod buf;
 pub mod color;
+pub mod color_space;
+pub mod image;
+pub mod lut;
+pub mod sampling;
+pub mod spectrum;
 pub mod vec;
 
 use self::vec::{CompleteVector, Vector};
@@ -44,7 +49,8 @@ pub mod prelude {
     }
 
     pub use super::buf::{Buffer, Rgba};
-    pub use super::color::Color;
+    pub use super::color::{Color, ColorAlpha, Encoding};
+    pub use super::color_space::{Hsl, Hsv, Oklab, Oklch};
     pub use super::vec::{CompleteVector, IntoVector, Vec2, Vec3, Vec4, Vector};
     pub use super::{CreateRay, Ray};
     pub type Vec2i = Vec2<i32>;
@@ -0,0 +1,431 @@
+//! 3D lookup tables for color grading, read from the `.cube` files Adobe and Resolve write
+
+use std::{error::Error, fmt};
+
+use winnow::{
+    ascii::{dec_uint, float, space0, space1},
+    combinator::{alt, cut_err, dispatch, eof, opt, preceded, separated_pair, terminated},
+    error::{ContextError, ErrMode},
+    prelude::*,
+    token::{one_of, rest, take_till, take_while},
+};
+
+use crate::prelude::*;
+
+/// How colors between the entries of a [`Lut3d`] are blended
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LutInterpolation {
+    /// Blends the 8 entries around the color
+    Trilinear,
+    /// Blends the 4 entries of the tetrahedron around the color, which keeps greys grey and is
+    /// what grading tools use
+    #[default]
+    Tetrahedral,
+}
+
+/// Maps colors by looking them up in a `size`³ lattice, with red changing fastest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lut3d {
+    title: Option<String>,
+    size: usize,
+    /// Input colors at the first and last entry along each axis
+    domain_min: Colorf32,
+    domain_max: Colorf32,
+    table: Vec<Colorf32>,
+}
+
+impl Lut3d {
+    /// Table mapping every color to itself
+    pub fn identity(size: usize) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let table = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                Color::new(r as f32 * step, g as f32 * step, b as f32 * step)
+            })
+            .collect();
+        Self {
+            title: None,
+            size,
+            domain_min: Color::black(),
+            domain_max: Color::white(),
+            table,
+        }
+    }
+    /// Reads a `.cube` file, the format of Adobe's Cube LUT specification
+    pub fn parse_cube(text: &str) -> Result<Self, CubeError> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = Color::black();
+        let mut domain_max = Color::white();
+        let mut table = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |kind| CubeError {
+                line: index + 1,
+                kind,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parsed = cube_line
+                .parse(line)
+                .map_err(|_| error(CubeErrorKind::Syntax(line.to_string())))?;
+            match parsed {
+                CubeLine::Title(name) => title = Some(name.to_string()),
+                CubeLine::Size3d(n) => {
+                    if !(2..=256).contains(&n) {
+                        return Err(error(CubeErrorKind::InvalidSize(n)));
+                    }
+                    size = Some(n);
+                    table.reserve(n * n * n);
+                }
+                CubeLine::Size1d => return Err(error(CubeErrorKind::Unsupported1d)),
+                CubeLine::DomainMin([r, g, b]) => domain_min = Color::new(r, g, b),
+                CubeLine::DomainMax([r, g, b]) => domain_max = Color::new(r, g, b),
+                CubeLine::InputRange(min, max) => {
+                    domain_min = Color::splat(min);
+                    domain_max = Color::splat(max);
+                }
+                CubeLine::Entry([r, g, b]) => {
+                    let Some(n) = size else {
+                        return Err(error(CubeErrorKind::MissingSize));
+                    };
+                    if table.len() == n * n * n {
+                        return Err(error(CubeErrorKind::EntryCount {
+                            expected: n * n * n,
+                            found: table.len() + 1,
+                        }));
+                    }
+                    table.push(Color::new(r, g, b));
+                }
+                CubeLine::Other => {}
+            }
+        }
+
+        let last = text.lines().count();
+        let size = size.ok_or(CubeError {
+            line: last,
+            kind: CubeErrorKind::MissingSize,
+        })?;
+        if table.len() != size * size * size {
+            return Err(CubeError {
+                line: last,
+                kind: CubeErrorKind::EntryCount {
+                    expected: size * size * size,
+                    found: table.len(),
+                },
+            });
+        }
+        Ok(Self {
+            title,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+    /// Number of entries along each axis
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    fn entry(&self, r: usize, g: usize, b: usize) -> Colorf32 {
+        self.table[(b * self.size + g) * self.size + r]
+    }
+    /// Looks `color` up, clamping it into the domain of the table first
+    pub fn apply(&self, color: Colorf32, interpolation: LutInterpolation) -> Colorf32 {
+        let last = (self.size - 1) as f32;
+        let coordinate = |v: f32, min: f32, max: f32| {
+            let t = ((v - min) / (max - min)).clamp(0.0, 1.0);
+            // NaN falls back to the first entry
+            let x = if t.is_nan() { 0.0 } else { t * last };
+            let i = (x as usize).min(self.size - 2);
+            (i, x - i as f32)
+        };
+        let (r, fr) = coordinate(color.r, self.domain_min.r, self.domain_max.r);
+        let (g, fg) = coordinate(color.g, self.domain_min.g, self.domain_max.g);
+        let (b, fb) = coordinate(color.b, self.domain_min.b, self.domain_max.b);
+        let c = |dr, dg, db| self.entry(r + dr, g + dg, b + db);
+
+        match interpolation {
+            LutInterpolation::Trilinear => {
+                let lerp = |a: Colorf32, b: Colorf32, t: f32| a + (b - a) * t;
+                let c00 = lerp(c(0, 0, 0), c(1, 0, 0), fr);
+                let c10 = lerp(c(0, 1, 0), c(1, 1, 0), fr);
+                let c01 = lerp(c(0, 0, 1), c(1, 0, 1), fr);
+                let c11 = lerp(c(0, 1, 1), c(1, 1, 1), fr);
+                lerp(lerp(c00, c10, fg), lerp(c01, c11, fg), fb)
+            }
+            LutInterpolation::Tetrahedral => {
+                // Walk from the first corner to the opposite one along the axes in order of how
+                // far the color is along them
+                let (c000, c111) = (c(0, 0, 0), c(1, 1, 1));
+                let (first, second, (x, y, z)) = if fr > fg {
+                    if fg > fb {
+                        (c(1, 0, 0), c(1, 1, 0), (fr, fg, fb))
+                    } else if fr > fb {
+                        (c(1, 0, 0), c(1, 0, 1), (fr, fb, fg))
+                    } else {
+                        (c(0, 0, 1), c(1, 0, 1), (fb, fr, fg))
+                    }
+                } else if fb > fg {
+                    (c(0, 0, 1), c(0, 1, 1), (fb, fg, fr))
+                } else if fb > fr {
+                    (c(0, 1, 0), c(0, 1, 1), (fg, fb, fr))
+                } else {
+                    (c(0, 1, 0), c(1, 1, 0), (fg, fr, fb))
+                };
+                c000 * (1.0 - x) + first * (x - y) + second * (y - z) + c111 * z
+            }
+        }
+    }
+}
+
+enum CubeLine<'a> {
+    Title(&'a str),
+    Size3d(usize),
+    Size1d,
+    DomainMin([f32; 3]),
+    DomainMax([f32; 3]),
+    InputRange(f32, f32),
+    Entry([f32; 3]),
+    /// Keywords that don't affect 3D tables, which the format says to ignore
+    Other,
+}
+
+fn triple(input: &mut &str) -> ModalResult<[f32; 3]> {
+    let (r, _, g, _, b) = (float, space1, float, space1, float).parse_next(input)?;
+    Ok([r, g, b])
+}
+
+/// The title, which may hold `#` when it's quoted
+fn title<'a>(input: &mut &'a str) -> ModalResult<&'a str> {
+    alt((
+        preceded('"', cut_err(terminated(take_till(0.., '"'), '"'))),
+        take_till(1.., '#').map(str::trim_end),
+    ))
+    .parse_next(input)
+}
+
+/// Arguments after a keyword, which fail the whole line when they don't parse
+fn arguments<'a, O>(
+    parser: impl Parser<&'a str, O, ErrMode<ContextError>>,
+) -> impl Parser<&'a str, O, ErrMode<ContextError>> {
+    cut_err(preceded(space1, parser))
+}
+
+/// Trailing spaces and comment
+fn line_end(input: &mut &str) -> ModalResult<()> {
+    (space0, opt(('#', rest)), eof).void().parse_next(input)
+}
+
+fn cube_line<'a>(input: &mut &'a str) -> ModalResult<CubeLine<'a>> {
+    let mut keyword = (
+        one_of(|c: char| c.is_ascii_uppercase() || c == '_'),
+        take_while(0.., |c: char| {
+            c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_'
+        }),
+    )
+        .take();
+    // Known keywords have to be followed by valid arguments, unknown ones are skipped whole
+    let keyword_line = dispatch! {keyword;
+        "TITLE" => arguments(title).map(CubeLine::Title),
+        "LUT_3D_SIZE" => arguments(dec_uint).map(CubeLine::Size3d),
+        "LUT_1D_SIZE" => arguments(dec_uint::<_, usize, _>).map(|_| CubeLine::Size1d),
+        "DOMAIN_MIN" => arguments(triple).map(CubeLine::DomainMin),
+        "DOMAIN_MAX" => arguments(triple).map(CubeLine::DomainMax),
+        "LUT_3D_INPUT_RANGE" => arguments(separated_pair(float, space1, float))
+            .map(|(min, max)| CubeLine::InputRange(min, max)),
+        _ => rest.map(|_| CubeLine::Other),
+    };
+    terminated(
+        alt((keyword_line, triple.map(CubeLine::Entry))),
+        cut_err(line_end),
+    )
+    .parse_next(input)
+}
+
+/// Why a `.cube` file couldn't be read, and on which line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CubeError {
+    /// 1-based, the last line for problems with the file as a whole
+    pub line: usize,
+    pub kind: CubeErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CubeErrorKind {
+    Syntax(String),
+    /// `LUT_3D_SIZE` outside of the 2 to 256 the format allows
+    InvalidSize(usize),
+    /// Table entries before `LUT_3D_SIZE`, or no size at all
+    MissingSize,
+    EntryCount {
+        expected: usize,
+        found: usize,
+    },
+    /// The file holds a 1D table
+    Unsupported1d,
+}
+
+impl fmt::Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            CubeErrorKind::Syntax(line) => write!(f, "can't parse {line:?}"),
+            CubeErrorKind::InvalidSize(size) => {
+                write!(f, "LUT_3D_SIZE {size} is outside of 2 to 256")
+            }
+            CubeErrorKind::MissingSize => f.write_str("missing LUT_3D_SIZE"),
+            CubeErrorKind::EntryCount { expected, found } => {
+                write!(f, "expected {expected} table entries, found {found}")
+            }
+            CubeErrorKind::Unsupported1d => f.write_str("1D tables aren't supported"),
+        }
+    }
+}
+
+impl Error for CubeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = "\
+# Swaps red and blue
+TITLE \"Swap # red and blue\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1 # the default
+LUT_SOMETHING_ELSE whatever 1 2
+
+0 0 0
+0 0 1
+0 1 0
+0 1 1
+1 0 0
+1 0 1
+1 1 0
+1 1 1
+";
+
+    fn close(a: Colorf32, b: Colorf32) -> bool {
+        (a.r - b.r).abs() < 1e-5 && (a.g - b.g).abs() < 1e-5 && (a.b - b.b).abs() < 1e-5
+    }
+
+    #[test]
+    fn parses_a_cube() {
+        let lut = Lut3d::parse_cube(CUBE).unwrap();
+        assert_eq!(lut.title(), Some("Swap # red and blue"));
+        assert_eq!(lut.size(), 2);
+        for interpolation in [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral] {
+            let swapped = lut.apply(Color::new(0.2, 0.5, 0.9), interpolation);
+            assert!(close(swapped, Color::new(0.9, 0.5, 0.2)), "{swapped:?}");
+        }
+    }
+
+    #[test]
+    fn identity_changes_nothing() {
+        let lut = Lut3d::identity(17);
+        for interpolation in [LutInterpolation::Trilinear, LutInterpolation::Tetrahedral] {
+            for i in 0..1000 {
+                let v = |n: usize| (i * n % 997) as f32 / 996.0;
+                let color = Color::new(v(1), v(31), v(557));
+                let mapped = lut.apply(color, interpolation);
+                assert!(close(mapped, color), "{color:?} became {mapped:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn interpolations_agree_on_the_lattice() {
+        // Every entry differs so a mixed up corner would show
+        let size = 5;
+        let mut lut = Lut3d::identity(size);
+        for (i, entry) in lut.table.iter_mut().enumerate() {
+            *entry = Color::new((i * 7 % 11) as f32, (i * 3 % 13) as f32, (i % 17) as f32);
+        }
+        let last = (size - 1) as f32;
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let color = Color::new(r as f32 / last, g as f32 / last, b as f32 / last);
+                    let trilinear = lut.apply(color, LutInterpolation::Trilinear);
+                    let tetrahedral = lut.apply(color, LutInterpolation::Tetrahedral);
+                    assert!(close(trilinear, lut.entry(r, g, b)), "{trilinear:?}");
+                    assert!(close(tetrahedral, lut.entry(r, g, b)), "{tetrahedral:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let error = |text: &str| Lut3d::parse_cube(text).unwrap_err();
+        let syntax = |line: usize, text: &str| CubeError {
+            line,
+            kind: CubeErrorKind::Syntax(text.to_string()),
+        };
+        let entries = "0 0 0\n".repeat(8);
+        for header in [
+            "TITLE \"unterminated",
+            "TITLE",
+            "LUT_3D_SIZE abc",
+            "LUT_3D_SIZE 2 3",
+            "LUT_1D_SIZE x",
+            "DOMAIN_MIN 0 0",
+            "DOMAIN_MAX 1 1 one",
+            "LUT_3D_INPUT_RANGE x y",
+            "0 0",
+        ] {
+            let text = format!("LUT_3D_SIZE 2\n{header}\n{entries}");
+            assert_eq!(error(&text), syntax(2, header));
+        }
+        assert_eq!(
+            error("LUT_3D_SIZE 1\n"),
+            CubeError {
+                line: 1,
+                kind: CubeErrorKind::InvalidSize(1)
+            }
+        );
+        assert_eq!(
+            error("LUT_1D_SIZE 2\n0 0 0\n0 0 0\n"),
+            CubeError {
+                line: 1,
+                kind: CubeErrorKind::Unsupported1d
+            }
+        );
+        assert_eq!(
+            error(&format!("TITLE \"a\"\n{entries}")),
+            CubeError {
+                line: 2,
+                kind: CubeErrorKind::MissingSize
+            }
+        );
+        assert_eq!(
+            error(&format!("LUT_3D_SIZE 2\n{entries}0 0 0\n")),
+            CubeError {
+                line: 10,
+                kind: CubeErrorKind::EntryCount {
+                    expected: 8,
+                    found: 9
+                }
+            }
+        );
+        assert_eq!(
+            error("LUT_3D_SIZE 2\n0 0 0\n"),
+            CubeError {
+                line: 2,
+                kind: CubeErrorKind::EntryCount {
+                    expected: 8,
+                    found: 1
+                }
+            }
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use std::f32::consts::{FRAC_1_PI, PI, TAU};
+
+use crate::prelude::*;
+
+/// Small, fast, non-cryptographic PCG32 random number generator
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    inc: u64,
+}
+
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// Creates a generator, different `stream`s give independent sequences for the same seed
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (splitmix64(stream) << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(splitmix64(seed));
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+    /// Uniformly distributed in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        // 24 bits is all the precision a f32 in [0, 1) can hold
+        (self.next_u32() >> 8) as f32 * (1.0 / (1u32 << 24) as f32)
+    }
+    pub fn next_vec2f(&mut self) -> Vec2f {
+        vec2f(self.next_f32(), self.next_f32())
+    }
+    pub fn next_vec3f(&mut self) -> Vec3f {
+        vec3f(self.next_f32(), self.next_f32(), self.next_f32())
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Orthonormal basis, for moving directions in and out of a local frame where `w` is +z
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3f,
+    pub v: Vec3f,
+    pub w: Vec3f,
+}
+
+impl Onb {
+    /// Builds a basis around the unit vector `w`.
+    ///
+    /// Duff et al., "Building an Orthonormal Basis, Revisited"
+    pub fn from_w(w: Vec3f) -> Self {
+        let sign = 1f32.copysign(w.z);
+        let a = -1.0 / (sign + w.z);
+        let b = w.x * w.y * a;
+        Self {
+            u: vec3f(1.0 + sign * w.x * w.x * a, sign * b, -sign * w.x),
+            v: vec3f(b, sign + w.y * w.y * a, -w.y),
+            w,
+        }
+    }
+    pub fn to_local(&self, v: Vec3f) -> Vec3f {
+        vec3f(v.dot(self.u), v.dot(self.v), v.dot(self.w))
+    }
+    pub fn to_world(&self, v: Vec3f) -> Vec3f {
+        self.u * v.x + self.v * v.y + self.w * v.z
+    }
+}
+
+/// Maps a pair of uniform samples in [0, 1) to a direction in the +z hemisphere, with a density
+/// proportional to the cosine of the angle to +z
+pub fn sample_cosine_hemisphere(u: Vec2f) -> Vec3f {
+    let r = u.x.sqrt();
+    let phi = TAU * u.y;
+    vec3f(r * phi.cos(), r * phi.sin(), (1.0 - u.x).max(0.0).sqrt())
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f32) -> f32 {
+    cos_theta.max(0.0) * FRAC_1_PI
+}
+
+/// Maps a pair of uniform samples in [0, 1) to a uniformly distributed direction
+pub fn sample_uniform_sphere(u: Vec2f) -> Vec3f {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = TAU * u.y;
+    vec3f(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub const fn uniform_sphere_pdf() -> f32 {
+    1.0 / (4.0 * PI)
+}
+
+/// Maps a pair of uniform samples in [0, 1) to a uniformly distributed point on the unit disk in
+/// the xy plane
+pub fn sample_uniform_disk(u: Vec2f) -> Vec2f {
+    let r = u.x.sqrt();
+    let phi = TAU * u.y;
+    vec2f(r * phi.cos(), r * phi.sin())
+}
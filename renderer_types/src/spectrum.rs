@@ -0,0 +1,244 @@
+//! Light as a function of wavelength, for effects RGB can't express, like dispersion.
+//!
+//! Paths carry a [`SampledSpectrum`], the spectrum at the handful of wavelengths picked by
+//! [`SampledWavelengths`], which turns back into RGB once the path is done. Everything else keeps
+//! working in RGB, which is converted to spectra on the fly
+
+use std::{
+    array,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign},
+    sync::LazyLock,
+};
+
+use crate::{color::invert, prelude::*};
+
+/// Shortest wavelength considered, in nanometers
+pub const LAMBDA_MIN: f32 = 360.0;
+/// Longest wavelength considered, in nanometers
+pub const LAMBDA_MAX: f32 = 830.0;
+/// Number of wavelengths every path carries
+pub const SPECTRUM_SAMPLES: usize = 4;
+
+/// The wavelengths a path carries, along with the probability densities they were chosen with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    lambda: [f32; SPECTRUM_SAMPLES],
+    pdf: [f32; SPECTRUM_SAMPLES],
+}
+
+impl SampledWavelengths {
+    /// Hero wavelength sampling (Wilkie et al., "Hero Wavelength Spectral Sampling", 2014): `u`, a
+    /// uniform sample in [0, 1), picks the first wavelength and the others are evenly spread out
+    /// from it.
+    ///
+    /// Wavelengths the eye is more sensitive to are picked more often
+    pub fn sample_visible(u: f32) -> Self {
+        let lambda = array::from_fn(|i| {
+            sample_visible_wavelength((u + i as f32 / SPECTRUM_SAMPLES as f32).fract())
+        });
+        Self {
+            lambda,
+            pdf: lambda.map(visible_wavelength_pdf),
+        }
+    }
+    /// The wavelengths in nanometers, starting with the hero wavelength
+    pub fn lambda(&self) -> [f32; SPECTRUM_SAMPLES] {
+        self.lambda
+    }
+    pub fn pdf(&self) -> [f32; SPECTRUM_SAMPLES] {
+        self.pdf
+    }
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+    /// Drops every wavelength but the hero one, for when light of different wavelengths goes
+    /// separate ways, like in a prism
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        self.pdf[1..].fill(0.0);
+        self.pdf[0] /= SPECTRUM_SAMPLES as f32;
+    }
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+/// Samples wavelengths in [`LAMBDA_MIN`, `LAMBDA_MAX`] roughly proportional to how sensitive the
+/// eye is to them, using the fit from Pharr et al.'s "Physically Based Rendering", 4th edition
+fn sample_visible_wavelength(u: f32) -> f32 {
+    538.0 - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+fn visible_wavelength_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+    let cosh = (0.0072 * (lambda - 538.0)).cosh();
+    0.003_939_804 / (cosh * cosh)
+}
+
+/// Values of a spectrum at the wavelengths of a [`SampledWavelengths`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SampledSpectrum(pub [f32; SPECTRUM_SAMPLES]);
+
+impl SampledSpectrum {
+    pub const fn splat(value: f32) -> Self {
+        Self([value; SPECTRUM_SAMPLES])
+    }
+    pub fn is_black(&self) -> bool {
+        self.0.iter().all(|&v| v == 0.0)
+    }
+    pub fn max_value(&self) -> f32 {
+        self.0.into_iter().fold(f32::NEG_INFINITY, f32::max)
+    }
+    /// Spectrum of a surface reflecting the linear sRGB `color`. Grey stays flat, so nothing
+    /// reflects more than its largest component
+    pub fn from_reflectance(color: Colorf32, wavelengths: &SampledWavelengths) -> Self {
+        Self(wavelengths.lambda.map(|lambda| {
+            let [r, g, b] = rgb_basis(lambda);
+            color.r * r + color.g * g + color.b * b
+        }))
+    }
+    /// Spectrum of light with the linear sRGB `color`, white being a D65-like illuminant
+    pub fn from_illuminant(color: Colorf32, wavelengths: &SampledWavelengths) -> Self {
+        let reflectance = Self::from_reflectance(color, wavelengths);
+        Self(array::from_fn(|i| {
+            reflectance.0[i] * white_illuminant(wavelengths.lambda[i])
+        }))
+    }
+    /// Estimates the CIE 1931 XYZ tristimulus values of the whole spectrum from its samples.
+    ///
+    /// The values aren't normalized, see [`SampledSpectrum::to_rgb`]
+    pub fn to_xyz(&self, wavelengths: &SampledWavelengths) -> Vec3f {
+        let mut xyz = Vec3f::splat(0.0);
+        for ((&value, &lambda), &pdf) in
+            self.0.iter().zip(&wavelengths.lambda).zip(&wavelengths.pdf)
+        {
+            if pdf > 0.0 {
+                xyz += cie_xyz(lambda) * (value / pdf);
+            }
+        }
+        xyz / SPECTRUM_SAMPLES as f32
+    }
+    /// Estimates the linear sRGB color of the whole spectrum from its samples.
+    ///
+    /// The result is calibrated so light from [`SampledSpectrum::from_illuminant`] keeps its
+    /// color, which also makes its white illuminant white
+    pub fn to_rgb(&self, wavelengths: &SampledWavelengths) -> Colorf32 {
+        let xyz = self.to_xyz(wavelengths);
+        let rgb = Color::from_xyz(xyz.x, xyz.y, xyz.z);
+        let [r, g, b] = CALIBRATION.map(|row| row[0] * rgb.r + row[1] * rgb.g + row[2] * rgb.b);
+        Color::new(r, g, b)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+impl Mul<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Self(self.0.map(|v| v * rhs))
+    }
+}
+impl Div<f32> for SampledSpectrum {
+    type Output = Self;
+
+    fn div(self, rhs: f32) -> Self::Output {
+        Self(self.0.map(|v| v / rhs))
+    }
+}
+impl DivAssign<f32> for SampledSpectrum {
+    fn div_assign(&mut self, rhs: f32) {
+        *self = *self / rhs;
+    }
+}
+
+/// CIE 1931 2° standard observer color matching functions at `lambda` nanometers.
+///
+/// Multi-lobe fit from Wyman et al., "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions", 2013
+pub fn cie_xyz(lambda: f32) -> Vec3f {
+    let lobe = |mu: f32, sigma_below: f32, sigma_above: f32| {
+        let t = (lambda - mu)
+            / if lambda < mu {
+                sigma_below
+            } else {
+                sigma_above
+            };
+        (-0.5 * t * t).exp()
+    };
+    vec3f(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Spectral radiance of a black body at `kelvin`, at `lambda` nanometers, by Planck's law
+pub fn blackbody(lambda: f32, kelvin: f32) -> f32 {
+    const C: f64 = 299_792_458.0;
+    const H: f64 = 6.626_070_15e-34;
+    const KB: f64 = 1.380_649e-23;
+    if kelvin <= 0.0 {
+        return 0.0;
+    }
+    let l = lambda as f64 * 1e-9;
+    let radiance = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * KB * kelvin as f64)).exp_m1()));
+    radiance as f32
+}
+
+/// Weights of red, green and blue at `lambda` when turning RGB into a spectrum. They add up to
+/// one at every wavelength
+fn rgb_basis(lambda: f32) -> [f32; 3] {
+    let step = |edge: f32| 1.0 / (1.0 + (-(lambda - edge) / 12.0).exp());
+    let (above_blue, above_green) = (step(490.0), step(585.0));
+    [above_green, above_blue - above_green, 1.0 - above_blue]
+}
+
+/// Shape of white light, a black body at the temperature of D65, scaled to be around 1
+fn white_illuminant(lambda: f32) -> f32 {
+    blackbody(lambda, 6504.0) / blackbody(560.0, 6504.0)
+}
+
+/// Maps the colors the red, green and blue illuminants of [`SampledSpectrum::from_illuminant`]
+/// come out as back to pure red, green and blue
+static CALIBRATION: LazyLock<[[f32; 3]; 3]> = LazyLock::new(|| {
+    // The same integrals `SampledSpectrum::to_xyz` estimates, in 1 nm steps
+    let mut primaries = [Vec3f::splat(0.0); 3];
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        let xyz = cie_xyz(lambda) * white_illuminant(lambda);
+        for (primary, weight) in primaries.iter_mut().zip(rgb_basis(lambda)) {
+            *primary += xyz * weight;
+        }
+        lambda += 1.0;
+    }
+    let [r, g, b] = primaries.map(|xyz| Color::from_xyz(xyz.x, xyz.y, xyz.z));
+    invert([[r.r, g.r, b.r], [r.g, g.g, b.g], [r.b, g.b, b.b]])
+});